@@ -0,0 +1,347 @@
+use std::f32::consts::PI;
+
+// Buzzer synthesis for the CHIP-8 sound timer. Producing the samples here
+// rather than in each frontend means that what's played back, written to disk
+// or checked in tests is always the same signal.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    // A buzzier tone in the spirit of the COSMAC VIP's speaker.
+    VipBuzz,
+}
+
+// Sound state changes kept for the next render. Machines that are never
+// rendered, or not for a long while, forget the oldest ones past this.
+const MAX_TRANSITIONS: usize = 4096;
+
+// One cycle of a narrow pulse followed by some ringing, which gives a harsh
+// buzz closer to the original VIP hardware than a clean square wave does.
+const VIP_BUZZ_TABLE: [f32; 16] = [
+    1.0, 1.0, 0.9, 0.6, -0.2, -0.7, -0.9, -0.8,
+    -0.5, -0.6, -0.8, -0.7, -0.4, -0.5, -0.6, -0.4,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BuzzerSettings {
+    pub waveform: Waveform,
+    // Tone frequency, in Hz.
+    pub frequency: f32,
+    // Peak amplitude of the generated samples.
+    pub volume: i16,
+    // How long the tone takes to fade in and out, in milliseconds. Switching
+    // the wave on and off instantly gives an audible click.
+    pub ramp_ms: f32,
+}
+
+impl Default for BuzzerSettings {
+    fn default() -> BuzzerSettings {
+        BuzzerSettings {
+            waveform: Waveform::Square,
+            frequency: 125.0,
+            volume: 3000,
+            ramp_ms: 2.0,
+        }
+    }
+}
+
+// Produces one wave cycle at a time, shaped by the selected waveform. The
+// phase keeps running during silence so that the wave doesn't restart from the
+// same point on every beep.
+struct Oscillator {
+    phase: f32,
+}
+
+impl Oscillator {
+    // Returns the next sample in the range -1.0 to 1.0.
+    fn next_sample(&mut self, waveform: Waveform, phase_increment: f32) -> f32 {
+        let phase = self.phase;
+        self.phase = (self.phase + phase_increment) % 1.0;
+        match waveform {
+            Waveform::Square => if phase <= 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sine => (phase * 2.0 * PI).sin(),
+            Waveform::VipBuzz => {
+                let index = (phase * VIP_BUZZ_TABLE.len() as f32) as usize;
+                VIP_BUZZ_TABLE[index.min(VIP_BUZZ_TABLE.len() - 1)]
+            }
+        }
+    }
+}
+
+// Tracks when the sound timer switched on and off, in emulated cycles, so that
+// the tone can start and stop at the exact point within a frame's worth of
+// samples rather than on frame boundaries.
+pub struct Buzzer {
+    settings: BuzzerSettings,
+    oscillator: Oscillator,
+    // Current envelope level, between 0.0 (silent) and 1.0 (full volume).
+    level: f32,
+    is_playing: bool,
+    // The cycle up to which samples have been rendered, and the sound state
+    // changes that happened since then.
+    rendered_cycle: u64,
+    transitions: Vec<(u64, bool)>,
+}
+
+impl Buzzer {
+    pub fn new(settings: BuzzerSettings) -> Buzzer {
+        Buzzer {
+            settings,
+            oscillator: Oscillator { phase: 0.0 },
+            level: 0.0,
+            is_playing: false,
+            rendered_cycle: 0,
+            transitions: Vec::new(),
+        }
+    }
+
+    pub fn settings(&self) -> BuzzerSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: BuzzerSettings) {
+        self.settings = settings;
+    }
+
+    // Records the sound state as of the given cycle. Only changes are kept.
+    pub fn set_playing(&mut self, cycle: u64, is_playing: bool) {
+        let last_state = self.transitions.last().map_or(self.is_playing, |&(_, state)| state);
+        if last_state != is_playing {
+            self.transitions.push((cycle, is_playing));
+        }
+        if self.transitions.len() > MAX_TRANSITIONS {
+            // Treat the older half as rendered already, silently.
+            let (last_cycle, last_state) = self.transitions[MAX_TRANSITIONS / 2 - 1];
+            self.transitions.drain(..MAX_TRANSITIONS / 2);
+            self.rendered_cycle = last_cycle;
+            self.is_playing = last_state;
+        }
+    }
+
+    // Fills the buffer with the sound covering the cycles since the last call,
+    // up to current_cycle. Sound state changes are placed in proportion to
+    // where their cycle falls in that span.
+    pub fn render(&mut self, out: &mut [i16], sample_rate: u32, current_cycle: u64) {
+        let phase_increment = self.settings.frequency / sample_rate as f32;
+        let ramp_samples = self.settings.ramp_ms * sample_rate as f32 / 1000.0;
+        let level_step = if ramp_samples >= 1.0 { 1.0 / ramp_samples } else { 1.0 };
+        let volume = self.settings.volume as f32;
+
+        let start_cycle = self.rendered_cycle;
+        let cycle_span = current_cycle.saturating_sub(start_cycle);
+        let sample_count = out.len() as u64;
+        let mut next_transition = 0;
+
+        for (i, x) in out.iter_mut().enumerate() {
+            let sample_cycle = start_cycle + (i as u64 * cycle_span) / sample_count.max(1);
+            while next_transition < self.transitions.len()
+                && (cycle_span == 0 || self.transitions[next_transition].0 <= sample_cycle) {
+                self.is_playing = self.transitions[next_transition].1;
+                next_transition += 1;
+            }
+
+            let target = if self.is_playing { 1.0 } else { 0.0 };
+            if self.level < target {
+                self.level = (self.level + level_step).min(target);
+            } else if self.level > target {
+                self.level = (self.level - level_step).max(target);
+            }
+
+            let wave = self.oscillator.next_sample(self.settings.waveform, phase_increment);
+            *x = (wave * self.level * volume) as i16;
+        }
+
+        // Anything left over happened at the very end of the span.
+        for &(_, state) in &self.transitions[next_transition..] {
+            self.is_playing = state;
+        }
+        self.transitions.clear();
+        self.rendered_cycle = current_cycle;
+    }
+}
+
+// Works out how many samples make up each emulated frame. Sample rates don't
+// always divide evenly by the frame rate, so the remainder is carried over to
+// the following frames to keep the audio in sync with the video.
+pub struct FrameSampleCounter {
+    sample_rate: u32,
+    frames_per_second: u32,
+    remainder: u32,
+}
+
+impl FrameSampleCounter {
+    pub fn new(sample_rate: u32, frames_per_second: u32) -> FrameSampleCounter {
+        FrameSampleCounter {
+            sample_rate,
+            frames_per_second,
+            remainder: 0,
+        }
+    }
+
+    pub fn next_frame(&mut self) -> usize {
+        let total = self.sample_rate + self.remainder;
+        self.remainder = total % self.frames_per_second;
+        (total / self.frames_per_second) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::{Buzzer, BuzzerSettings, FrameSampleCounter, Waveform};
+    use wav::WavWriter;
+    use Chip8;
+
+    fn settings_without_ramp(waveform: Waveform) -> BuzzerSettings {
+        BuzzerSettings {
+            waveform,
+            frequency: 100.0,
+            volume: 1000,
+            ramp_ms: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_buzzer_is_silent_when_not_playing() {
+        let mut buzzer = Buzzer::new(BuzzerSettings::default());
+        let mut samples = [1i16; 256];
+        buzzer.render(&mut samples, 44100, 100);
+        assert!(samples.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn test_square_wave_alternates_at_frequency() {
+        // 100Hz at 800Hz sample rate gives 8 samples per wave: 5 high, 3 low
+        // since the high half includes the midpoint.
+        let mut buzzer = Buzzer::new(settings_without_ramp(Waveform::Square));
+        buzzer.set_playing(0, true);
+        let mut samples = [0i16; 8];
+        buzzer.render(&mut samples, 800, 0);
+        assert_eq!([1000, 1000, 1000, 1000, 1000, -1000, -1000, -1000], samples);
+    }
+
+    #[test]
+    fn test_triangle_and_sine_waves() {
+        let mut buzzer = Buzzer::new(settings_without_ramp(Waveform::Triangle));
+        buzzer.set_playing(0, true);
+        let mut samples = [0i16; 4];
+        buzzer.render(&mut samples, 400, 0);
+        assert_eq!([-1000, 0, 1000, 0], samples);
+
+        let mut buzzer = Buzzer::new(settings_without_ramp(Waveform::Sine));
+        buzzer.set_playing(0, true);
+        buzzer.render(&mut samples, 400, 0);
+        assert_eq!([0, 1000, 0, -1000], samples);
+    }
+
+    #[test]
+    fn test_vip_buzz_follows_table() {
+        let mut buzzer = Buzzer::new(settings_without_ramp(Waveform::VipBuzz));
+        buzzer.set_playing(0, true);
+        // 1600Hz sample rate at 100Hz steps through the table one entry at a time.
+        let mut samples = [0i16; 16];
+        buzzer.render(&mut samples, 1600, 0);
+        assert_eq!(1000, samples[0]);
+        assert_eq!(-200, samples[4]);
+        assert_eq!(-400, samples[15]);
+    }
+
+    #[test]
+    fn test_transitions_are_placed_within_the_frame() {
+        let mut buzzer = Buzzer::new(settings_without_ramp(Waveform::Square));
+        // Sound on for the middle half of 100 cycles.
+        buzzer.set_playing(25, true);
+        buzzer.set_playing(75, false);
+        let mut samples = [0i16; 100];
+        buzzer.render(&mut samples, 800, 100);
+        assert!(samples[..25].iter().all(|&s| s == 0));
+        assert!(samples[25..75].iter().all(|&s| s != 0));
+        assert!(samples[75..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn test_unrendered_transitions_are_capped() {
+        let mut buzzer = Buzzer::new(settings_without_ramp(Waveform::Square));
+        for cycle in 0..100_000 {
+            buzzer.set_playing(cycle, cycle % 2 == 0);
+        }
+        assert!(buzzer.transitions.len() <= super::MAX_TRANSITIONS);
+        // What's left still plays, and leaves the buzzer where it should be.
+        buzzer.set_playing(100_000, true);
+        let mut samples = [0i16; 16];
+        buzzer.render(&mut samples, 800, 100_000);
+        assert!(buzzer.transitions.is_empty());
+        buzzer.render(&mut samples, 800, 100_100);
+        assert!(samples.iter().all(|&s| s != 0));
+    }
+
+    #[test]
+    fn test_ramp_fades_in_and_out() {
+        let mut buzzer = Buzzer::new(BuzzerSettings {
+            waveform: Waveform::Square,
+            frequency: 10.0,
+            volume: 1000,
+            // Four samples at 1000Hz.
+            ramp_ms: 4.0,
+        });
+        buzzer.set_playing(0, true);
+        let mut samples = [0i16; 6];
+        buzzer.render(&mut samples, 1000, 0);
+        assert_eq!([250, 500, 750, 1000, 1000, 1000], samples);
+
+        buzzer.set_playing(0, false);
+        buzzer.render(&mut samples, 1000, 0);
+        assert_eq!([750, 500, 250, 0, 0, 0], samples);
+    }
+
+    #[test]
+    fn test_frame_sample_counter_carries_remainder() {
+        let mut counter = FrameSampleCounter::new(100, 60);
+        let total: usize = (0..60).map(|_| counter.next_frame()).sum();
+        assert_eq!(100, total);
+        let mut counter = FrameSampleCounter::new(44100, 60);
+        assert_eq!(735, counter.next_frame());
+    }
+
+    #[test]
+    fn test_sound_timer_beep_length() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.set_buzzer_settings(settings_without_ramp(Waveform::Square));
+        let mut rom: [u8; 3584] = [0; 3584];
+        // V[A] = 30, sound timer = V[A], then loop forever.
+        rom[0..6].copy_from_slice(&[0x6A, 30, 0xFA, 0x18, 0x12, 0x04]);
+        chip8.load_rom(&rom);
+
+        let mut counter = FrameSampleCounter::new(44100, 60);
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        let mut audible_samples = Vec::new();
+
+        for frame in 0..60 {
+            for _ in 0..10 {
+                chip8.execute_next_opcode();
+            }
+            chip8.update_timers();
+            let mut samples = vec![0i16; counter.next_frame()];
+            chip8.render_audio(&mut samples, 44100);
+            for (i, &sample) in samples.iter().enumerate() {
+                if sample != 0 {
+                    audible_samples.push(frame * 735 + i);
+                }
+            }
+            wav.write_samples(&samples).unwrap();
+        }
+
+        // The tone starts once the second opcode has run, 2/10ths into the
+        // first frame, and stops when the timer runs out at the end of the
+        // 30th frame. That's 298 cycles at 73.5 samples per cycle.
+        assert_eq!(147, audible_samples[0]);
+        assert_eq!(30 * 735 - 1, *audible_samples.last().unwrap());
+        assert_eq!(30 * 735 - 147, audible_samples.len());
+
+        let bytes = wav.finish().unwrap().into_inner();
+        assert_eq!(44 + 44100 * 2, bytes.len());
+    }
+}
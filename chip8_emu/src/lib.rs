@@ -1,7 +1,12 @@
 extern crate rand;
 
+pub mod audio;
+pub mod wav;
+
 use rand::{Rng, SeedableRng, XorShiftRng};
 
+use audio::{Buzzer, BuzzerSettings};
+
 struct Memory {
     ram: [u8; 4096],
 }
@@ -31,6 +36,41 @@ struct Display {
     // 64x32, black or white
     screen: [[bool; 64]; 32],
     needs_draw: bool,
+    // Rows that have changed since the last call to take_dirty_rows().
+    dirty_rows: DirtyRows,
+    // Set after a draw with the vblank quirk, until the next timer tick.
+    waiting_for_vblank: bool,
+}
+
+// A set of display rows, one bit per row with row 0 in the lowest bit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DirtyRows(u32);
+
+impl DirtyRows {
+    pub fn all() -> DirtyRows {
+        DirtyRows(!0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, row: usize) -> bool {
+        self.0 & (1 << row) != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> {
+        let rows = self.0;
+        (0..32).filter(move |&row| rows & (1 << row) != 0)
+    }
+
+    fn insert(&mut self, row: usize) {
+        self.0 |= 1 << row;
+    }
 }
 
 struct Timers {
@@ -38,6 +78,63 @@ struct Timers {
     sound_timer: u8,
 }
 
+// Behaviours that differ between CHIP-8 interpreters, named after the quirks
+// in the community chip-8-database. The defaults are what this emulator has
+// always done, which suits most games written for CHIP-48 and later.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    // 8xy6 and 8xyE shift Vx in place, rather than putting Vy shifted into Vx.
+    pub shift: bool,
+    // Fx55 and Fx65 only move I on by x, rather than x + 1.
+    pub memory_increment_by_x: bool,
+    // Fx55 and Fx65 leave I alone.
+    pub memory_leave_i_unchanged: bool,
+    // Sprites wrap around the edges of the screen, rather than being clipped.
+    pub wrap: bool,
+    // Bxnn jumps to xnn + Vx, rather than to nnn + V0.
+    pub jump: bool,
+    // Drawing waits for the next timer tick, as it did on the COSMAC VIP, so
+    // only one sprite is drawn per frame.
+    pub vblank: bool,
+    // 8xy1, 8xy2 and 8xy3 reset VF to 0.
+    pub logic: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: true,
+            jump: false,
+            vblank: false,
+            logic: false,
+        }
+    }
+}
+
+// The registers, stack and timers, for debuggers to look at and change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuState {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+// How much of the machine a reset starts over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetKind {
+    // Restarts the program, but leaves memory as it is.
+    Soft,
+    // As if the machine were switched off and on again.
+    Hard,
+}
+
 pub struct Chip8<R: Rng> {
     memory: Memory,
     registers: Registers,
@@ -47,6 +144,17 @@ pub struct Chip8<R: Rng> {
     timers: Timers,
     // Random number generator that will be used for opcodes 0xC000...0xCFFF.
     rng: R,
+    // Makes a new generator from a seed on a hard reset, if we know how, along
+    // with the seed to use. Without a seed, a new one is picked at random.
+    seeded_rng: Option<fn(u64) -> R>,
+    seed: Option<u64>,
+    // The last ROM loaded and where it went, for hard resets.
+    rom: Vec<u8>,
+    rom_address: u16,
+    // Number of opcodes executed so far. Used to time sound on and off.
+    cycles: u64,
+    buzzer: Buzzer,
+    quirks: Quirks,
 }
 
 impl Memory {
@@ -173,16 +281,40 @@ impl Memory {
     fn load_rom_into_memory(&mut self, rom: &[u8; 3584]) {
         self.ram[0x200..].copy_from_slice(rom);
     }
+
+    // Anything that doesn't fit is cut off.
+    fn load_rom_into_memory_at(&mut self, rom: &[u8], address: usize) {
+        let length = rom.len().min(self.ram.len() - address);
+        self.ram[address..address + length].copy_from_slice(&rom[..length]);
+    }
 }
 
 // Use a fast RNG as the default.
 impl Chip8<XorShiftRng> {
     pub fn new_and_init() -> Chip8<XorShiftRng> {
-        Chip8::new_and_init_with_rng(SeedableRng::from_seed(rand::random()))
+        let mut chip8 = Chip8::new_and_init_with_rng(xorshift_from_seed(rand::random()));
+        chip8.seeded_rng = Some(xorshift_from_seed);
+        chip8
+    }
+
+    // The same seed always gives the same random numbers, including after a
+    // hard reset, for repeatable runs.
+    pub fn new_and_init_with_seed(seed: u64) -> Chip8<XorShiftRng> {
+        let mut chip8 = Chip8::new_and_init_with_rng(xorshift_from_seed(seed));
+        chip8.seeded_rng = Some(xorshift_from_seed);
+        chip8.seed = Some(seed);
+        chip8
     }
 }
 
+fn xorshift_from_seed(seed: u64) -> XorShiftRng {
+    // XorShift needs at least one bit set, which the constants see to.
+    XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x193A_6754, 0xA8A7_D469])
+}
+
 impl<R: Rng> Chip8<R> {
+    // There's no telling how to seed a generator given here, so it just
+    // carries on through hard resets.
     pub fn new_and_init_with_rng(r: R) -> Chip8<R> {
         let mut chip8 = Chip8 {
             memory: Memory { ram: [0; 4096] },
@@ -200,12 +332,21 @@ impl<R: Rng> Chip8<R> {
             display: Display {
                 screen: [[false; 64]; 32],
                 needs_draw: false,
+                dirty_rows: DirtyRows::default(),
+                waiting_for_vblank: false,
             },
             timers: Timers {
                 delay_timer: 0,
                 sound_timer: 0,
             },
+            seeded_rng: None,
+            seed: None,
             rng: r,
+            rom: Vec::new(),
+            rom_address: 0x200,
+            cycles: 0,
+            buzzer: Buzzer::new(BuzzerSettings::default()),
+            quirks: Quirks::default(),
         };
         chip8.memory.load_font_into_memory();
         return chip8;
@@ -213,6 +354,36 @@ impl<R: Rng> Chip8<R> {
 
     pub fn load_rom(&mut self, rom: &[u8; 3584]) {
         self.memory.load_rom_into_memory(rom);
+        self.rom = rom.to_vec();
+        self.rom_address = 0x200;
+    }
+
+    // Loads a ROM at some other address than 0x200, for programs written for
+    // machines like the ETI 660, and starts execution there.
+    pub fn load_rom_at(&mut self, rom: &[u8], address: u16) {
+        let address = (address & 0xFFF) as usize;
+        self.memory.load_rom_into_memory_at(rom, address);
+        self.registers.pc = address as u16;
+        self.rom = rom.to_vec();
+        self.rom_address = address as u16;
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory.ram
+    }
+
+    // Anything past the end of memory is cut off.
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) {
+        let address = (address & 0xFFF) as usize;
+        self.memory.load_rom_into_memory_at(bytes, address);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
     }
 
     #[inline]
@@ -223,11 +394,21 @@ impl<R: Rng> Chip8<R> {
     }
 
     pub fn execute_next_opcode(&mut self) {
+        if self.display.waiting_for_vblank {
+            // Time passes, but nothing happens until the next frame.
+            self.cycles += 1;
+            return;
+        }
         let opcode = self.read_next_opcode();
         self.registers.pc += 2;
         match opcode {
             0x00E0 => {
-                // Clear the screen.
+                // Clear the screen. Only rows with something on them change.
+                for (y, row) in self.display.screen.iter().enumerate() {
+                    if row.iter().any(|&pixel| pixel) {
+                        self.display.dirty_rows.insert(y);
+                    }
+                }
                 self.display.screen = [[false; 64]; 32];
                 self.display.needs_draw = true;
             }
@@ -299,14 +480,17 @@ impl<R: Rng> Chip8<R> {
                     0x1 => {
                         // Set Vx = Vx OR Vy.
                         self.registers.v[reg_x] |= self.registers.v[reg_y];
+                        self.reset_flag_for_logic_quirk();
                     }
                     0x2 => {
                         // Set Vx = Vx AND Vy.
                         self.registers.v[reg_x] &= self.registers.v[reg_y];
+                        self.reset_flag_for_logic_quirk();
                     }
                     0x3 => {
                         // Set Vx = Vx XOR Vy.
                         self.registers.v[reg_x] ^= self.registers.v[reg_y];
+                        self.reset_flag_for_logic_quirk();
                     }
                     0x4 => {
                         // Set Vx = Vx + Vy, with VF = carry.
@@ -325,7 +509,8 @@ impl<R: Rng> Chip8<R> {
                     }
                     0x6 => {
                         // Set Vx = Vx shifted right by 1, with VF = LSB of Vx equals 1.
-                        let v_x = self.registers.v[reg_x];
+                        // Without the shift quirk, Vy is shifted instead.
+                        let v_x = self.registers.v[if self.quirks.shift { reg_x } else { reg_y }];
                         self.registers.v[0xF] = v_x & 0x1;
                         self.registers.v[reg_x] = v_x >> 1;
                     }
@@ -338,7 +523,7 @@ impl<R: Rng> Chip8<R> {
                     }
                     0xE => {
                         // Set Vx = Vx shifted left by 1, with VF = MSB of Vx equals 1.
-                        let v_x = self.registers.v[reg_x];
+                        let v_x = self.registers.v[if self.quirks.shift { reg_x } else { reg_y }];
                         self.registers.v[0xF] = (v_x & 0x80) >> 7;
                         self.registers.v[reg_x] = v_x << 1;
                     }
@@ -363,9 +548,10 @@ impl<R: Rng> Chip8<R> {
                 self.registers.i = address;
             }
             0xB000...0xBFFF => {
-                // Jump to address + V0.
+                // Jump to address + V0, or to address + Vx with the jump quirk.
                 let address = opcode_address(opcode);
-                let computed_address = address.wrapping_add(self.registers.v[0x0] as u16);
+                let reg = if self.quirks.jump { opcode_register_vx(opcode) } else { 0x0 };
+                let computed_address = address.wrapping_add(self.registers.v[reg] as u16);
                 self.registers.pc = computed_address;
             }
             0xC000...0xCFFF => {
@@ -388,10 +574,16 @@ impl<R: Rng> Chip8<R> {
 
                 let mut did_overwrite = false;
 
-                // We'll draw in rows of 8 for num_bytes
+                // We'll draw in rows of 8 for num_bytes. The sprite always
+                // starts on screen, but whatever goes off the edge either
+                // wraps around or is clipped.
                 for sprite_y in 0..num_bytes {
                     let sprite_row = self.memory.ram[(memory_base + sprite_y as u16) as usize];
-                    let screen_y = ((v_y + sprite_y) % 32) as usize;
+                    let screen_y = (v_y % 32) as usize + sprite_y as usize;
+                    if screen_y >= 32 && !self.quirks.wrap {
+                        break;
+                    }
+                    let screen_y = screen_y % 32;
                     for sprite_x in 0..8 {
                         // Need to mask off the pixel since each byte represents a row of 8 pixels.
                         let sprite_pixel = if (sprite_row & 0x80 >> sprite_x) > 0 {
@@ -400,13 +592,20 @@ impl<R: Rng> Chip8<R> {
                             false
                         };
 
-                        let screen_x = ((v_x + sprite_x) % 64) as usize;
+                        let screen_x = (v_x % 64) as usize + sprite_x;
+                        if screen_x >= 64 && !self.quirks.wrap {
+                            break;
+                        }
+                        let screen_x = screen_x % 64;
                         let current_pixel = self.display.screen[screen_y][screen_x];
                         let new_pixel = sprite_pixel ^ current_pixel;
 
                         if current_pixel == true && sprite_pixel == true {
                             did_overwrite = true;
                         }
+                        if sprite_pixel {
+                            self.display.dirty_rows.insert(screen_y);
+                        }
 
                         self.display.screen[screen_y][screen_x] = new_pixel;
                     }
@@ -414,6 +613,7 @@ impl<R: Rng> Chip8<R> {
 
                 self.registers.v[0xF] = if did_overwrite { 1 } else { 0 };
                 self.display.needs_draw = true;
+                self.display.waiting_for_vblank = self.quirks.vblank;
             }
             0xE000...0xEFFF => {
                 // Handle key input
@@ -494,12 +694,14 @@ impl<R: Rng> Chip8<R> {
                         let index = self.registers.i as usize;
                         self.memory.ram[index..index + reg_x + 1]
                             .copy_from_slice(&self.registers.v[0..reg_x + 1]);
+                        self.advance_index_for_memory_quirks(reg_x);
                     }
                     0x65 => {
                         // Load memory into registers from 0 to x, inclusive.
                         let index = self.registers.i as usize;
                         self.registers.v[0..reg_x + 1]
                             .copy_from_slice(&self.memory.ram[index..index + reg_x + 1]);
+                        self.advance_index_for_memory_quirks(reg_x);
                     }
                     _ => {
                         // Unknown opcode.
@@ -510,28 +712,143 @@ impl<R: Rng> Chip8<R> {
                 // Unknown opcode.
             }
         }
+
+        self.cycles += 1;
+        self.buzzer.set_playing(self.cycles, self.timers.sound_timer > 0);
+    }
+
+    fn reset_flag_for_logic_quirk(&mut self) {
+        if self.quirks.logic {
+            self.registers.v[0xF] = 0;
+        }
+    }
+
+    // After Fx55 or Fx65 has stored or loaded registers 0 to x.
+    fn advance_index_for_memory_quirks(&mut self, reg_x: usize) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let step = if self.quirks.memory_increment_by_x { reg_x } else { reg_x + 1 };
+        self.registers.i = (self.registers.i + step as u16) & 0xFFF;
     }
 
     pub fn update_timers(&mut self) {
+        self.display.waiting_for_vblank = false;
         if self.timers.delay_timer > 0 {
             self.timers.delay_timer -= 1;
         }
         if self.timers.sound_timer > 0 {
             self.timers.sound_timer -= 1;
         }
+        self.buzzer.set_playing(self.cycles, self.timers.sound_timer > 0);
+    }
+
+    // Renders the buzzer output for everything executed since the last call.
+    // Call this once per frame with a frame's worth of samples; the tone will
+    // start and stop at the matching points within the buffer.
+    pub fn render_audio(&mut self, out: &mut [i16], sample_rate: u32) {
+        self.buzzer.render(out, sample_rate, self.cycles);
+    }
+
+    pub fn buzzer_settings(&self) -> BuzzerSettings {
+        self.buzzer.settings()
+    }
+
+    pub fn set_buzzer_settings(&mut self, settings: BuzzerSettings) {
+        self.buzzer.set_settings(settings);
     }
 
     pub fn get_screen_ref(&self) -> &[[bool; 64]; 32] {
         &self.display.screen
     }
 
+    // Returns whether the screen has changed since the last call, and clears
+    // the flag. Frontends can use this to skip redrawing unchanged frames.
+    pub fn take_draw_flag(&mut self) -> bool {
+        let needs_draw = self.display.needs_draw;
+        self.display.needs_draw = false;
+        needs_draw
+    }
+
+    // Returns the rows that have changed since the last call, and clears
+    // them. This is kept separately from the draw flag, so frontends can use
+    // either. Drawing the same sprite twice still counts as a change.
+    pub fn take_dirty_rows(&mut self) -> DirtyRows {
+        let dirty_rows = self.display.dirty_rows;
+        self.display.dirty_rows = DirtyRows::default();
+        dirty_rows
+    }
+
     pub fn set_key_state(&mut self, key_index: u8, state: bool) {
         self.input.keys[key_index as usize] = state;
     }
 
+    // Which keys are held, by index.
+    pub fn key_states(&self) -> [bool; 16] {
+        self.input.keys
+    }
+
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            v: self.registers.v,
+            i: self.registers.i,
+            pc: self.registers.pc,
+            sp: self.stack.sp,
+            stack: self.stack.ret_addresses,
+            delay_timer: self.timers.delay_timer,
+            sound_timer: self.timers.sound_timer,
+        }
+    }
+
+    // Addresses are kept inside memory and the stack pointer inside the
+    // stack, so that a debugger can't make the next opcode crash.
+    pub fn set_cpu_state(&mut self, state: &CpuState) {
+        self.registers.v = state.v;
+        self.registers.i = state.i & 0xFFF;
+        self.registers.pc = (state.pc & 0xFFF).min(0xFFE);
+        self.stack.sp = state.sp.min(15);
+        for (address, &new_address) in self.stack.ret_addresses.iter_mut().zip(state.stack.iter()) {
+            *address = new_address & 0xFFF;
+        }
+        self.timers.delay_timer = state.delay_timer;
+        self.timers.sound_timer = state.sound_timer;
+    }
+
     pub fn should_play_sound(&self) -> bool {
         self.timers.sound_timer > 0
     }
+
+    // Starts the last ROM loaded over from the beginning. Keys that are held
+    // stay held, and the quirks and buzzer settings are kept.
+    pub fn reset(&mut self, kind: ResetKind) {
+        self.registers = Registers {
+            pc: self.rom_address,
+            i: 0,
+            v: [0; 16],
+        };
+        self.stack = Stack {
+            ret_addresses: [0; 16],
+            sp: 0,
+        };
+        self.timers = Timers {
+            delay_timer: 0,
+            sound_timer: 0,
+        };
+        self.buzzer.set_playing(self.cycles, false);
+        self.display.screen = [[false; 64]; 32];
+        self.display.needs_draw = true;
+        self.display.dirty_rows = DirtyRows::all();
+        self.display.waiting_for_vblank = false;
+        if kind == ResetKind::Hard {
+            // Which also puts back anything the program changed in itself.
+            self.memory.ram = [0; 4096];
+            self.memory.load_font_into_memory();
+            self.memory.load_rom_into_memory_at(&self.rom, self.rom_address as usize);
+            if let Some(seeded_rng) = self.seeded_rng {
+                self.rng = seeded_rng(self.seed.unwrap_or_else(rand::random));
+            }
+        }
+    }
 }
 
 #[inline]
@@ -569,6 +886,8 @@ mod tests {
     use rand::{Rng, XorShiftRng};
     use super::Memory;
     use super::Chip8;
+    use super::Quirks;
+    use super::{CpuState, DirtyRows, ResetKind};
 
     #[test]
     fn test_load_font() {
@@ -635,6 +954,45 @@ mod tests {
         assert_eq!(false, chip8.display.screen[0][2]);
     }
 
+    #[test]
+    fn test_take_draw_flag() {
+        let mut chip8 = Chip8::new_and_init();
+        assert!(!chip8.take_draw_flag());
+
+        chip8.memory.ram[chip8.registers.pc as usize] = 0x00;
+        chip8.memory.ram[(chip8.registers.pc + 1) as usize] = 0xE0;
+        chip8.execute_next_opcode();
+
+        assert!(chip8.take_draw_flag());
+        // Taking the flag clears it.
+        assert!(!chip8.take_draw_flag());
+    }
+
+    #[test]
+    fn test_dirty_rows() {
+        let mut chip8 = Chip8::new_and_init();
+        assert!(chip8.take_dirty_rows().is_empty());
+
+        // Draw the top three rows of "0" at (0, 30), which wraps to the top.
+        chip8.memory.ram[chip8.registers.pc as usize] = 0xDA;
+        chip8.memory.ram[(chip8.registers.pc + 1) as usize] = 0xB3;
+        chip8.registers.v[0xB] = 30;
+        chip8.registers.i = 0x50;
+        chip8.execute_next_opcode();
+
+        let dirty_rows = chip8.take_dirty_rows();
+        assert_eq!(vec![0, 30, 31], dirty_rows.iter().collect::<Vec<_>>());
+        assert!(dirty_rows.contains(31));
+        assert!(!dirty_rows.contains(1));
+        assert!(chip8.take_dirty_rows().is_empty());
+
+        // Clearing only touches the rows that had something on them.
+        chip8.memory.ram[chip8.registers.pc as usize] = 0x00;
+        chip8.memory.ram[(chip8.registers.pc + 1) as usize] = 0xE0;
+        chip8.execute_next_opcode();
+        assert_eq!(3, chip8.take_dirty_rows().count());
+    }
+
     #[test]
     fn test_opcode_00ee_ret_pop_stack() {
         let mut chip8 = Chip8::new_and_init();
@@ -1301,4 +1659,159 @@ mod tests {
         // Index should NOT have changed.
         assert_eq!(1000, chip8.registers.i);
     }
+
+    fn execute_with_quirks(chip8: &mut Chip8<XorShiftRng>, opcode: u16, quirks: Quirks) {
+        chip8.set_quirks(quirks);
+        chip8.memory.ram[chip8.registers.pc as usize] = (opcode >> 8) as u8;
+        chip8.memory.ram[(chip8.registers.pc + 1) as usize] = opcode as u8;
+        chip8.execute_next_opcode();
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.registers.v[1] = 0b0000_0011;
+        chip8.registers.v[2] = 0b1000_0000;
+        execute_with_quirks(&mut chip8, 0x8126, Quirks { shift: false, ..Quirks::default() });
+        // Vy is shifted into Vx.
+        assert_eq!(0b0100_0000, chip8.registers.v[1]);
+        assert_eq!(0, chip8.registers.v[0xF]);
+    }
+
+    #[test]
+    fn test_memory_quirks() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.registers.i = 1000;
+        execute_with_quirks(&mut chip8, 0xF455, Quirks { memory_leave_i_unchanged: false, ..Quirks::default() });
+        assert_eq!(1005, chip8.registers.i);
+
+        let quirks = Quirks { memory_leave_i_unchanged: false, memory_increment_by_x: true, ..Quirks::default() };
+        execute_with_quirks(&mut chip8, 0xF465, quirks);
+        assert_eq!(1009, chip8.registers.i);
+    }
+
+    #[test]
+    fn test_jump_and_logic_quirks() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.registers.v[0x0] = 0x10;
+        chip8.registers.v[0x3] = 0x20;
+        execute_with_quirks(&mut chip8, 0xB300, Quirks { jump: true, ..Quirks::default() });
+        assert_eq!(0x320, chip8.registers.pc);
+
+        chip8.registers.v[0xF] = 1;
+        execute_with_quirks(&mut chip8, 0x8011, Quirks { logic: true, ..Quirks::default() });
+        assert_eq!(0, chip8.registers.v[0xF]);
+    }
+
+    #[test]
+    fn test_sprites_clip_without_wrap_quirk() {
+        let mut chip8 = Chip8::new_and_init();
+        // Draw the top row of "0" at (60, 31).
+        chip8.registers.v[0xA] = 60;
+        chip8.registers.v[0xB] = 31;
+        chip8.registers.i = 0x50;
+        execute_with_quirks(&mut chip8, 0xDAB2, Quirks { wrap: false, ..Quirks::default() });
+        assert!(chip8.display.screen[31][63]);
+        assert!(!chip8.display.screen[31][0]);
+        assert!(!chip8.display.screen[0][60]);
+    }
+
+    #[test]
+    fn test_vblank_quirk_waits_for_timers() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.registers.i = 0x50;
+        execute_with_quirks(&mut chip8, 0xD001, Quirks { vblank: true, ..Quirks::default() });
+        let pc = chip8.registers.pc;
+        chip8.execute_next_opcode();
+        assert_eq!(pc, chip8.registers.pc);
+
+        chip8.update_timers();
+        chip8.execute_next_opcode();
+        assert_eq!(pc + 2, chip8.registers.pc);
+    }
+
+    #[test]
+    fn test_load_rom_at() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.load_rom_at(&[0x12, 0x34], 0x600);
+        assert_eq!(0x600, chip8.registers.pc);
+        assert_eq!(0x1234, chip8.read_next_opcode());
+        // Whatever doesn't fit is dropped.
+        chip8.load_rom_at(&[1, 2, 3], 0xFFE);
+        assert_eq!(2, chip8.memory.ram[0xFFF]);
+    }
+
+    #[test]
+    fn test_write_memory() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.write_memory(0xE00, &[0xAB, 0xCD]);
+        assert_eq!([0xAB, 0xCD], chip8.memory()[0xE00..0xE02]);
+        // The program counter stays where it was.
+        assert_eq!(0x200, chip8.registers.pc);
+    }
+
+    #[test]
+    fn test_cpu_state_round_trip() {
+        let mut chip8 = Chip8::new_and_init();
+        let state = CpuState {
+            v: [7; 16],
+            i: 0x1234,
+            pc: 0xFFF,
+            sp: 20,
+            stack: [0x300; 16],
+            delay_timer: 3,
+            sound_timer: 4,
+        };
+        chip8.set_cpu_state(&state);
+        let read_back = chip8.cpu_state();
+        assert_eq!(CpuState { i: 0x234, pc: 0xFFE, sp: 15, ..state }, read_back);
+        assert!(chip8.should_play_sound());
+    }
+
+    #[test]
+    fn test_soft_reset_keeps_memory() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.load_rom_at(&[0x60, 0x05, 0xF0, 0x15, 0xF0, 0x29, 0xD0, 0x05], 0x600);
+        for _ in 0..4 {
+            chip8.execute_next_opcode();
+        }
+        chip8.memory.ram[0xE00] = 0x42;
+        chip8.reset(ResetKind::Soft);
+
+        assert_eq!(0x600, chip8.registers.pc);
+        assert_eq!(0, chip8.registers.v[0]);
+        assert_eq!(0, chip8.timers.delay_timer);
+        assert!(chip8.get_screen_ref().iter().all(|row| row.iter().all(|&pixel| !pixel)));
+        assert!(chip8.take_dirty_rows() == DirtyRows::all());
+        assert_eq!(0x42, chip8.memory.ram[0xE00]);
+    }
+
+    #[test]
+    fn test_hard_reset_reloads_the_rom_and_reseeds() {
+        // Put a random byte into V0, over and over.
+        let mut chip8 = Chip8::new_and_init_with_seed(42);
+        chip8.load_rom_at(&[0xC0, 0xFF, 0x12, 0x00], 0x200);
+        chip8.execute_next_opcode();
+        let first_random = chip8.registers.v[0];
+        chip8.execute_next_opcode();
+        chip8.execute_next_opcode();
+        chip8.memory.ram[0x201] = 0x0F;
+        chip8.memory.ram[0xE00] = 0x42;
+        chip8.reset(ResetKind::Hard);
+
+        assert_eq!(0xFF, chip8.memory.ram[0x201]);
+        assert_eq!(0, chip8.memory.ram[0xE00]);
+        assert_eq!(0xF0, chip8.memory.ram[0x50]);
+        chip8.execute_next_opcode();
+        assert_eq!(first_random, chip8.registers.v[0]);
+    }
+
+    #[test]
+    fn test_hard_reset_without_a_seed_picks_a_new_one() {
+        let mut chip8 = Chip8::new_and_init();
+        let first: Vec<u8> = (0..8).map(|_| chip8.rng.gen()).collect();
+        chip8.reset(ResetKind::Hard);
+        let second: Vec<u8> = (0..8).map(|_| chip8.rng.gen()).collect();
+        assert!(first != second);
+    }
 }
@@ -0,0 +1,96 @@
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+// Writes mono, signed 16-bit PCM samples to a WAV file. The chunk sizes in the
+// header aren't known until we're done, so they're filled in by finish().
+
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_bytes: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        let channels: u16 = 1;
+        let bits_per_sample: u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+        let byte_rate = sample_rate * block_align as u32;
+
+        out.write_all(b"RIFF")?;
+        // RIFF chunk size, patched in finish().
+        write_u32_le(&mut out, 0)?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        write_u32_le(&mut out, 16)?;
+        // Format 1 is uncompressed PCM.
+        write_u16_le(&mut out, 1)?;
+        write_u16_le(&mut out, channels)?;
+        write_u32_le(&mut out, sample_rate)?;
+        write_u32_le(&mut out, byte_rate)?;
+        write_u16_le(&mut out, block_align)?;
+        write_u16_le(&mut out, bits_per_sample)?;
+
+        out.write_all(b"data")?;
+        // Data chunk size, patched in finish().
+        write_u32_le(&mut out, 0)?;
+
+        Ok(WavWriter {
+            out,
+            data_bytes: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            bytes.push((*sample as u16 & 0xFF) as u8);
+            bytes.push((*sample as u16 >> 8) as u8);
+        }
+        self.out.write_all(&bytes)?;
+        self.data_bytes += bytes.len() as u32;
+        Ok(())
+    }
+
+    // Fills in the header and hands back the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        write_u32_le(&mut self.out, 36 + self.data_bytes)?;
+        self.out.seek(SeekFrom::Start(40))?;
+        write_u32_le(&mut self.out, self.data_bytes)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn write_u16_le<W: Write>(out: &mut W, value: u16) -> io::Result<()> {
+    out.write_all(&[(value & 0xFF) as u8, (value >> 8) as u8])
+}
+
+fn write_u32_le<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+    write_u16_le(out, (value & 0xFFFF) as u16)?;
+    write_u16_le(out, (value >> 16) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::WavWriter;
+
+    #[test]
+    fn test_header_sizes_are_patched() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 8000).unwrap();
+        wav.write_samples(&[0, 1, -1]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(b"RIFF", &bytes[0..4]);
+        assert_eq!([42, 0, 0, 0], bytes[4..8]);
+        assert_eq!(b"WAVE", &bytes[8..12]);
+        // Sample rate.
+        assert_eq!([0x40, 0x1F, 0, 0], bytes[24..28]);
+        assert_eq!(b"data", &bytes[36..40]);
+        assert_eq!([6, 0, 0, 0], bytes[40..44]);
+        assert_eq!([0, 0, 1, 0, 0xFF, 0xFF], bytes[44..50]);
+    }
+}
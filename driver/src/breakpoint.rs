@@ -0,0 +1,132 @@
+use chip8_emu::CpuState;
+
+use expression::{Expression, Template};
+
+// A debugger's breakpoint beyond its address: a condition, how many times it
+// has to be hit, and a message to log instead of stopping.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum HitCondition {
+    Exactly(u64),
+    AtLeast(u64),
+    After(u64),
+    Every(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BreakpointAction {
+    Continue,
+    Stop,
+    Log(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    condition: Option<Expression>,
+    hit_condition: Option<HitCondition>,
+    log_message: Option<Template>,
+    // Times the condition held when it was reached.
+    hits: u64,
+}
+
+impl Breakpoint {
+    // Empty strings count as nothing given, since that's what editors send
+    // when a condition is cleared.
+    pub fn new(condition: Option<&str>, hit_condition: Option<&str>, log_message: Option<&str>)
+               -> Result<Breakpoint, String> {
+        let condition = match given(condition) {
+            Some(condition) => Some(Expression::parse(condition)
+                .map_err(|error| format!("Couldn't read the condition: {}", error))?),
+            None => None,
+        };
+        let hit_condition = match given(hit_condition) {
+            Some(hit_condition) => Some(parse_hit_condition(hit_condition)
+                .ok_or_else(|| String::from("Hit counts look like 10, >= 10, > 10 or % 10"))?),
+            None => None,
+        };
+        // The message is kept as it is, spaces and all.
+        let log_message = match log_message.filter(|text| !text.is_empty()) {
+            Some(log_message) => Some(Template::parse(log_message)
+                .map_err(|error| format!("Couldn't read the log message: {}", error))?),
+            None => None,
+        };
+        Ok(Breakpoint { condition, hit_condition, log_message, hits: 0 })
+    }
+
+    // Called when the machine reaches the breakpoint's address.
+    pub fn hit(&mut self, state: &CpuState, memory: &[u8]) -> BreakpointAction {
+        if let Some(ref condition) = self.condition {
+            if condition.evaluate(state, memory) == 0 {
+                return BreakpointAction::Continue;
+            }
+        }
+        self.hits += 1;
+        let is_counted = match self.hit_condition {
+            None => true,
+            Some(HitCondition::Exactly(count)) => self.hits == count,
+            Some(HitCondition::AtLeast(count)) => self.hits >= count,
+            Some(HitCondition::After(count)) => self.hits > count,
+            Some(HitCondition::Every(count)) => self.hits.is_multiple_of(count),
+        };
+        match (is_counted, self.log_message.as_ref()) {
+            (false, _) => BreakpointAction::Continue,
+            (true, Some(log_message)) => BreakpointAction::Log(log_message.format(state, memory)),
+            (true, None) => BreakpointAction::Stop,
+        }
+    }
+}
+
+fn given(text: Option<&str>) -> Option<&str> {
+    text.map(str::trim).filter(|text| !text.is_empty())
+}
+
+fn parse_hit_condition(text: &str) -> Option<HitCondition> {
+    let (make, count): (fn(u64) -> HitCondition, &str) = if let Some(count) = text.strip_prefix(">=") {
+        (HitCondition::AtLeast, count)
+    } else if let Some(count) = text.strip_prefix('>') {
+        (HitCondition::After, count)
+    } else if let Some(count) = text.strip_prefix('%') {
+        (HitCondition::Every, count)
+    } else if let Some(count) = text.strip_prefix("==") {
+        (HitCondition::Exactly, count)
+    } else {
+        (HitCondition::Exactly, text)
+    };
+    count.trim().parse::<u64>().ok().filter(|&count| count > 0).map(make)
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_emu::Chip8;
+
+    use super::{Breakpoint, BreakpointAction};
+
+    #[test]
+    fn test_conditions_and_hit_counts() {
+        let mut chip8 = Chip8::new_and_init();
+        let mut breakpoint = Breakpoint::new(Some("V0 >= 2"), Some("% 2"), None).unwrap();
+        let mut actions = Vec::new();
+        for value in 0..6 {
+            let mut state = chip8.cpu_state();
+            state.v[0] = value;
+            chip8.set_cpu_state(&state);
+            actions.push(breakpoint.hit(&chip8.cpu_state(), chip8.memory()));
+        }
+        // Hit at 2, 3, 4 and 5, and every other one of those stops.
+        assert_eq!(vec![BreakpointAction::Continue, BreakpointAction::Continue, BreakpointAction::Continue,
+                        BreakpointAction::Stop, BreakpointAction::Continue, BreakpointAction::Stop],
+                   actions);
+
+        let mut logpoint = Breakpoint::new(Some(""), Some("200"), Some("V0 is {v0}")).unwrap();
+        let state = chip8.cpu_state();
+        for _ in 0..199 {
+            assert_eq!(BreakpointAction::Continue, logpoint.hit(&state, chip8.memory()));
+        }
+        assert_eq!(BreakpointAction::Log(String::from("V0 is 5")), logpoint.hit(&state, chip8.memory()));
+        assert_eq!(BreakpointAction::Continue, logpoint.hit(&state, chip8.memory()));
+
+        assert!(Breakpoint::new(Some("V0 >="), None, None).unwrap_err().contains("condition"));
+        assert!(Breakpoint::new(None, Some("0"), None).is_err());
+        assert!(Breakpoint::new(None, Some("< 3"), None).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use std::mem;
+
+use chip8_emu::{Chip8, Quirks, ResetKind};
+
+use rand::Rng;
+
+use display::Rotation;
+use filter::{FilterSettings, FrameFilter};
+use frontend::{Grid, GridCell, Shades};
+use palette::{Palette, Rgb};
+use runner;
+use runner::{Game, DEFAULT_CYCLES_PER_FRAME};
+
+// Running the same ROM on several machines at once, for instance with
+// different quirks or at different speeds, to see where they part ways. The
+// runner's own machine is the first; the rest follow along here, a frame at
+// a time, and are shown next to it in a grid.
+
+type Screen = [[bool; 64]; 32];
+
+// Around each display in the grid, in emulated pixels.
+const BORDER: usize = 1;
+const BORDER_SHADE: u8 = 0x40;
+const INPUT_BORDER_SHADE: u8 = 0xC0;
+// Stands out whatever the palette.
+const DIFFERENCE_COLOR: Rgb = [0xFF, 0x20, 0x40];
+
+// How a machine being compared differs from the first one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MachineSpec {
+    pub quirks: Option<Quirks>,
+    pub cycles_per_frame: Option<u32>,
+    pub seed: Option<u64>,
+}
+
+struct Machine<R: Rng> {
+    chip8: Chip8<R>,
+    cycles_per_frame: u32,
+    filter: FrameFilter,
+    // Where its display differs from the first machine's, from the frame on
+    // which that happened until the next one runs.
+    differences: Option<Screen>,
+}
+
+pub struct Comparison<R: Rng> {
+    machines: Vec<Machine<R>>,
+    // The machine the keys go to, counting the runner's own as 0, or None
+    // for all of them.
+    focus: Option<usize>,
+    frames: u64,
+    // The frame on which the displays first differed. Only the first time is
+    // reported, until the machines are reset.
+    first_difference: Option<u64>,
+    // Whether the grid needs drawing again, even if no display has changed.
+    has_changed: bool,
+}
+
+impl<R: Rng> Comparison<R> {
+    pub fn new(games: Vec<(Chip8<R>, Game)>, filter_settings: FilterSettings) -> Comparison<R> {
+        let machines = games.into_iter().map(|(chip8, game)| Machine {
+            chip8,
+            cycles_per_frame: game.cycles_per_frame.unwrap_or(DEFAULT_CYCLES_PER_FRAME),
+            filter: FrameFilter::new(filter_settings),
+            differences: None,
+        }).collect();
+        Comparison {
+            machines,
+            focus: None,
+            frames: 0,
+            first_difference: None,
+            has_changed: true,
+        }
+    }
+
+    pub fn machine_count(&self) -> usize {
+        self.machines.len() + 1
+    }
+
+    pub fn first_difference(&self) -> Option<u64> {
+        self.first_difference
+    }
+
+    // Runs the other machines for a frame, once the first has run its own,
+    // and compares their displays with the first's. Returns true if this is
+    // the first time they've differed.
+    pub fn execute_frame(&mut self, screen: &Screen) -> bool {
+        self.frames += 1;
+        for machine in self.machines.iter_mut() {
+            if machine.differences.take().is_some() {
+                self.has_changed = true;
+            }
+            runner::execute_for_frame(&mut machine.chip8, machine.cycles_per_frame);
+        }
+        if self.first_difference.is_some() {
+            return false;
+        }
+
+        for machine in self.machines.iter_mut() {
+            let other = machine.chip8.get_screen_ref();
+            if other != screen {
+                let mut differences = [[false; 64]; 32];
+                for (y, row) in differences.iter_mut().enumerate() {
+                    for (x, pixel) in row.iter_mut().enumerate() {
+                        *pixel = other[y][x] != screen[y][x];
+                    }
+                }
+                machine.differences = Some(differences);
+            }
+        }
+        if self.machines.iter().any(|machine| machine.differences.is_some()) {
+            self.first_difference = Some(self.frames);
+            self.has_changed = true;
+        }
+        self.first_difference.is_some()
+    }
+
+    // Passes a key on to the machines that have the focus. Returns whether
+    // the first machine should get it too.
+    pub fn set_key_state(&mut self, index: u8, state: bool) -> bool {
+        for (number, machine) in self.machines.iter_mut().enumerate() {
+            if has_input(self.focus, number + 1) {
+                machine.chip8.set_key_state(index, state);
+            }
+        }
+        has_input(self.focus, 0)
+    }
+
+    // Moves the focus from all the machines to each one in turn, and back.
+    // Keys that were held are let go. Returns a message saying where the
+    // keys go now.
+    pub fn cycle_focus(&mut self) -> String {
+        self.focus = match self.focus {
+            None => Some(0),
+            Some(number) if number + 1 < self.machine_count() => Some(number + 1),
+            Some(_) => None,
+        };
+        for machine in self.machines.iter_mut() {
+            for index in 0..16 {
+                machine.chip8.set_key_state(index, false);
+            }
+        }
+        self.has_changed = true;
+        match self.focus {
+            None => String::from("Keys go to every machine"),
+            Some(number) => format!("Keys go to machine {}", number + 1),
+        }
+    }
+
+    // Resets the other machines along with the first, after which they're
+    // watched for differences again.
+    pub fn reset(&mut self, kind: ResetKind) {
+        for machine in self.machines.iter_mut() {
+            machine.chip8.reset(kind);
+            machine.differences = None;
+        }
+        self.frames = 0;
+        self.first_difference = None;
+        self.has_changed = true;
+    }
+
+    // Lays the first machine's display, as given, out with the others'.
+    // has_changed is whether the first one has changed; the result says
+    // whether any of them have.
+    pub fn grid(&mut self, shades: &Shades, has_changed: bool) -> (Grid, bool) {
+        let mut has_changed = has_changed || mem::replace(&mut self.has_changed, false);
+        let mut cells = vec![GridCell {
+            shades: *shades,
+            has_input: has_input(self.focus, 0),
+            differences: None,
+        }];
+        for (number, machine) in self.machines.iter_mut().enumerate() {
+            let dirty_rows = machine.chip8.take_dirty_rows();
+            has_changed |= machine.filter.apply(machine.chip8.get_screen_ref(), dirty_rows);
+            cells.push(GridCell {
+                shades: *machine.filter.shades(),
+                has_input: has_input(self.focus, number + 1),
+                differences: machine.differences,
+            });
+        }
+        let columns = grid_size(cells.len()).0;
+        (Grid { cells, columns }, has_changed)
+    }
+}
+
+fn has_input(focus: Option<usize>, number: usize) -> bool {
+    focus.is_none_or(|focus| focus == number)
+}
+
+// Columns and rows for a grid of this many displays, as near square as it
+// gets.
+pub fn grid_size(count: usize) -> (usize, usize) {
+    let columns = (1..=count).find(|columns| columns * columns >= count).unwrap_or(1);
+    (columns, count.div_ceil(columns))
+}
+
+// Size of the picture of a grid of displays of the given size.
+pub fn grid_image_size(display_size: (usize, usize), grid_size: (usize, usize)) -> (usize, usize) {
+    ((display_size.0 + 2 * BORDER) * grid_size.0, (display_size.1 + 2 * BORDER) * grid_size.1)
+}
+
+// Draws the grid as RGB pixels, each display rotated in its own place.
+// Returns the size of the picture.
+pub fn render_grid(grid: &Grid, palette: &Palette, rotation: Rotation, pixels: &mut Vec<u8>) -> (usize, usize) {
+    let (width, height) = (64, 32);
+    let display_size = rotation.rotated_size(width, height);
+    let rows = grid.cells.len().div_ceil(grid.columns);
+    let image_size = grid_image_size(display_size, (grid.columns, rows));
+    pixels.clear();
+    pixels.extend(palette.background().iter().cycle().take(image_size.0 * image_size.1 * 3));
+
+    let mut put = |x: usize, y: usize, color: Rgb| {
+        let offset = (y * image_size.0 + x) * 3;
+        pixels[offset..offset + 3].copy_from_slice(&color);
+    };
+    let (cell_width, cell_height) = (display_size.0 + 2 * BORDER, display_size.1 + 2 * BORDER);
+    for (number, cell) in grid.cells.iter().enumerate() {
+        let left = number % grid.columns * cell_width;
+        let top = number / grid.columns * cell_height;
+        let border = palette.shade_color(if cell.has_input { INPUT_BORDER_SHADE } else { BORDER_SHADE });
+        for y in 0..cell_height {
+            for x in 0..cell_width {
+                if x < BORDER || y < BORDER || x >= cell_width - BORDER || y >= cell_height - BORDER {
+                    put(left + x, top + y, border);
+                }
+            }
+        }
+        for (y, row) in cell.shades.iter().enumerate() {
+            for (x, &shade) in row.iter().enumerate() {
+                let color = match cell.differences {
+                    Some(ref differences) if differences[y][x] => DIFFERENCE_COLOR,
+                    _ => palette.shade_color(shade),
+                };
+                let (image_x, image_y) = rotation.rotate_position(x, y, width, height);
+                put(left + BORDER + image_x, top + BORDER + image_y, color);
+            }
+        }
+    }
+    image_size
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chip8_emu::Chip8;
+
+    use display::Rotation;
+    use filter::FilterSettings;
+    use frontend::{Grid, GridCell};
+    use palette::Palette;
+    use runner::Game;
+    use runner::tests::test_game;
+    use super::{Comparison, DIFFERENCE_COLOR};
+
+    fn game(rom: &[u8]) -> (Chip8<::rand::XorShiftRng>, Game) {
+        let mut chip8 = Chip8::new_and_init();
+        let game = test_game(rom, Path::new("test.ch8"), &mut chip8);
+        (chip8, game)
+    }
+
+    #[test]
+    fn test_grid_size() {
+        assert_eq!((1, 1), super::grid_size(1));
+        assert_eq!((2, 1), super::grid_size(2));
+        assert_eq!((2, 2), super::grid_size(3));
+        assert_eq!((3, 2), super::grid_size(5));
+    }
+
+    #[test]
+    fn test_first_difference_is_found_once() {
+        // Wait for key 5 to be pressed, then draw the "0" glyph.
+        let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0xF1, 0x29, 0xD1, 0x15, 0x12, 0x0A];
+        let mut first = game(&rom).0;
+        let mut comparison = Comparison::new(vec![game(&rom)], FilterSettings::default());
+        let run_frame = |first: &mut Chip8<_>, comparison: &mut Comparison<_>| {
+            ::runner::execute_for_frame(first, 10);
+            comparison.execute_frame(first.get_screen_ref())
+        };
+        assert!(!run_frame(&mut first, &mut comparison));
+
+        // Only the first machine sees the key.
+        assert!(comparison.cycle_focus().contains("machine 1"));
+        let first_gets_it = comparison.set_key_state(5, true);
+        first.set_key_state(5, first_gets_it);
+        assert!(run_frame(&mut first, &mut comparison));
+        assert_eq!(Some(2), comparison.first_difference());
+        let (grid, has_changed) = comparison.grid(&[[0; 64]; 32], false);
+        assert!(has_changed);
+        assert!(grid.cells[1].differences.is_some_and(|differences| differences[0][0]));
+        assert!(grid.cells[0].has_input && !grid.cells[1].has_input);
+
+        assert!(!run_frame(&mut first, &mut comparison));
+        let (grid, _) = comparison.grid(&[[0; 64]; 32], false);
+        assert!(grid.cells[1].differences.is_none());
+    }
+
+    #[test]
+    fn test_render_grid() {
+        let mut shades = [[0; 64]; 32];
+        shades[0][0] = 255;
+        let mut differences = [[false; 64]; 32];
+        differences[0][1] = true;
+        let grid = Grid {
+            cells: vec![
+                GridCell { shades, has_input: true, differences: None },
+                GridCell { shades, has_input: false, differences: Some(differences) },
+            ],
+            columns: 2,
+        };
+        let palette = Palette::default();
+        let mut pixels = Vec::new();
+        assert_eq!((132, 34), super::render_grid(&grid, &palette, Rotation::None, &mut pixels));
+        let pixel = |x: usize, y: usize| [pixels[(y * 132 + x) * 3], pixels[(y * 132 + x) * 3 + 1], pixels[(y * 132 + x) * 3 + 2]];
+        assert_eq!(palette.foreground(), pixel(1, 1));
+        assert_eq!(palette.foreground(), pixel(67, 1));
+        assert_eq!(DIFFERENCE_COLOR, pixel(68, 1));
+        assert_eq!(palette.shade_color(super::INPUT_BORDER_SHADE), pixel(0, 0));
+        assert_eq!(palette.shade_color(super::BORDER_SHADE), pixel(66, 0));
+
+        // Turned on their sides, the displays are laid out the same way.
+        assert_eq!((68, 66), super::render_grid(&grid, &palette, Rotation::Quarter, &mut pixels));
+    }
+}
@@ -0,0 +1,231 @@
+// A software imitation of an old CRT, for when the display is going to be
+// shown off. The picture is scaled up on the CPU and each effect is applied
+// to the scaled image, so no shaders or GPU are needed and the result can be
+// handed to any backend as a plain RGB image.
+
+// Smallest and largest number of output pixels per emulated pixel. Below the
+// minimum there's no room for the effects to show; above the maximum the GPU
+// can do the rest of the scaling without anyone noticing.
+const MIN_SCALE: usize = 3;
+const MAX_SCALE: usize = 8;
+// How much of the light is left in the dark half of each scanline and in the
+// gaps between pixels.
+const SCANLINE_LEVEL: f32 = 0.55;
+const PIXEL_GAP_LEVEL: f32 = 0.35;
+const BLOOM_PASSES: usize = 2;
+const BLOOM_STRENGTH: f32 = 0.6;
+// How far the corners get pulled in; zero would be a flat screen.
+const CURVATURE: f32 = 0.08;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CrtEffects {
+    pub scanlines: bool,
+    pub pixel_gaps: bool,
+    pub bloom: bool,
+    pub curvature: bool,
+}
+
+impl CrtEffects {
+    pub fn is_enabled(&self) -> bool {
+        self.scanlines || self.pixel_gaps || self.bloom || self.curvature
+    }
+}
+
+// Picks a scale for rendering into a viewport of the given width.
+pub fn output_scale(viewport_width: usize, image_width: usize) -> usize {
+    (viewport_width / image_width).clamp(MIN_SCALE, MAX_SCALE)
+}
+
+// Renders an RGB image of width x height pixels into out at the given scale,
+// with the effects applied.
+pub fn render(effects: CrtEffects,
+              image: &[u8],
+              width: usize,
+              height: usize,
+              scale: usize,
+              out: &mut Vec<u8>) {
+    let (out_width, out_height) = (width * scale, height * scale);
+    out.clear();
+    out.resize(out_width * out_height * 3, 0);
+
+    let glow = if effects.bloom {
+        blur(image, width, height)
+    } else {
+        Vec::new()
+    };
+    // The gaps are one output pixel wide.
+    let gap_start = 1.0 - 1.0 / scale as f32;
+
+    for out_y in 0..out_height {
+        for out_x in 0..out_width {
+            // Screen position from -1 to 1 across, measured from the centre.
+            let mut u = (out_x as f32 + 0.5) / out_width as f32 * 2.0 - 1.0;
+            let mut v = (out_y as f32 + 0.5) / out_height as f32 * 2.0 - 1.0;
+            if effects.curvature {
+                let (curved_u, curved_v) = (u * (1.0 + CURVATURE * v * v),
+                                            v * (1.0 + CURVATURE * u * u));
+                if curved_u.abs() > 1.0 || curved_v.abs() > 1.0 {
+                    // Off the edge of the tube.
+                    continue;
+                }
+                u = curved_u;
+                v = curved_v;
+            }
+
+            // Position on the emulated display, in pixels.
+            let x = (u + 1.0) / 2.0 * width as f32;
+            let y = (v + 1.0) / 2.0 * height as f32;
+            let (cell_x, cell_y) = ((x as usize).min(width - 1), (y as usize).min(height - 1));
+            let (within_x, within_y) = (x - cell_x as f32, y - cell_y as f32);
+
+            let mut level = 1.0;
+            if effects.pixel_gaps && (within_x >= gap_start || within_y >= gap_start) {
+                level *= PIXEL_GAP_LEVEL;
+            }
+            if effects.scanlines && within_y >= 0.5 {
+                level *= SCANLINE_LEVEL;
+            }
+
+            let source = (cell_y * width + cell_x) * 3;
+            let destination = (out_y * out_width + out_x) * 3;
+            for channel in 0..3 {
+                let mut value = image[source + channel] as f32 * level;
+                if effects.bloom {
+                    // Sample the glow between pixel centres so that it spreads
+                    // smoothly rather than in blocks.
+                    value += sample(&glow, width, height, x - 0.5, y - 0.5, channel) * BLOOM_STRENGTH;
+                }
+                out[destination + channel] = value.round().min(255.0) as u8;
+            }
+        }
+    }
+}
+
+// A blurred copy of the image, from a few passes of a small box filter.
+fn blur(image: &[u8], width: usize, height: usize) -> Vec<f32> {
+    let mut current: Vec<f32> = image.iter().map(|&value| value as f32).collect();
+    let mut next = vec![0.0; current.len()];
+    for _ in 0..BLOOM_PASSES {
+        for &(step_x, step_y) in &[(1, 0), (0, 1)] {
+            for y in 0..height {
+                for x in 0..width {
+                    let before = (x.saturating_sub(step_x), y.saturating_sub(step_y));
+                    let after = ((x + step_x).min(width - 1), (y + step_y).min(height - 1));
+                    for channel in 0..3 {
+                        let at = |(x, y): (usize, usize)| current[(y * width + x) * 3 + channel];
+                        next[(y * width + x) * 3 + channel] =
+                            (at(before) + 2.0 * at((x, y)) + at(after)) / 4.0;
+                    }
+                }
+            }
+            ::std::mem::swap(&mut current, &mut next);
+        }
+    }
+    current
+}
+
+// Bilinear sample of one channel of a width x height image.
+fn sample(image: &[f32], width: usize, height: usize, x: f32, y: f32, channel: usize) -> f32 {
+    let x = x.clamp(0.0, (width - 1) as f32);
+    let y = y.clamp(0.0, (height - 1) as f32);
+    let (left, top) = (x as usize, y as usize);
+    let (right, bottom) = ((left + 1).min(width - 1), (top + 1).min(height - 1));
+    let (across, down) = (x - left as f32, y - top as f32);
+    let at = |x: usize, y: usize| image[(y * width + x) * 3 + channel];
+    let upper = at(left, top) * (1.0 - across) + at(right, top) * across;
+    let lower = at(left, bottom) * (1.0 - across) + at(right, bottom) * across;
+    upper * (1.0 - down) + lower * down
+}
+
+// Parses a comma-separated list of effects, or "all" or "none".
+pub fn parse_effects(value: &str) -> Option<CrtEffects> {
+    let mut effects = CrtEffects::default();
+    for name in value.split(',') {
+        match name {
+            "scanlines" => effects.scanlines = true,
+            "gaps" => effects.pixel_gaps = true,
+            "bloom" => effects.bloom = true,
+            "curvature" => effects.curvature = true,
+            "all" => {
+                effects = CrtEffects {
+                    scanlines: true,
+                    pixel_gaps: true,
+                    bloom: true,
+                    curvature: true,
+                };
+            }
+            "none" => {}
+            _ => return None,
+        }
+    }
+    Some(effects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_effects, render, CrtEffects};
+
+    // A 2x2 image with only the top left pixel lit.
+    const IMAGE: [u8; 12] = [200, 200, 200, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    fn render_with(effects: CrtEffects) -> Vec<u8> {
+        let mut out = Vec::new();
+        render(effects, &IMAGE, 2, 2, 4, &mut out);
+        assert_eq!(8 * 8 * 3, out.len());
+        out
+    }
+
+    // Red channel of a pixel in the 8x8 output.
+    fn red(out: &[u8], x: usize, y: usize) -> u8 {
+        out[(y * 8 + x) * 3]
+    }
+
+    #[test]
+    fn test_no_effects_just_scales() {
+        let out = render_with(CrtEffects::default());
+        assert_eq!(200, red(&out, 0, 0));
+        assert_eq!(200, red(&out, 3, 3));
+        assert_eq!(0, red(&out, 4, 0));
+    }
+
+    #[test]
+    fn test_scanlines_darken_the_lower_half_of_each_row() {
+        let out = render_with(CrtEffects { scanlines: true, ..CrtEffects::default() });
+        assert_eq!(200, red(&out, 0, 1));
+        assert_eq!(110, red(&out, 0, 2));
+    }
+
+    #[test]
+    fn test_pixel_gaps_darken_the_edges_of_each_pixel() {
+        let out = render_with(CrtEffects { pixel_gaps: true, ..CrtEffects::default() });
+        assert_eq!(200, red(&out, 2, 2));
+        assert_eq!(70, red(&out, 3, 2));
+        assert_eq!(70, red(&out, 2, 3));
+    }
+
+    #[test]
+    fn test_bloom_spills_into_neighbours() {
+        let out = render_with(CrtEffects { bloom: true, ..CrtEffects::default() });
+        assert!(red(&out, 4, 0) > 0);
+        assert!(red(&out, 4, 0) > red(&out, 7, 7));
+        assert!(red(&out, 0, 0) >= 200);
+    }
+
+    #[test]
+    fn test_curvature_cuts_off_the_corners() {
+        let image = [255u8; 12];
+        let mut out = Vec::new();
+        render(CrtEffects { curvature: true, ..CrtEffects::default() }, &image, 2, 2, 8, &mut out);
+        assert_eq!(0, out[0]);
+        assert_eq!(255, out[(8 * 16 + 8) * 3]);
+    }
+
+    #[test]
+    fn test_parse_effects() {
+        assert_eq!(Some(CrtEffects { scanlines: true, bloom: true, ..CrtEffects::default() }),
+                   parse_effects("scanlines,bloom"));
+        assert!(parse_effects("all").unwrap().curvature);
+        assert_eq!(Some(CrtEffects::default()), parse_effects("none"));
+        assert_eq!(None, parse_effects("scanlines,wobble"));
+    }
+}
@@ -0,0 +1,897 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+
+use chip8_emu::Chip8;
+
+use rand::Rng;
+use serde_json;
+use serde_json::Value;
+
+use breakpoint::{Breakpoint, BreakpointAction};
+use debugger::{DebugEvent, Debugger};
+use disassembler;
+use expression;
+use expression::Expression;
+use symbols;
+use symbols::SymbolMap;
+
+// A server for the Debug Adapter Protocol, so that ROMs can be debugged from
+// any editor that speaks it. Like the GDB stub, it listens on a local TCP port
+// and is polled once a frame. Editors connect to it as a debug server and
+// launch the ROM with its symbol map, which lets breakpoints be set on source
+// lines:
+//
+//   "request": "launch", "program": "game.ch8", "symbols": "game.sym"
+//
+// Attaching instead debugs whatever's already running. Breakpoints can have
+// conditions, hit counts and log messages, in the expression language, which
+// also answers the editor's watches.
+
+const THREAD_ID: u32 = 1;
+// The scopes' variable references.
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const MEMORY: u64 = 3;
+// Memory is shown this many bytes to a variable.
+const MEMORY_ROW: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RunMode {
+    Stopped,
+    Running,
+    // Running until the stack's no deeper than this, to step over a call or
+    // out of a subroutine.
+    Returning(u8),
+}
+
+pub struct DapServer {
+    listener: TcpListener,
+    connection: Option<TcpStream>,
+    // Bytes received but not yet made sense of.
+    input: Vec<u8>,
+    sequence: u64,
+    symbols: SymbolMap,
+    // The editor sets breakpoints a file at a time.
+    source_breakpoints: BTreeMap<PathBuf, Vec<(u16, Breakpoint)>>,
+    instruction_breakpoints: Vec<(u16, Breakpoint)>,
+    // Every address with a breakpoint, to check quickly before looking at
+    // what they are.
+    breakpoints: BTreeSet<u16>,
+    mode: RunMode,
+    // Where the machine was last resumed from, so that a breakpoint there
+    // doesn't stop it again straight away.
+    resumed_at: Option<u16>,
+    // The launch request, until the ROM's been loaded.
+    pending_launch: Option<Value>,
+    // The machine only starts once there's a ROM and the editor has set its
+    // breakpoints.
+    is_launched: bool,
+    is_configured: bool,
+    stops_on_entry: bool,
+}
+
+impl DapServer {
+    // The machine starts out stopped, waiting for an editor to attach and
+    // tell it to go.
+    pub fn listen(port: u16) -> io::Result<DapServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(DapServer {
+            listener,
+            connection: None,
+            input: Vec::new(),
+            sequence: 0,
+            symbols: SymbolMap::default(),
+            source_breakpoints: BTreeMap::new(),
+            instruction_breakpoints: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            mode: RunMode::Stopped,
+            resumed_at: None,
+            pending_launch: None,
+            is_launched: false,
+            is_configured: false,
+            stops_on_entry: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.mode == RunMode::Stopped
+    }
+
+    pub fn poll<R: Rng>(&mut self, chip8: &mut Chip8<R>, events: &mut Vec<DebugEvent>) {
+        if self.connection.is_none() {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    if let Err(error) = stream.set_nonblocking(true) {
+                        events.push(DebugEvent::Message(format!("Couldn't talk to the debugger: {}", error)));
+                        return;
+                    }
+                    let _ = stream.set_nodelay(true);
+                    self.disconnect();
+                    self.connection = Some(stream);
+                    self.mode = RunMode::Stopped;
+                    events.push(DebugEvent::Message(format!("Debugger attached from {}", address)));
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                Err(error) => {
+                    events.push(DebugEvent::Message(format!("Couldn't accept a debugger: {}", error)));
+                    return;
+                }
+            }
+        }
+
+        let mut buffer = [0; 4096];
+        loop {
+            let result = match self.connection {
+                Some(ref mut stream) => stream.read(&mut buffer),
+                None => return,
+            };
+            match result {
+                Ok(0) => {
+                    self.disconnect();
+                    break;
+                }
+                Ok(count) => self.input.extend_from_slice(&buffer[..count]),
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.disconnect();
+                    break;
+                }
+            }
+        }
+
+        while self.connection.is_some() {
+            match take_message(&mut self.input) {
+                Some(Ok(message)) => {
+                    if message["type"] == "request" {
+                        self.handle_request(&message, chip8, events);
+                    }
+                }
+                Some(Err(error)) => {
+                    events.push(DebugEvent::Message(format!("Couldn't read a message from the debugger: {}", error)));
+                }
+                None => return,
+            }
+        }
+        events.push(DebugEvent::Message(String::from("Debugger detached")));
+    }
+
+    // Called once the ROM the editor asked for has been loaded, or hasn't.
+    pub fn launched(&mut self, result: Result<(), String>) {
+        let request = match self.pending_launch.take() {
+            Some(request) => request,
+            None => return,
+        };
+        let is_launched = result.is_ok();
+        self.respond(&request, result.map(|_| Value::Null));
+        if is_launched {
+            self.is_launched = true;
+            self.start();
+        }
+    }
+
+    pub fn execute_for_frame<R: Rng>(&mut self, chip8: &mut Chip8<R>, cycles: u32) -> bool {
+        if self.mode == RunMode::Stopped {
+            return false;
+        }
+        for _ in 0..cycles {
+            let pc = chip8.cpu_state().pc;
+            let is_resuming = self.resumed_at.take() == Some(pc);
+            if !is_resuming && self.breakpoints.contains(&pc) && self.hit_breakpoints(chip8, pc) {
+                self.stop("breakpoint");
+                return true;
+            }
+            chip8.execute_next_opcode();
+            if let RunMode::Returning(depth) = self.mode {
+                if chip8.cpu_state().sp <= depth {
+                    self.stop("step");
+                    return false;
+                }
+            }
+        }
+        chip8.update_timers();
+        false
+    }
+
+    fn handle_request<R: Rng>(&mut self, request: &Value, chip8: &mut Chip8<R>,
+                                      events: &mut Vec<DebugEvent>) {
+        let command = request["command"].as_str().unwrap_or("");
+        let arguments = &request["arguments"];
+        // Anything that stops the machine says so after the response.
+        let mut stop_reason = None;
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsHitConditionalBreakpoints": true,
+                "supportsLogPoints": true,
+                "supportsEvaluateForHovers": true,
+            })),
+            "launch" => match arguments["program"].as_str() {
+                Some(program) => match self.load_symbols(arguments) {
+                    Ok(()) => {
+                        self.pending_launch = Some(request.clone());
+                        events.push(DebugEvent::Launch(PathBuf::from(program)));
+                        return;
+                    }
+                    Err(error) => Err(error),
+                },
+                None => Err(String::from("No program to launch")),
+            },
+            "attach" => self.load_symbols(arguments).map(|_| {
+                self.is_launched = true;
+                Value::Null
+            }),
+            "configurationDone" => {
+                self.is_configured = true;
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace(arguments, chip8)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "presentationHint": "registers", "variablesReference": REGISTERS,
+                  "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY, "expensive": true },
+            ] })),
+            "variables" => variables(arguments, chip8),
+            "continue" => {
+                self.resume(chip8, RunMode::Running);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            // Stepping over a call runs until it returns.
+            "next" => {
+                let state = chip8.cpu_state();
+                let memory = chip8.memory();
+                if memory.get(state.pc as usize).is_some_and(|&byte| byte >> 4 == 0x2) {
+                    self.resume(chip8, RunMode::Returning(state.sp));
+                } else {
+                    chip8.execute_next_opcode();
+                    stop_reason = Some("step");
+                }
+                Ok(Value::Null)
+            }
+            // With nowhere to return to, stepping out steps once.
+            "stepIn" | "stepOut" => {
+                let depth = chip8.cpu_state().sp;
+                if command == "stepOut" && depth > 0 {
+                    self.resume(chip8, RunMode::Returning(depth - 1));
+                } else {
+                    chip8.execute_next_opcode();
+                    stop_reason = Some("step");
+                }
+                Ok(Value::Null)
+            }
+            "pause" => {
+                stop_reason = Some("pause");
+                Ok(Value::Null)
+            }
+            "disassemble" => self.disassemble(arguments, chip8),
+            "evaluate" => evaluate(arguments, chip8),
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Value::Null));
+                self.disconnect();
+                return;
+            }
+            _ => Err(format!("{} isn't supported", command)),
+        };
+        self.respond(request, result);
+        match command {
+            "initialize" => self.send_event("initialized", Value::Null),
+            "attach" | "configurationDone" => self.start(),
+            _ => {}
+        }
+        if let Some(reason) = stop_reason {
+            self.stop(reason);
+        }
+    }
+
+    // Symbols are optional, for debugging without source.
+    fn load_symbols(&mut self, arguments: &Value) -> Result<(), String> {
+        self.stops_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.symbols = match arguments["symbols"].as_str() {
+            Some(path) => SymbolMap::load(&PathBuf::from(path))?,
+            None => SymbolMap::default(),
+        };
+        Ok(())
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["source"]["path"].as_str().map(PathBuf::from)
+            .ok_or_else(|| String::from("Breakpoints need a source file"))?;
+        let requested = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut set = Vec::new();
+        let mut breakpoints = Vec::new();
+        for requested in requested.iter() {
+            let line = requested["line"].as_u64().unwrap_or(0) as u32;
+            let breakpoint = match parse_breakpoint(requested) {
+                Ok(breakpoint) => breakpoint,
+                Err(message) => {
+                    breakpoints.push(json!({ "verified": false, "line": line, "message": message }));
+                    continue;
+                }
+            };
+            match self.symbols.address_of(&path, line) {
+                Some((address, line)) => {
+                    set.push((address, breakpoint));
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": format!("{:#05X}", address),
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "There's no code on or after this line",
+                })),
+            }
+        }
+        self.source_breakpoints.insert(path, set);
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    // From the disassembly view.
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let requested = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+        self.instruction_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for requested in requested.iter() {
+            let address = requested["instructionReference"].as_str().and_then(symbols::parse_address)
+                .map(|address| address as i64 + requested["offset"].as_i64().unwrap_or(0))
+                .filter(|&address| (0..0x1000).contains(&address))
+                .ok_or_else(|| String::from("Not an address in memory"));
+            match address.and_then(|address| Ok((address as u16, parse_breakpoint(requested)?))) {
+                Ok(breakpoint) => {
+                    self.instruction_breakpoints.push(breakpoint);
+                    breakpoints.push(json!({ "verified": true }));
+                }
+                Err(message) => breakpoints.push(json!({ "verified": false, "message": message })),
+            }
+        }
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.breakpoints = self.source_breakpoints.values().flatten()
+            .chain(self.instruction_breakpoints.iter())
+            .map(|&(address, _)| address)
+            .collect();
+    }
+
+    // Checks the breakpoints at this address, and logs whatever the
+    // logpoints have to say. Returns true if any of them stop the machine.
+    fn hit_breakpoints<R: Rng>(&mut self, chip8: &Chip8<R>, pc: u16) -> bool {
+        let state = chip8.cpu_state();
+        let memory = chip8.memory();
+        let mut is_stopping = false;
+        let mut messages = Vec::new();
+        let breakpoints = self.source_breakpoints.values_mut().flatten()
+            .chain(self.instruction_breakpoints.iter_mut())
+            .filter(|&&mut (address, _)| address == pc);
+        for &mut (_, ref mut breakpoint) in breakpoints {
+            match breakpoint.hit(&state, memory) {
+                BreakpointAction::Continue => {}
+                BreakpointAction::Stop => is_stopping = true,
+                BreakpointAction::Log(message) => messages.push(message),
+            }
+        }
+        for message in messages {
+            let mut output = json!({ "category": "console", "output": format!("{}\n", message) });
+            if let Some(location) = self.symbols.location(pc) {
+                output["source"] = self.source(location);
+                output["line"] = json!(location.line);
+            }
+            self.send_event("output", output);
+        }
+        is_stopping
+    }
+
+    // The innermost frame is where the machine is, and each one after it is
+    // where a subroutine was called from.
+    fn stack_trace<R: Rng>(&self, arguments: &Value, chip8: &Chip8<R>) -> Value {
+        let state = chip8.cpu_state();
+        let calls = state.stack[..state.sp as usize].iter().rev()
+            .map(|&return_address| return_address.wrapping_sub(2) & 0xFFF);
+        let addresses: Vec<u16> = Some(state.pc).into_iter().chain(calls).collect();
+        let start = arguments["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match arguments["levels"].as_u64() {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => addresses.len(),
+        };
+        let frames: Vec<Value> = addresses.iter().enumerate().skip(start).take(levels)
+            .map(|(id, &address)| self.stack_frame(id, address))
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": addresses.len() })
+    }
+
+    fn stack_frame(&self, id: usize, address: u16) -> Value {
+        let name = match self.symbols.enclosing_label(address) {
+            Some(label) => String::from(label),
+            None => format!("{:#05X}", address),
+        };
+        let mut frame = json!({
+            "id": id,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("{:#05X}", address),
+        });
+        if let Some(location) = self.symbols.location(address) {
+            frame["source"] = self.source(location);
+            frame["line"] = json!(location.line);
+            frame["column"] = json!(1);
+        }
+        frame
+    }
+
+    fn source(&self, location: &symbols::SourceLocation) -> Value {
+        let path = self.symbols.source_path(location);
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        json!({ "name": name, "path": path.to_string_lossy() })
+    }
+
+    fn disassemble<R: Rng>(&self, arguments: &Value, chip8: &Chip8<R>) -> Result<Value, String> {
+        let reference = arguments["memoryReference"].as_str().and_then(symbols::parse_address)
+            .ok_or_else(|| String::from("Not an address in memory"))?;
+        let start = reference as i64 + arguments["offset"].as_i64().unwrap_or(0)
+            + arguments["instructionOffset"].as_i64().unwrap_or(0) * 2;
+        let count = arguments["instructionCount"].as_i64().unwrap_or(0).max(0);
+        let memory = chip8.memory();
+        let instructions: Vec<Value> = (0..count).map(|index| {
+            let address = start + index * 2;
+            // The editor asks for a screenful either side, which can run off
+            // the ends of memory.
+            if address < 0 || address + 1 >= memory.len() as i64 {
+                return json!({
+                    "address": format!("{:#X}", address.rem_euclid(0x10000)),
+                    "instruction": "",
+                    "presentationHint": "invalid",
+                });
+            }
+            let address = address as usize;
+            let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+            let mut instruction = json!({
+                "address": format!("{:#05X}", address),
+                "instructionBytes": format!("{:02X} {:02X}", memory[address], memory[address + 1]),
+                "instruction": disassembler::disassemble(opcode),
+            });
+            if let Some(label) = self.symbols.label_at(address as u16) {
+                instruction["symbol"] = json!(label);
+            }
+            if let Some(location) = self.symbols.location(address as u16) {
+                instruction["location"] = self.source(location);
+                instruction["line"] = json!(location.line);
+            }
+            instruction
+        }).collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn start(&mut self) {
+        if !self.is_launched || !self.is_configured {
+            return;
+        }
+        if self.stops_on_entry {
+            self.stop("entry");
+        } else {
+            self.mode = RunMode::Running;
+            self.resumed_at = None;
+        }
+    }
+
+    fn resume<R: Rng>(&mut self, chip8: &Chip8<R>, mode: RunMode) {
+        self.resumed_at = Some(chip8.cpu_state().pc);
+        self.mode = mode;
+    }
+
+    fn stop(&mut self, reason: &str) {
+        self.mode = RunMode::Stopped;
+        self.send_event("stopped", json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }));
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message);
+    }
+
+    fn send(&mut self, mut message: Value) {
+        self.sequence += 1;
+        message["seq"] = json!(self.sequence);
+        let body = message.to_string();
+        let result = match self.connection {
+            Some(ref mut stream) => stream.write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes()),
+            None => return,
+        };
+        if result.is_err() {
+            self.disconnect();
+        }
+    }
+
+    // With no one watching, the machine carries on by itself.
+    fn disconnect(&mut self) {
+        self.connection = None;
+        self.input.clear();
+        self.sequence = 0;
+        self.source_breakpoints.clear();
+        self.instruction_breakpoints.clear();
+        self.breakpoints.clear();
+        self.mode = RunMode::Running;
+        self.resumed_at = None;
+        self.pending_launch = None;
+        self.is_launched = false;
+        self.is_configured = false;
+    }
+}
+
+impl<R: Rng> Debugger<R> for DapServer {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        DapServer::local_addr(self)
+    }
+
+    fn poll(&mut self, chip8: &mut Chip8<R>, events: &mut Vec<DebugEvent>) {
+        DapServer::poll(self, chip8, events)
+    }
+
+    fn launched(&mut self, result: Result<(), String>) {
+        DapServer::launched(self, result)
+    }
+
+    fn is_stopped(&self) -> bool {
+        DapServer::is_stopped(self)
+    }
+
+    fn execute_for_frame(&mut self, chip8: &mut Chip8<R>, cycles: u32) -> bool {
+        DapServer::execute_for_frame(self, chip8, cycles)
+    }
+}
+
+fn parse_breakpoint(requested: &Value) -> Result<Breakpoint, String> {
+    Breakpoint::new(requested["condition"].as_str(), requested["hitCondition"].as_str(),
+                    requested["logMessage"].as_str())
+}
+
+// Watches, hovers and the debug console all work out expressions.
+fn evaluate<R: Rng>(arguments: &Value, chip8: &Chip8<R>) -> Result<Value, String> {
+    let expression = Expression::parse(arguments["expression"].as_str().unwrap_or(""))?;
+    let value = expression.evaluate(&chip8.cpu_state(), chip8.memory());
+    Ok(json!({
+        "result": format!("{} ({})", value, expression::hex(value)),
+        "variablesReference": 0,
+    }))
+}
+
+fn variables<R: Rng>(arguments: &Value, chip8: &Chip8<R>) -> Result<Value, String> {
+    let state = chip8.cpu_state();
+    let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+    let variables: Vec<Value> = match arguments["variablesReference"].as_u64() {
+        Some(REGISTERS) => {
+            let mut registers: Vec<Value> = state.v.iter().enumerate()
+                .map(|(index, &value)| variable(format!("V{:X}", index), format!("{:#04X} ({})", value, value)))
+                .collect();
+            registers.push(variable(String::from("I"), format!("{:#05X}", state.i)));
+            registers.push(variable(String::from("PC"), format!("{:#05X}", state.pc)));
+            registers.push(variable(String::from("SP"), state.sp.to_string()));
+            registers
+        }
+        Some(TIMERS) => vec![
+            variable(String::from("delay"), state.delay_timer.to_string()),
+            variable(String::from("sound"), state.sound_timer.to_string()),
+        ],
+        Some(MEMORY) => chip8.memory().chunks(MEMORY_ROW).enumerate().map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            variable(format!("{:#05X}", row * MEMORY_ROW), hex.join(" "))
+        }).collect(),
+        _ => return Err(String::from("No such variables")),
+    };
+    Ok(json!({ "variables": variables }))
+}
+
+// Takes the next whole message off the front of the input. Each has a
+// Content-Length header and then that many bytes of JSON.
+fn take_message(input: &mut Vec<u8>) -> Option<Result<Value, String>> {
+    let header_end = input.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let header = String::from_utf8_lossy(&input[..header_end]).into_owned();
+    let length = header.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok());
+    let body_start = header_end + 4;
+    let length = match length {
+        Some(length) => length,
+        None => {
+            input.drain(..body_start);
+            return Some(Err(String::from("a message without a Content-Length")));
+        }
+    };
+    if input.len() < body_start + length {
+        return None;
+    }
+    let message: Vec<u8> = input.drain(..body_start + length).collect();
+    Some(serde_json::from_slice(&message[body_start..]).map_err(|error| error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use chip8_emu::Chip8;
+
+    use rand::XorShiftRng;
+    use serde_json::Value;
+
+    use debugger::DebugEvent;
+    use symbols::SymbolMap;
+
+    use super::{DapServer, take_message};
+
+    const SYMBOLS: &str = "0x200 main\n\
+                           0x200 main.8o:1\n\
+                           0x202 main.8o:2\n\
+                           0x204 main.8o:3\n\
+                           0x206 main.8o:4\n\
+                           0x208 add_seven\n\
+                           0x208 main.8o:6\n\
+                           0x20A main.8o:7\n";
+
+    // Plays the part of an editor.
+    struct Client {
+        stream: TcpStream,
+        received: Vec<u8>,
+        sequence: u64,
+        events: Vec<Value>,
+        debug_events: Vec<DebugEvent>,
+    }
+
+    impl Client {
+        fn connect(server: &mut DapServer, chip8: &mut Chip8<XorShiftRng>) -> Client {
+            let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+            stream.set_nonblocking(true).unwrap();
+            let mut client = Client {
+                stream,
+                received: Vec::new(),
+                sequence: 0,
+                events: Vec::new(),
+                debug_events: Vec::new(),
+            };
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while server.connection.is_none() {
+                assert!(Instant::now() < deadline, "the server never accepted");
+                client.poll(server, chip8);
+            }
+            client
+        }
+
+        fn send(&mut self, command: &str, arguments: Value) -> u64 {
+            self.sequence += 1;
+            let body = json!({
+                "seq": self.sequence,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            }).to_string();
+            self.stream.write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes()).unwrap();
+            self.sequence
+        }
+
+        fn request(&mut self, server: &mut DapServer, chip8: &mut Chip8<XorShiftRng>,
+                   command: &str, arguments: Value) -> Value {
+            let sequence = self.send(command, arguments);
+            self.response(server, chip8, sequence)
+        }
+
+        // Polls the server until it's answered, keeping any events that
+        // come before.
+        fn response(&mut self, server: &mut DapServer, chip8: &mut Chip8<XorShiftRng>, sequence: u64) -> Value {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                while let Some(message) = take_message(&mut self.received) {
+                    let message = message.unwrap();
+                    if message["type"] == "event" {
+                        self.events.push(message);
+                    } else if message["request_seq"] == sequence {
+                        return message;
+                    }
+                }
+                assert!(Instant::now() < deadline, "no response from the server");
+                self.poll(server, chip8);
+            }
+        }
+
+        fn poll(&mut self, server: &mut DapServer, chip8: &mut Chip8<XorShiftRng>) {
+            server.poll(chip8, &mut self.debug_events);
+            let mut buffer = [0; 4096];
+            while let Ok(count) = self.stream.read(&mut buffer) {
+                if count == 0 {
+                    break;
+                }
+                self.received.extend_from_slice(&buffer[..count]);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Reads whatever's arrived, and returns the reason for the last stop.
+        fn stop_reason(&mut self, server: &mut DapServer, chip8: &mut Chip8<XorShiftRng>) -> String {
+            let sequence = self.send("threads", Value::Null);
+            self.response(server, chip8, sequence);
+            let stop = self.events.iter().rev().find(|event| event["event"] == "stopped").cloned();
+            self.events.clear();
+            stop.map(|event| event["body"]["reason"].as_str().unwrap().to_string()).unwrap_or_default()
+        }
+    }
+
+    fn machine() -> Chip8<XorShiftRng> {
+        let mut chip8 = Chip8::new_and_init_with_rng(XorShiftRng::new_unseeded());
+        chip8.load_rom_at(&[0x60, 0x05, 0x22, 0x08, 0x70, 0x01, 0x12, 0x04, 0x61, 0x07, 0x00, 0xEE], 0x200);
+        chip8
+    }
+
+    #[test]
+    fn test_launch_breakpoints_and_stepping() {
+        let mut server = DapServer::listen(0).unwrap();
+        let mut chip8 = machine();
+        let mut client = Client::connect(&mut server, &mut chip8);
+
+        let response = client.request(&mut server, &mut chip8, "initialize", json!({ "adapterID": "chip8" }));
+        assert_eq!(true, response["body"]["supportsDisassembleRequest"]);
+
+        // The launch is answered once the runner's loaded the ROM.
+        let launch = client.send("launch", json!({ "program": "game.ch8" }));
+        let program = loop {
+            let program = client.debug_events.iter().find_map(|event| match *event {
+                DebugEvent::Launch(ref path) => Some(path.clone()),
+                DebugEvent::Message(_) => None,
+            });
+            match program {
+                Some(program) => break program,
+                None => client.poll(&mut server, &mut chip8),
+            }
+        };
+        assert_eq!(PathBuf::from("game.ch8"), program);
+        server.symbols = SymbolMap::parse(SYMBOLS).unwrap();
+        server.launched(Ok(()));
+        assert_eq!(true, client.response(&mut server, &mut chip8, launch)["success"]);
+        assert!(client.events.iter().any(|event| event["event"] == "initialized"));
+
+        // There's no code on line 5, so that breakpoint moves to line 6.
+        let response = client.request(&mut server, &mut chip8, "setBreakpoints", json!({
+            "source": { "path": "/home/me/game/main.8o" },
+            "breakpoints": [{ "line": 5 }],
+        }));
+        assert_eq!(json!([{ "verified": true, "line": 6, "instructionReference": "0x208" }]),
+                   response["body"]["breakpoints"]);
+        assert!(server.is_stopped());
+        client.request(&mut server, &mut chip8, "configurationDone", json!({}));
+        assert!(!server.is_stopped());
+        assert!(server.execute_for_frame(&mut chip8, 10));
+        assert_eq!("breakpoint", client.stop_reason(&mut server, &mut chip8));
+
+        let response = client.request(&mut server, &mut chip8, "stackTrace", json!({ "threadId": 1 }));
+        let frames = &response["body"]["stackFrames"];
+        assert_eq!(2, response["body"]["totalFrames"]);
+        assert_eq!("add_seven", frames[0]["name"]);
+        assert_eq!(6, frames[0]["line"]);
+        assert_eq!("main.8o", frames[1]["source"]["path"]);
+        assert_eq!("main", frames[1]["name"]);
+        assert_eq!("0x202", frames[1]["instructionPointerReference"]);
+
+        let response = client.request(&mut server, &mut chip8, "variables", json!({ "variablesReference": 1 }));
+        assert_eq!(json!({ "name": "V0", "value": "0x05 (5)", "variablesReference": 0 }),
+                   response["body"]["variables"][0]);
+
+        // Out of the subroutine, then over the add.
+        client.request(&mut server, &mut chip8, "stepOut", json!({ "threadId": 1 }));
+        assert!(!server.execute_for_frame(&mut chip8, 10));
+        assert_eq!("step", client.stop_reason(&mut server, &mut chip8));
+        assert_eq!(0x204, chip8.cpu_state().pc);
+        assert_eq!(7, chip8.cpu_state().v[1]);
+        client.request(&mut server, &mut chip8, "next", json!({ "threadId": 1 }));
+        assert_eq!("step", client.stop_reason(&mut server, &mut chip8));
+        assert_eq!(0x206, chip8.cpu_state().pc);
+
+        let response = client.request(&mut server, &mut chip8, "disassemble", json!({
+            "memoryReference": "0x200",
+            "instructionOffset": -1,
+            "instructionCount": 3,
+        }));
+        let instructions = &response["body"]["instructions"];
+        assert_eq!("0x1FE", instructions[0]["address"]);
+        assert_eq!("main", instructions[1]["symbol"]);
+        assert_eq!(1, instructions[1]["line"]);
+        assert_eq!("CALL 0x208", instructions[2]["instruction"]);
+
+        client.request(&mut server, &mut chip8, "disconnect", json!({}));
+        assert!(!server.is_stopped());
+    }
+
+    #[test]
+    fn test_conditions_logpoints_and_watches() {
+        let mut server = DapServer::listen(0).unwrap();
+        let mut chip8 = machine();
+        let mut client = Client::connect(&mut server, &mut chip8);
+        client.request(&mut server, &mut chip8, "initialize", json!({ "adapterID": "chip8" }));
+        assert_eq!(true, client.request(&mut server, &mut chip8, "attach", json!({}))["success"]);
+        server.symbols = SymbolMap::parse(SYMBOLS).unwrap();
+
+        let response = client.request(&mut server, &mut chip8, "setBreakpoints", json!({
+            "source": { "path": "main.8o" },
+            "breakpoints": [
+                { "line": 3, "logMessage": "V0 is {V0}" },
+                { "line": 4, "condition": "V0 == 8 && [PC] == 0x12" },
+                { "line": 1, "condition": "V0 ==" },
+            ],
+        }));
+        let breakpoints = &response["body"]["breakpoints"];
+        assert_eq!(true, breakpoints[1]["verified"]);
+        assert_eq!(false, breakpoints[2]["verified"]);
+        assert!(breakpoints[2]["message"].as_str().unwrap().contains("condition"));
+
+        client.request(&mut server, &mut chip8, "configurationDone", json!({}));
+        // The logpoint logs each time round the loop, without stopping.
+        assert!(server.execute_for_frame(&mut chip8, 20));
+        client.request(&mut server, &mut chip8, "threads", json!({}));
+        let output: Vec<&str> = client.events.iter().filter(|event| event["event"] == "output")
+            .map(|event| event["body"]["output"].as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["V0 is 5\n", "V0 is 6\n", "V0 is 7\n"], output);
+        assert_eq!("breakpoint", client.stop_reason(&mut server, &mut chip8));
+        assert_eq!(0x206, chip8.cpu_state().pc);
+
+        let response = client.request(&mut server, &mut chip8, "evaluate", json!({ "expression": "V0 * 2" }));
+        assert_eq!("16 (0x10)", response["body"]["result"]);
+        let response = client.request(&mut server, &mut chip8, "evaluate", json!({ "expression": "V0 +" }));
+        assert_eq!(false, response["success"]);
+    }
+
+    #[test]
+    fn test_take_message_waits_for_the_whole_body() {
+        let mut input = b"Content-Length: 10\r\n\r\n{\"seq\":".to_vec();
+        assert!(take_message(&mut input).is_none());
+        input.extend_from_slice(b" 1}Content-Length: 2\r\n\r\n{}Content");
+        assert_eq!(json!({ "seq": 1 }), take_message(&mut input).unwrap().unwrap());
+        assert_eq!(json!({}), take_message(&mut input).unwrap().unwrap());
+        assert!(take_message(&mut input).is_none());
+        assert_eq!(b"Content", &input[..]);
+    }
+}
@@ -0,0 +1,36 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use chip8_emu::Chip8;
+
+use rand::Rng;
+
+// Something outside the emulator that takes charge of the machine, like GDB
+// or an editor. The runner polls it once a frame, and leaves it to run the
+// frame's instructions so that it can stop them where it likes.
+
+pub enum DebugEvent {
+    // Something to show the user.
+    Message(String),
+    // The debugger wants this ROM loaded. The runner tells it how that went
+    // with `launched`.
+    Launch(PathBuf),
+}
+
+pub trait Debugger<R: Rng> {
+    // Where it's listening.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    // Answers whatever the debugger's sent.
+    fn poll(&mut self, chip8: &mut Chip8<R>, events: &mut Vec<DebugEvent>);
+
+    fn launched(&mut self, _result: Result<(), String>) {}
+
+    fn is_stopped(&self) -> bool;
+
+    // Runs a frame's worth of instructions unless the debugger has the
+    // machine stopped, stopping early at a breakpoint. Returns true if it
+    // did.
+    fn execute_for_frame(&mut self, chip8: &mut Chip8<R>, cycles: u32) -> bool;
+}
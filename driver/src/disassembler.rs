@@ -0,0 +1,85 @@
+// Turns opcodes back into assembly, in the mnemonics of Cowgod's technical
+// reference, for the debugger's disassembly view.
+
+pub fn disassemble(opcode: u16) -> String {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let kk = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => String::from("CLS"),
+            0x00EE => String::from("RET"),
+            _ => format!("SYS {:#05X}", nnn),
+        },
+        0x1 => format!("JP {:#05X}", nnn),
+        0x2 => format!("CALL {:#05X}", nnn),
+        0x3 => format!("SE V{:X}, {:#04X}", x, kk),
+        0x4 => format!("SNE V{:X}, {:#04X}", x, kk),
+        0x5 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6 => format!("LD V{:X}, {:#04X}", x, kk),
+        0x7 => format!("ADD V{:X}, {:#04X}", x, kk),
+        0x8 => {
+            let mnemonic = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return data(opcode),
+            };
+            format!("{} V{:X}, V{:X}", mnemonic, x, y)
+        }
+        0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA => format!("LD I, {:#05X}", nnn),
+        0xB => format!("JP V0, {:#05X}", nnn),
+        0xC => format!("RND V{:X}, {:#04X}", x, kk),
+        0xD => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE => match kk {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => data(opcode),
+        },
+        0xF => match kk {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => data(opcode),
+        },
+        _ => data(opcode),
+    }
+}
+
+// Anything that isn't an instruction, such as sprite data.
+fn data(opcode: u16) -> String {
+    format!("DW {:#06X}", opcode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!("CLS", disassemble(0x00E0));
+        assert_eq!("CALL 0x2A0", disassemble(0x22A0));
+        assert_eq!("SE V3, 0x05", disassemble(0x3305));
+        assert_eq!("SUBN VA, VB", disassemble(0x8AB7));
+        assert_eq!("LD I, 0x300", disassemble(0xA300));
+        assert_eq!("DRW V0, V1, 5", disassemble(0xD015));
+        assert_eq!("LD V4, [I]", disassemble(0xF465));
+        assert_eq!("DW 0x8008", disassemble(0x8008));
+        assert_eq!("DW 0xFFFF", disassemble(0xFFFF));
+    }
+}
@@ -0,0 +1,156 @@
+// How the emulated display is fitted into a window: scaling, letterboxing and
+// rotation. None of this depends on a particular backend, so the maths lives
+// here and the frontends just apply the result.
+
+use crt::CrtEffects;
+
+const MAX_SCALE: u32 = 50;
+
+// Rotations are clockwise, for ROMs made for screens turned on their side or
+// upside down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplaySettings {
+    // Size of each emulated pixel in the initial window.
+    pub scale: u32,
+    // Only scale by whole numbers, so that every pixel comes out the same
+    // size. Whatever's left over becomes border.
+    pub integer_scaling: bool,
+    pub rotation: Rotation,
+    pub fullscreen: bool,
+    pub crt: CrtEffects,
+    // Columns and rows of displays, when comparing several machines.
+    pub grid: (usize, usize),
+}
+
+impl Default for DisplaySettings {
+    fn default() -> DisplaySettings {
+        DisplaySettings {
+            scale: 10,
+            integer_scaling: false,
+            rotation: Rotation::None,
+            fullscreen: false,
+            crt: CrtEffects::default(),
+            grid: (1, 1),
+        }
+    }
+}
+
+// The part of the window the display is drawn into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rotation {
+    // Size of a width x height display once rotated.
+    pub fn rotated_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::None | Rotation::Half => (width, height),
+            Rotation::Quarter | Rotation::ThreeQuarters => (height, width),
+        }
+    }
+
+    // Where pixel (x, y) of a width x height display ends up once rotated.
+    pub fn rotate_position(self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::None => (x, y),
+            Rotation::Quarter => (height - 1 - y, x),
+            Rotation::Half => (width - 1 - x, height - 1 - y),
+            Rotation::ThreeQuarters => (y, width - 1 - x),
+        }
+    }
+}
+
+// Scales a display_width x display_height picture up to fit the output while
+// keeping its aspect ratio, and centres it. If the output is smaller than the
+// display, integer scaling still uses a scale of 1 and the edges get cut off.
+pub fn fit(output_width: u32, output_height: u32,
+           display_width: u32, display_height: u32,
+           integer_scaling: bool) -> Viewport {
+    let scale = (output_width as f32 / display_width as f32)
+        .min(output_height as f32 / display_height as f32);
+    let (width, height) = if integer_scaling {
+        let scale = (scale.floor() as u32).max(1);
+        (display_width * scale, display_height * scale)
+    } else {
+        ((display_width as f32 * scale).round() as u32,
+         (display_height as f32 * scale).round() as u32)
+    };
+
+    Viewport {
+        x: (output_width as i32 - width as i32) / 2,
+        y: (output_height as i32 - height as i32) / 2,
+        width,
+        height,
+    }
+}
+
+pub fn parse_rotation(value: &str) -> Option<Rotation> {
+    match value {
+        "0" => Some(Rotation::None),
+        "90" => Some(Rotation::Quarter),
+        "180" => Some(Rotation::Half),
+        "270" => Some(Rotation::ThreeQuarters),
+        _ => None,
+    }
+}
+
+pub fn parse_scale(value: &str) -> Option<u32> {
+    value.parse::<u32>().ok().filter(|&scale| (1..=MAX_SCALE).contains(&scale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fit, parse_rotation, parse_scale, Rotation, Viewport};
+
+    #[test]
+    fn test_fit_letterboxes_to_aspect_ratio() {
+        // Too wide: bars on the left and right.
+        assert_eq!(Viewport { x: 80, y: 0, width: 640, height: 320 }, fit(800, 320, 64, 32, false));
+        // Too tall: bars above and below.
+        assert_eq!(Viewport { x: 0, y: 140, width: 640, height: 320 }, fit(640, 600, 64, 32, false));
+        // Non-integer scales fill as much as they can.
+        assert_eq!(Viewport { x: 0, y: 10, width: 700, height: 350 }, fit(700, 370, 64, 32, false));
+    }
+
+    #[test]
+    fn test_fit_with_integer_scaling() {
+        assert_eq!(Viewport { x: 30, y: 25, width: 640, height: 320 }, fit(700, 370, 64, 32, true));
+        // Never scales below 1, even if that means cropping.
+        assert_eq!(Viewport { x: -2, y: -1, width: 64, height: 32 }, fit(60, 30, 64, 32, true));
+    }
+
+    #[test]
+    fn test_rotation() {
+        assert_eq!((32, 64), Rotation::Quarter.rotated_size(64, 32));
+        assert_eq!((64, 32), Rotation::Half.rotated_size(64, 32));
+        // The top left corner moves to the top right, bottom right and bottom
+        // left as we rotate clockwise.
+        assert_eq!((0, 0), Rotation::None.rotate_position(0, 0, 64, 32));
+        assert_eq!((31, 0), Rotation::Quarter.rotate_position(0, 0, 64, 32));
+        assert_eq!((63, 31), Rotation::Half.rotate_position(0, 0, 64, 32));
+        assert_eq!((0, 63), Rotation::ThreeQuarters.rotate_position(0, 0, 64, 32));
+        // The top right corner goes to the bottom right when rotated by 90.
+        assert_eq!((31, 63), Rotation::Quarter.rotate_position(63, 0, 64, 32));
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(Some(Rotation::ThreeQuarters), parse_rotation("270"));
+        assert_eq!(None, parse_rotation("45"));
+        assert_eq!(Some(4), parse_scale("4"));
+        assert_eq!(None, parse_scale("0"));
+        assert_eq!(None, parse_scale("x"));
+    }
+}
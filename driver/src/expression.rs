@@ -0,0 +1,437 @@
+use chip8_emu::CpuState;
+
+// A small expression language over the machine's state, for breakpoint
+// conditions, log messages and the debugger's watches:
+//
+//   V3 == 5 && I >= 0x300 && [I] != 0
+//
+// The names are V0 to VF, I, PC, SP, delay (or DT) and sound (or ST), in any
+// case, and [x] is the byte of memory at x. The operators are Rust's, with the
+// same precedence, and comparisons and logic give 1 or 0. Everything's worked
+// out in 64 bits, and dividing by zero gives zero. Expressions are parsed
+// once, so they're cheap enough to check on every instruction.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum UnaryOp {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// Longest first, so that "<=" isn't read as "<" and "=".
+const SYMBOLS: [&str; 24] = ["||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+                             "<", ">", "|", "^", "&", "+", "-", "*", "/", "%",
+                             "!", "~", "(", ")", "[", "]"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let root = parser.binary(0)?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(Expression { root }),
+            Some(token) => Err(format!("didn't expect {}", describe(token))),
+        }
+    }
+
+    pub fn evaluate(&self, state: &CpuState, memory: &[u8]) -> i64 {
+        evaluate(&self.root, state, memory)
+    }
+}
+
+// Text with expressions in braces, for log messages. "{x:x}" shows x in hex,
+// and "{{" and "}}" are literal braces:
+//
+//   "Score {[I]}, lives {V4} at {PC:x}"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Decimal(Expression),
+    Hex(Expression),
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut inside = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => inside.push(c),
+                            None => return Err(String::from("a { without a }")),
+                        }
+                    }
+                    let (expression, format) = match inside.rsplit_once(':') {
+                        Some((expression, format)) => (expression, format.trim()),
+                        None => (inside.as_str(), ""),
+                    };
+                    let expression = Expression::parse(expression)?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(literal.split_off(0)));
+                    }
+                    parts.push(match format {
+                        "" | "d" => Part::Decimal(expression),
+                        "x" => Part::Hex(expression),
+                        _ => return Err(format!("{{...:{}}} isn't a format; there's d and x", format)),
+                    });
+                }
+                '}' => return Err(String::from("a } without a {")),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    pub fn format(&self, state: &CpuState, memory: &[u8]) -> String {
+        let mut text = String::new();
+        for part in self.parts.iter() {
+            match *part {
+                Part::Text(ref literal) => text.push_str(literal),
+                Part::Decimal(ref expression) => text.push_str(&expression.evaluate(state, memory).to_string()),
+                Part::Hex(ref expression) => text.push_str(&hex(expression.evaluate(state, memory))),
+            }
+        }
+        text
+    }
+}
+
+pub fn hex(value: i64) -> String {
+    if value < 0 {
+        format!("-{:#X}", value.unsigned_abs())
+    } else {
+        format!("{:#X}", value)
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            let word = &rest[..end];
+            tokens.push(if c.is_ascii_digit() {
+                Token::Number(parse_number(word).ok_or_else(|| format!("{} isn't a number", word))?)
+            } else {
+                Token::Name(word.to_ascii_lowercase())
+            });
+            rest = &rest[end..];
+        } else {
+            let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol))
+                .ok_or_else(|| format!("didn't expect {}", c))?;
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    let lower = word.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse::<i64>().ok()
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match *token {
+        Token::Number(number) => number.to_string(),
+        Token::Name(ref name) => name.clone(),
+        Token::Symbol(symbol) => String::from(symbol),
+    }
+}
+
+// Each operator and how tightly it binds.
+fn binary_op(symbol: &str) -> Option<(BinaryOp, u8)> {
+    let op = match symbol {
+        "||" => (BinaryOp::Or, 1),
+        "&&" => (BinaryOp::And, 2),
+        "==" => (BinaryOp::Equal, 3),
+        "!=" => (BinaryOp::NotEqual, 3),
+        "<" => (BinaryOp::Less, 3),
+        "<=" => (BinaryOp::LessOrEqual, 3),
+        ">" => (BinaryOp::Greater, 3),
+        ">=" => (BinaryOp::GreaterOrEqual, 3),
+        "|" => (BinaryOp::BitOr, 4),
+        "^" => (BinaryOp::BitXor, 5),
+        "&" => (BinaryOp::BitAnd, 6),
+        "<<" => (BinaryOp::ShiftLeft, 7),
+        ">>" => (BinaryOp::ShiftRight, 7),
+        "+" => (BinaryOp::Add, 8),
+        "-" => (BinaryOp::Subtract, 8),
+        "*" => (BinaryOp::Multiply, 9),
+        "/" => (BinaryOp::Divide, 9),
+        "%" => (BinaryOp::Remainder, 9),
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn register(name: &str) -> Option<Register> {
+    let register = match name {
+        "i" => Register::I,
+        "pc" => Register::Pc,
+        "sp" => Register::Sp,
+        "delay" | "dt" => Register::Delay,
+        "sound" | "st" => Register::Sound,
+        _ => {
+            let index = name.strip_prefix('v').filter(|index| index.len() == 1)?;
+            Register::V(usize::from_str_radix(index, 16).ok()?)
+        }
+    };
+    Some(register)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // Operators that bind at least this tightly.
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        while let Some(&Token::Symbol(symbol)) = self.tokens.get(self.position) {
+            let (op, precedence) = match binary_op(symbol) {
+                Some((op, precedence)) if precedence >= min_precedence => (op, precedence),
+                _ => break,
+            };
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let op = match self.tokens.get(self.position) {
+            Some(&Token::Symbol("!")) => UnaryOp::Not,
+            Some(&Token::Symbol("-")) => UnaryOp::Negate,
+            Some(&Token::Symbol("~")) => UnaryOp::Complement,
+            _ => return self.primary(),
+        };
+        self.position += 1;
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Node::Number(number)),
+            Some(Token::Name(name)) => register(&name).map(Node::Register)
+                .ok_or_else(|| format!("{} isn't a register; there's V0 to VF, I, PC, SP, delay and sound", name)),
+            Some(Token::Symbol("[")) => {
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(address)))
+            }
+            Some(Token::Symbol("(")) => {
+                let inner = self.binary(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(token) => Err(format!("didn't expect {}", describe(&token))),
+            None => Err(String::from("the expression ends too soon")),
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(found)) if found == symbol => Ok(()),
+            _ => Err(format!("expected {}", symbol)),
+        }
+    }
+}
+
+fn evaluate(node: &Node, state: &CpuState, memory: &[u8]) -> i64 {
+    match *node {
+        Node::Number(number) => number,
+        Node::Register(register) => match register {
+            Register::V(index) => state.v[index] as i64,
+            Register::I => state.i as i64,
+            Register::Pc => state.pc as i64,
+            Register::Sp => state.sp as i64,
+            Register::Delay => state.delay_timer as i64,
+            Register::Sound => state.sound_timer as i64,
+        },
+        Node::Memory(ref address) => {
+            let address = evaluate(address, state, memory) & 0xFFF;
+            memory.get(address as usize).cloned().unwrap_or(0) as i64
+        }
+        Node::Unary(op, ref operand) => {
+            let value = evaluate(operand, state, memory);
+            match op {
+                UnaryOp::Not => (value == 0) as i64,
+                UnaryOp::Negate => value.wrapping_neg(),
+                UnaryOp::Complement => !value,
+            }
+        }
+        // Both sides of && and || are only worked out if they're needed.
+        Node::Binary(BinaryOp::And, ref left, ref right) => {
+            (evaluate(left, state, memory) != 0 && evaluate(right, state, memory) != 0) as i64
+        }
+        Node::Binary(BinaryOp::Or, ref left, ref right) => {
+            (evaluate(left, state, memory) != 0 || evaluate(right, state, memory) != 0) as i64
+        }
+        Node::Binary(op, ref left, ref right) => {
+            let left = evaluate(left, state, memory);
+            let right = evaluate(right, state, memory);
+            match op {
+                BinaryOp::Equal => (left == right) as i64,
+                BinaryOp::NotEqual => (left != right) as i64,
+                BinaryOp::Less => (left < right) as i64,
+                BinaryOp::LessOrEqual => (left <= right) as i64,
+                BinaryOp::Greater => (left > right) as i64,
+                BinaryOp::GreaterOrEqual => (left >= right) as i64,
+                BinaryOp::BitOr => left | right,
+                BinaryOp::BitXor => left ^ right,
+                BinaryOp::BitAnd => left & right,
+                BinaryOp::ShiftLeft => left.wrapping_shl(right as u32),
+                BinaryOp::ShiftRight => left.wrapping_shr(right as u32),
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Subtract => left.wrapping_sub(right),
+                BinaryOp::Multiply => left.wrapping_mul(right),
+                BinaryOp::Divide => left.checked_div(right).unwrap_or(0),
+                BinaryOp::Remainder => left.checked_rem(right).unwrap_or(0),
+                BinaryOp::And | BinaryOp::Or => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_emu::Chip8;
+
+    use super::{Expression, Template};
+
+    fn evaluate(text: &str) -> i64 {
+        let mut chip8 = Chip8::new_and_init();
+        let mut state = chip8.cpu_state();
+        state.v[3] = 5;
+        state.v[0xF] = 1;
+        state.i = 0x300;
+        state.delay_timer = 0;
+        chip8.set_cpu_state(&state);
+        chip8.write_memory(0x300, &[7, 9]);
+        Expression::parse(text).unwrap().evaluate(&chip8.cpu_state(), chip8.memory())
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(1, evaluate("V3 == 5 && I >= 0x300 && [I] != 0"));
+        assert_eq!(1, evaluate("delay == 0"));
+        assert_eq!(9, evaluate("[i + 1]"));
+        assert_eq!(14, evaluate("2 + 3 * 4"));
+        assert_eq!(20, evaluate("(2 + 3) * 4"));
+        // Bitwise operators bind more tightly than comparisons, as in Rust.
+        assert_eq!(1, evaluate("vf & 1 == 1"));
+        assert_eq!(1, evaluate("!(v3 < 5) || 0b10 >> 1 == 3"));
+        assert_eq!(-6, evaluate("~V3"));
+        assert_eq!(0, evaluate("v3 / 0"));
+        assert_eq!(0x200, evaluate("PC"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expression::parse("V3 ==").unwrap_err().contains("ends too soon"));
+        assert!(Expression::parse("VG == 1").unwrap_err().contains("isn't a register"));
+        assert!(Expression::parse("[I").unwrap_err().contains("expected ]"));
+        assert!(Expression::parse("V3 = 5").unwrap_err().contains("didn't expect ="));
+        assert!(Expression::parse("1 2").unwrap_err().contains("didn't expect 2"));
+    }
+
+    #[test]
+    fn test_template() {
+        let chip8 = Chip8::new_and_init();
+        let template = Template::parse("{{PC}} is {pc:x}, {PC - 0x100}").unwrap();
+        assert_eq!("{PC} is 0x200, 256", template.format(&chip8.cpu_state(), chip8.memory()));
+        assert!(Template::parse("{pc:q}").is_err());
+        assert!(Template::parse("pc}").is_err());
+        assert!(Template::parse("{pc").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use chip8_emu::DirtyRows;
+
+use frontend::Shades;
+
+// Optional filters to take the edge off the flicker you get from games that
+// erase and redraw their sprites every frame with XOR. They work on a copy of
+// the display, so the emulator's own framebuffer is left alone.
+
+const DEFAULT_PHOSPHOR_STRENGTH: u8 = 60;
+const DEFAULT_MERGE_STRENGTH: u8 = 100;
+// Any more than this and pixels would take too long to fade out.
+const MAX_PHOSPHOR_STRENGTH: u32 = 95;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayFilter {
+    None,
+    // Pixels fade out over a few frames rather than switching off straight
+    // away, like the phosphor on an old CRT. The strength is how much of a
+    // pixel's brightness carries over from one frame to the next.
+    Phosphor,
+    // A pixel is shown as lit if it was lit in this frame or the one before.
+    // The strength is how bright it is in the second frame.
+    Merge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterSettings {
+    pub filter: DisplayFilter,
+    // As a percentage.
+    pub strength: u8,
+}
+
+impl FilterSettings {
+    pub fn new(filter: DisplayFilter) -> FilterSettings {
+        let strength = match filter {
+            DisplayFilter::None => 0,
+            DisplayFilter::Phosphor => DEFAULT_PHOSPHOR_STRENGTH,
+            DisplayFilter::Merge => DEFAULT_MERGE_STRENGTH,
+        };
+        FilterSettings { filter, strength }
+    }
+}
+
+impl Default for FilterSettings {
+    fn default() -> FilterSettings {
+        FilterSettings::new(DisplayFilter::None)
+    }
+}
+
+pub struct FrameFilter {
+    settings: FilterSettings,
+    shades: Shades,
+    previous_screen: [[bool; 64]; 32],
+    // Whether the shades are just the display as it is, with nothing still
+    // fading. Until then, frames keep changing even if nothing is drawn.
+    is_settled: bool,
+    // Set when the whole picture has to be presented again, whether or not
+    // any shade changes.
+    is_cleared: bool,
+}
+
+impl FrameFilter {
+    pub fn new(settings: FilterSettings) -> FrameFilter {
+        FrameFilter {
+            settings,
+            shades: [[0; 64]; 32],
+            previous_screen: [[false; 64]; 32],
+            is_settled: true,
+            is_cleared: false,
+        }
+    }
+
+    // Forgets the last picture, for when a different machine takes over the
+    // screen and its rows won't all be marked as drawn to.
+    pub fn clear(&mut self) {
+        self.shades = [[0; 64]; 32];
+        self.previous_screen = [[false; 64]; 32];
+        self.is_settled = false;
+        self.is_cleared = true;
+    }
+
+    pub fn shades(&self) -> &Shades {
+        &self.shades
+    }
+
+    // Works out the shades for the next frame, given the rows that have been
+    // drawn to since the last one. Once everything has settled down, only
+    // those rows need looking at. Returns whether any shade changed.
+    pub fn apply(&mut self, screen: &[[bool; 64]; 32], dirty_rows: DirtyRows) -> bool {
+        if dirty_rows.is_empty() && self.is_settled {
+            return false;
+        }
+
+        let strength = self.settings.strength as u32;
+        let mut has_changed = self.is_cleared;
+        let mut is_settled = true;
+        for (y, row) in screen.iter().enumerate() {
+            if self.is_settled && !dirty_rows.contains(y) {
+                continue;
+            }
+            for (x, &is_lit) in row.iter().enumerate() {
+                let old_shade = self.shades[y][x];
+                let shade = if is_lit {
+                    255
+                } else {
+                    match self.settings.filter {
+                        DisplayFilter::None => 0,
+                        DisplayFilter::Phosphor => {
+                            (old_shade as u32 * strength.min(MAX_PHOSPHOR_STRENGTH) / 100) as u8
+                        }
+                        DisplayFilter::Merge if self.previous_screen[y][x] => {
+                            (255 * strength / 100) as u8
+                        }
+                        DisplayFilter::Merge => 0,
+                    }
+                };
+                has_changed |= shade != old_shade;
+                is_settled &= shade == if is_lit { 255 } else { 0 };
+                self.shades[y][x] = shade;
+            }
+        }
+        self.previous_screen = *screen;
+        self.is_settled = is_settled;
+        self.is_cleared = false;
+        has_changed
+    }
+}
+
+pub fn parse_filter(name: &str) -> Option<DisplayFilter> {
+    match name {
+        "none" => Some(DisplayFilter::None),
+        "phosphor" => Some(DisplayFilter::Phosphor),
+        "merge" => Some(DisplayFilter::Merge),
+        _ => None,
+    }
+}
+
+pub fn parse_strength(value: &str) -> Option<u8> {
+    value.parse::<u8>().ok().filter(|&strength| strength <= 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_emu::DirtyRows;
+
+    use super::{DisplayFilter, FilterSettings, FrameFilter};
+
+    fn screen_with_pixel(is_lit: bool) -> [[bool; 64]; 32] {
+        let mut screen = [[false; 64]; 32];
+        screen[0][0] = is_lit;
+        screen
+    }
+
+    #[test]
+    fn test_no_filter_passes_the_display_through() {
+        let mut filter = FrameFilter::new(FilterSettings::default());
+        assert!(filter.apply(&screen_with_pixel(true), DirtyRows::all()));
+        assert_eq!(255, filter.shades()[0][0]);
+        assert!(filter.apply(&screen_with_pixel(false), DirtyRows::all()));
+        assert_eq!(0, filter.shades()[0][0]);
+        assert!(!filter.apply(&screen_with_pixel(false), DirtyRows::default()));
+    }
+
+    #[test]
+    fn test_phosphor_fades_out() {
+        let mut filter = FrameFilter::new(FilterSettings {
+            filter: DisplayFilter::Phosphor,
+            strength: 50,
+        });
+        filter.apply(&screen_with_pixel(true), DirtyRows::all());
+        filter.apply(&screen_with_pixel(false), DirtyRows::all());
+        assert_eq!(127, filter.shades()[0][0]);
+        // Keeps fading even though nothing more is drawn.
+        assert!(filter.apply(&screen_with_pixel(false), DirtyRows::default()));
+        assert_eq!(63, filter.shades()[0][0]);
+
+        let mut frames = 0;
+        while filter.apply(&screen_with_pixel(false), DirtyRows::default()) {
+            frames += 1;
+        }
+        assert_eq!(0, filter.shades()[0][0]);
+        assert_eq!(6, frames);
+    }
+
+    #[test]
+    fn test_merge_keeps_the_previous_frame() {
+        let mut filter = FrameFilter::new(FilterSettings::new(DisplayFilter::Merge));
+        filter.apply(&screen_with_pixel(true), DirtyRows::all());
+        filter.apply(&screen_with_pixel(false), DirtyRows::all());
+        assert_eq!(255, filter.shades()[0][0]);
+        assert!(filter.apply(&screen_with_pixel(false), DirtyRows::default()));
+        assert_eq!(0, filter.shades()[0][0]);
+        assert!(!filter.apply(&screen_with_pixel(false), DirtyRows::default()));
+
+        let mut filter = FrameFilter::new(FilterSettings {
+            filter: DisplayFilter::Merge,
+            strength: 50,
+        });
+        filter.apply(&screen_with_pixel(true), DirtyRows::all());
+        filter.apply(&screen_with_pixel(false), DirtyRows::all());
+        assert_eq!(127, filter.shades()[0][0]);
+    }
+}
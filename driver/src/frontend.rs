@@ -0,0 +1,147 @@
+// The pieces a frontend has to provide. The runner owns the emulator, the event
+// loop and frame pacing, and talks to the outside world only through these
+// traits, so a new backend only has to deal with its own input, video and
+// audio APIs.
+
+use std::path::PathBuf;
+
+use keymap::Keymap;
+use palette::Palette;
+
+// How brightly each pixel of the emulated display should be shown, from 0 for
+// off to 255 for fully lit. Without a display filter every pixel is one or
+// the other.
+pub type Shades = [[u8; 64]; 32];
+
+// For sinks that can only show a pixel as on or off.
+pub fn is_lit(shade: u8) -> bool {
+    shade >= 0x80
+}
+
+// A key on the host keyboard, or a game controller input, independent of any
+// particular backend. Letters are always lower case, and the space bar is
+// Char(' ').
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Char(char),
+    // F1 to F12.
+    Function(u8),
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Tab,
+    Backspace,
+    Pad(PadInput),
+}
+
+// Game controller inputs, named after the Xbox layout like SDL does. Sticks
+// and triggers count as pressed once they're pushed past the deadzone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PadInput {
+    A,
+    B,
+    X,
+    Y,
+    Back,
+    Start,
+    LeftShoulder,
+    RightShoulder,
+    LeftStickButton,
+    RightStickButton,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    LeftStickUp,
+    LeftStickDown,
+    LeftStickLeft,
+    LeftStickRight,
+    RightStickUp,
+    RightStickDown,
+    RightStickLeft,
+    RightStickRight,
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    Quit,
+    KeyDown { key: Key, repeat: bool },
+    KeyUp { key: Key },
+    // A ROM file dragged onto the window.
+    FileDropped(PathBuf),
+    // Something to tell the user, like a controller being plugged in.
+    Message(String),
+}
+
+// Several displays to show side by side, a row of columns at a time, when
+// comparing machines.
+pub struct Grid {
+    pub cells: Vec<GridCell>,
+    pub columns: usize,
+}
+
+pub struct GridCell {
+    pub shades: Shades,
+    // Whether the keys go to this machine.
+    pub has_input: bool,
+    // Pixels to pick out because they differ from the first display.
+    pub differences: Option<[[bool; 64]; 32]>,
+}
+
+pub trait InputSource {
+    // Appends any events that have happened since the last call.
+    fn poll_events(&mut self, events: &mut Vec<InputEvent>);
+
+    // Called whenever the bindings change, such as when another ROM is
+    // loaded, for sources that go by its key layout or deadzone.
+    fn set_keymap(&mut self, _keymap: &Keymap) {}
+}
+
+pub trait VideoSink {
+    // Called once per frame with the emulated display. has_changed is false
+    // if it looks the same as last frame, in which case sinks are free to
+    // skip redrawing.
+    fn present(&mut self, shades: &Shades, has_changed: bool);
+
+    // Like present(), for several displays at once. Sinks that can only fit
+    // one show the first that's taking input.
+    fn present_grid(&mut self, grid: &Grid, has_changed: bool) {
+        let cell = grid.cells.iter().find(|cell| cell.has_input).or_else(|| grid.cells.first());
+        if let Some(cell) = cell {
+            self.present(&cell.shades, has_changed);
+        }
+    }
+
+    // Whether present() already takes care of frame timing, for example by
+    // waiting for vsync. If not, the runner sleeps between frames to keep to
+    // 60Hz.
+    fn paces_frames(&self) -> bool {
+        false
+    }
+
+    // Switches between windowed and fullscreen, for sinks that have a window.
+    fn toggle_fullscreen(&mut self) {}
+
+    // Called before the first frame and whenever the palette changes.
+    fn set_palette(&mut self, _palette: &Palette) {}
+
+    // Called before the first frame and whenever the title changes, for
+    // sinks with somewhere to show it.
+    fn set_title(&mut self, _title: &str) {}
+}
+
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+
+    // Queues up one frame's worth of samples for playback.
+    fn queue_samples(&mut self, samples: &[i16]);
+}
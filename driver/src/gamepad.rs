@@ -0,0 +1,180 @@
+use frontend::{InputEvent, Key, PadInput};
+
+// Turns game controller buttons and sticks into key events, so that they can
+// be bound like any other key. Sticks and triggers count as pressed once
+// they're pushed past the deadzone. Controllers are told apart by an id from
+// the backend, so several can be plugged in at once.
+
+pub const DEFAULT_DEADZONE: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl PadAxis {
+    // The inputs for pushing the axis towards its negative and positive ends.
+    // Triggers only go one way.
+    fn inputs(self) -> (Option<PadInput>, PadInput) {
+        match self {
+            PadAxis::LeftX => (Some(PadInput::LeftStickLeft), PadInput::LeftStickRight),
+            PadAxis::LeftY => (Some(PadInput::LeftStickUp), PadInput::LeftStickDown),
+            PadAxis::RightX => (Some(PadInput::RightStickLeft), PadInput::RightStickRight),
+            PadAxis::RightY => (Some(PadInput::RightStickUp), PadInput::RightStickDown),
+            PadAxis::LeftTrigger => (None, PadInput::LeftTrigger),
+            PadAxis::RightTrigger => (None, PadInput::RightTrigger),
+        }
+    }
+}
+
+pub struct PadState {
+    // How far an axis has to move before it counts, out of 32767.
+    threshold: i32,
+    // What's currently held down, by controller.
+    held: Vec<(i32, PadInput)>,
+}
+
+impl PadState {
+    // The deadzone is a fraction of the full range of each axis.
+    pub fn new(deadzone: f32) -> PadState {
+        PadState {
+            threshold: threshold(deadzone),
+            held: Vec::new(),
+        }
+    }
+
+    // Takes effect from the next time each axis moves.
+    pub fn set_deadzone(&mut self, deadzone: f32) {
+        self.threshold = threshold(deadzone);
+    }
+
+    pub fn button(&mut self, id: i32, input: PadInput, is_pressed: bool, events: &mut Vec<InputEvent>) {
+        if is_pressed {
+            self.press(id, input, events);
+        } else {
+            self.release(id, input, events);
+        }
+    }
+
+    pub fn axis(&mut self, id: i32, axis: PadAxis, value: i16, events: &mut Vec<InputEvent>) {
+        let (negative, positive) = axis.inputs();
+        let value = value as i32;
+        let target = if value > self.threshold {
+            Some(positive)
+        } else if value < -self.threshold {
+            negative
+        } else {
+            None
+        };
+
+        for input in negative.into_iter().chain(Some(positive)) {
+            if Some(input) != target {
+                self.release(id, input, events);
+            }
+        }
+        if let Some(input) = target {
+            self.press(id, input, events);
+        }
+    }
+
+    // Lets go of everything that was held on a controller that's gone away,
+    // so that no keys get stuck down.
+    pub fn disconnect(&mut self, id: i32, events: &mut Vec<InputEvent>) {
+        for &(held_id, input) in self.held.iter() {
+            if held_id == id {
+                events.push(InputEvent::KeyUp { key: Key::Pad(input) });
+            }
+        }
+        self.held.retain(|held| held.0 != id);
+    }
+
+    fn press(&mut self, id: i32, input: PadInput, events: &mut Vec<InputEvent>) {
+        if !self.held.contains(&(id, input)) {
+            self.held.push((id, input));
+            events.push(InputEvent::KeyDown { key: Key::Pad(input), repeat: false });
+        }
+    }
+
+    fn release(&mut self, id: i32, input: PadInput, events: &mut Vec<InputEvent>) {
+        if let Some(index) = self.held.iter().position(|&held| held == (id, input)) {
+            self.held.remove(index);
+            events.push(InputEvent::KeyUp { key: Key::Pad(input) });
+        }
+    }
+}
+
+fn threshold(deadzone: f32) -> i32 {
+    (deadzone.clamp(0.0, 0.95) * 32767.0) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::{InputEvent, Key, PadInput};
+    use super::{PadAxis, PadState};
+
+    fn down(input: PadInput) -> InputEvent {
+        InputEvent::KeyDown { key: Key::Pad(input), repeat: false }
+    }
+
+    fn up(input: PadInput) -> InputEvent {
+        InputEvent::KeyUp { key: Key::Pad(input) }
+    }
+
+    #[test]
+    fn test_sticks_respect_the_deadzone() {
+        let mut pad = PadState::new(0.5);
+        let mut events = Vec::new();
+        pad.axis(0, PadAxis::LeftX, 10000, &mut events);
+        assert!(events.is_empty());
+
+        pad.axis(0, PadAxis::LeftX, 20000, &mut events);
+        pad.axis(0, PadAxis::LeftX, 30000, &mut events);
+        assert_eq!(vec![down(PadInput::LeftStickRight)], events);
+
+        // Flicking straight across releases one side and presses the other.
+        events.clear();
+        pad.axis(0, PadAxis::LeftX, -30000, &mut events);
+        assert_eq!(vec![up(PadInput::LeftStickRight), down(PadInput::LeftStickLeft)], events);
+
+        events.clear();
+        pad.axis(0, PadAxis::LeftX, 0, &mut events);
+        assert_eq!(vec![up(PadInput::LeftStickLeft)], events);
+
+        // Another ROM can ask for a smaller deadzone.
+        pad.set_deadzone(0.25);
+        pad.axis(0, PadAxis::LeftX, 10000, &mut events);
+        assert_eq!(Some(&down(PadInput::LeftStickRight)), events.last());
+    }
+
+    #[test]
+    fn test_triggers_only_press_one_way() {
+        let mut pad = PadState::new(0.3);
+        let mut events = Vec::new();
+        pad.axis(0, PadAxis::RightTrigger, 32767, &mut events);
+        pad.axis(0, PadAxis::RightTrigger, -32768, &mut events);
+        assert_eq!(vec![down(PadInput::RightTrigger), up(PadInput::RightTrigger)], events);
+    }
+
+    #[test]
+    fn test_unplugging_releases_held_inputs() {
+        let mut pad = PadState::new(0.3);
+        let mut events = Vec::new();
+        pad.button(1, PadInput::A, true, &mut events);
+        pad.button(2, PadInput::B, true, &mut events);
+        pad.axis(1, PadAxis::LeftY, -32768, &mut events);
+
+        events.clear();
+        pad.disconnect(1, &mut events);
+        assert_eq!(vec![up(PadInput::A), up(PadInput::LeftStickUp)], events);
+
+        // The other controller is unaffected.
+        events.clear();
+        pad.button(2, PadInput::B, false, &mut events);
+        assert_eq!(vec![up(PadInput::B)], events);
+    }
+}
@@ -0,0 +1,600 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+
+use chip8_emu::{Chip8, CpuState};
+
+use rand::Rng;
+
+use debugger::{DebugEvent, Debugger};
+
+// A stub for GDB's remote serial protocol, so that ROMs can be debugged from
+// GDB or anything else that speaks it:
+//
+//   (gdb) target remote localhost:2159
+//
+// It listens on a local TCP port and is polled once a frame, so the display
+// keeps going while the machine is stopped. The registers are V0 to VF
+// (numbers 0 to 15), I, PC, SP and the delay and sound timers, each sent
+// big-endian like everything else on CHIP-8.
+
+const REGISTER_NAMES: [&str; 21] = ["v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7",
+                                    "v8", "v9", "va", "vb", "vc", "vd", "ve", "vf",
+                                    "i", "pc", "sp", "dt", "st"];
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const INTERRUPT: u8 = 0x03;
+
+enum Incoming {
+    Packet(String),
+    BadChecksum,
+    Interrupt,
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    connection: Option<TcpStream>,
+    // Bytes received but not yet made sense of.
+    input: Vec<u8>,
+    breakpoints: BTreeSet<u16>,
+    is_stopped: bool,
+    // Where the machine was last continued or stepped from, so that a
+    // breakpoint there doesn't stop it again straight away.
+    resumed_at: Option<u16>,
+    // Until the debugger asks for them to stop, every packet is acknowledged.
+    uses_acks: bool,
+}
+
+impl GdbStub {
+    // The machine starts out stopped, waiting for a debugger to attach and
+    // tell it to go.
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            connection: None,
+            input: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            is_stopped: true,
+            resumed_at: None,
+            uses_acks: true,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.is_stopped
+    }
+
+    // Accepts a debugger if there isn't one, and answers whatever it's sent.
+    // Returns a message when a debugger comes or goes.
+    pub fn poll<R: Rng>(&mut self, chip8: &mut Chip8<R>) -> Option<String> {
+        let mut message = None;
+        if self.connection.is_none() {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    if let Err(error) = stream.set_nonblocking(true) {
+                        return Some(format!("Couldn't talk to the debugger: {}", error));
+                    }
+                    let _ = stream.set_nodelay(true);
+                    self.connection = Some(stream);
+                    self.input.clear();
+                    self.uses_acks = true;
+                    self.is_stopped = true;
+                    message = Some(format!("Debugger attached from {}", address));
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return None,
+                Err(error) => return Some(format!("Couldn't accept a debugger: {}", error)),
+            }
+        }
+
+        let mut buffer = [0; 1024];
+        loop {
+            let result = match self.connection {
+                Some(ref mut stream) => stream.read(&mut buffer),
+                None => return message,
+            };
+            match result {
+                Ok(0) => return Some(self.disconnect()),
+                Ok(count) => self.input.extend_from_slice(&buffer[..count]),
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return Some(self.disconnect()),
+            }
+        }
+
+        while let Some(incoming) = take_incoming(&mut self.input) {
+            let result = match incoming {
+                Incoming::Interrupt if self.is_stopped => Ok(()),
+                Incoming::Interrupt => {
+                    self.is_stopped = true;
+                    self.send_packet(&format!("S{:02x}", SIGINT))
+                }
+                Incoming::BadChecksum => self.send_raw(b"-"),
+                Incoming::Packet(packet) => {
+                    let acked = if self.uses_acks { self.send_raw(b"+") } else { Ok(()) };
+                    let reply = self.handle_packet(&packet, chip8);
+                    match reply {
+                        Some(reply) => acked.and_then(|_| self.send_packet(&reply)),
+                        None => acked,
+                    }
+                }
+            };
+            if result.is_err() {
+                return Some(self.disconnect());
+            }
+            if self.connection.is_none() {
+                return Some(String::from("Debugger detached"));
+            }
+        }
+        message
+    }
+
+    // Runs a frame's worth of instructions unless the debugger has the
+    // machine stopped, stopping early at a breakpoint. Returns true if it
+    // did.
+    pub fn execute_for_frame<R: Rng>(&mut self, chip8: &mut Chip8<R>, cycles: u32) -> bool {
+        if self.is_stopped {
+            return false;
+        }
+        for _ in 0..cycles {
+            let pc = chip8.cpu_state().pc;
+            let is_resuming = self.resumed_at.take() == Some(pc);
+            if !is_resuming && self.breakpoints.contains(&pc) {
+                self.is_stopped = true;
+                if self.send_packet(&format!("T{:02x}swbreak:;", SIGTRAP)).is_err() {
+                    self.disconnect();
+                }
+                return true;
+            }
+            chip8.execute_next_opcode();
+        }
+        chip8.update_timers();
+        false
+    }
+
+    // Returns the reply, or None if there isn't one yet.
+    fn handle_packet<R: Rng>(&mut self, packet: &str, chip8: &mut Chip8<R>) -> Option<String> {
+        let mut chars = packet.chars();
+        let command = chars.next()?;
+        let arguments = chars.as_str();
+        let reply = match command {
+            '?' => format!("S{:02x}", SIGTRAP),
+            'g' => {
+                let state = chip8.cpu_state();
+                (0..REGISTER_NAMES.len()).map(|number| encode_register(&state, number)).collect()
+            }
+            'G' => {
+                let mut state = chip8.cpu_state();
+                let mut rest = arguments;
+                for number in 0..REGISTER_NAMES.len() {
+                    let digits = register_size(number) * 2;
+                    match rest.get(..digits).and_then(|value| u16::from_str_radix(value, 16).ok()) {
+                        Some(value) => set_register(&mut state, number, value),
+                        None => return Some(error_reply()),
+                    }
+                    rest = &rest[digits..];
+                }
+                chip8.set_cpu_state(&state);
+                ok_reply()
+            }
+            'p' => match usize::from_str_radix(arguments, 16) {
+                Ok(number) if number < REGISTER_NAMES.len() => encode_register(&chip8.cpu_state(), number),
+                _ => error_reply(),
+            },
+            'P' => {
+                let parsed = arguments.split_once('=').and_then(|(number, value)| {
+                    let number = usize::from_str_radix(number, 16).ok().filter(|&n| n < REGISTER_NAMES.len())?;
+                    let value = u16::from_str_radix(value, 16).ok()?;
+                    Some((number, value))
+                });
+                match parsed {
+                    Some((number, value)) => {
+                        let mut state = chip8.cpu_state();
+                        set_register(&mut state, number, value);
+                        chip8.set_cpu_state(&state);
+                        ok_reply()
+                    }
+                    None => error_reply(),
+                }
+            }
+            'm' => match parse_range(arguments, chip8.memory().len()) {
+                Some((address, length)) => {
+                    chip8.memory()[address..address + length].iter().map(|byte| format!("{:02x}", byte)).collect()
+                }
+                None => error_reply(),
+            },
+            'M' => {
+                let written = arguments.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = parse_range(range, chip8.memory().len())?;
+                    let bytes = decode_hex(data).filter(|bytes| bytes.len() == length)?;
+                    chip8.write_memory(address as u16, &bytes);
+                    Some(())
+                });
+                if written.is_some() { ok_reply() } else { error_reply() }
+            }
+            // Software and hardware breakpoints come to the same thing here.
+            'Z' | 'z' => {
+                let mut fields = arguments.split(',');
+                let kind = fields.next();
+                let address = fields.next().and_then(|address| u16::from_str_radix(address, 16).ok());
+                match (kind, address) {
+                    (Some("0"), Some(address)) | (Some("1"), Some(address)) => {
+                        if command == 'Z' {
+                            self.breakpoints.insert(address);
+                        } else {
+                            self.breakpoints.remove(&address);
+                        }
+                        ok_reply()
+                    }
+                    // Watchpoints aren't supported.
+                    (Some(_), Some(_)) => String::new(),
+                    _ => error_reply(),
+                }
+            }
+            's' | 'c' => {
+                if let Ok(address) = u16::from_str_radix(arguments, 16) {
+                    let mut state = chip8.cpu_state();
+                    state.pc = address;
+                    chip8.set_cpu_state(&state);
+                }
+                if command == 's' {
+                    chip8.execute_next_opcode();
+                    format!("S{:02x}", SIGTRAP)
+                } else {
+                    self.resumed_at = Some(chip8.cpu_state().pc);
+                    self.is_stopped = false;
+                    return None;
+                }
+            }
+            // Detaching and killing both leave the machine running on its own.
+            'D' => {
+                let _ = self.send_packet(&ok_reply());
+                self.disconnect();
+                return None;
+            }
+            'k' => {
+                self.disconnect();
+                return None;
+            }
+            // There's only the one thread.
+            'H' => ok_reply(),
+            'T' => ok_reply(),
+            'q' => self.handle_query(arguments),
+            'Q' if arguments == "StartNoAckMode" => {
+                self.uses_acks = false;
+                ok_reply()
+            }
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn handle_query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return String::from("PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+");
+        }
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let description = target_description();
+            return match range.split_once(',') {
+                Some((offset, length)) => {
+                    let offset = usize::from_str_radix(offset, 16).unwrap_or(usize::MAX);
+                    let length = usize::from_str_radix(length, 16).unwrap_or(0);
+                    match description.get(offset.min(description.len())..) {
+                        Some(rest) if rest.len() > length => format!("m{}", &rest[..length]),
+                        Some(rest) => format!("l{}", rest),
+                        None => error_reply(),
+                    }
+                }
+                None => error_reply(),
+            };
+        }
+        match query {
+            "Attached" => String::from("1"),
+            "C" => String::from("QC1"),
+            "fThreadInfo" => String::from("m1"),
+            "sThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.send_raw(format!("${}#{:02x}", data, checksum).as_bytes())
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.connection {
+            Some(ref mut stream) => stream.write_all(bytes),
+            None => Ok(()),
+        }
+    }
+
+    // With no one watching, the machine carries on by itself.
+    fn disconnect(&mut self) -> String {
+        self.connection = None;
+        self.breakpoints.clear();
+        self.is_stopped = false;
+        String::from("Debugger detached")
+    }
+}
+
+impl<R: Rng> Debugger<R> for GdbStub {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        GdbStub::local_addr(self)
+    }
+
+    fn poll(&mut self, chip8: &mut Chip8<R>, events: &mut Vec<DebugEvent>) {
+        if let Some(message) = GdbStub::poll(self, chip8) {
+            events.push(DebugEvent::Message(message));
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        GdbStub::is_stopped(self)
+    }
+
+    fn execute_for_frame(&mut self, chip8: &mut Chip8<R>, cycles: u32) -> bool {
+        GdbStub::execute_for_frame(self, chip8, cycles)
+    }
+}
+
+// Takes the next packet or interrupt off the front of the input, skipping
+// acknowledgements. Returns None if there isn't a whole one yet.
+fn take_incoming(input: &mut Vec<u8>) -> Option<Incoming> {
+    loop {
+        match input.first() {
+            None => return None,
+            Some(&INTERRUPT) => {
+                input.remove(0);
+                return Some(Incoming::Interrupt);
+            }
+            Some(&b'$') => break,
+            Some(_) => {
+                input.remove(0);
+            }
+        }
+    }
+    let end = input.iter().position(|&byte| byte == b'#')?;
+    if input.len() < end + 3 {
+        return None;
+    }
+    let packet: Vec<u8> = input.drain(..end + 3).collect();
+    let data = &packet[1..end];
+    let checksum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    let expected = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match (expected == Some(checksum), String::from_utf8(data.to_vec())) {
+        (true, Ok(text)) => Some(Incoming::Packet(text)),
+        _ => Some(Incoming::BadChecksum),
+    }
+}
+
+fn target_description() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\
+                                <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+                                <target version=\"1.0\"><feature name=\"org.chip8.cpu\">");
+    for (number, name) in REGISTER_NAMES.iter().enumerate() {
+        let kind = match *name {
+            "pc" => "code_ptr",
+            "i" => "data_ptr",
+            _ => "uint8",
+        };
+        xml.push_str(&format!("<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+                              name, register_size(number) * 8, kind, number));
+    }
+    xml.push_str("</feature></target>");
+    xml
+}
+
+// In bytes.
+fn register_size(number: usize) -> usize {
+    match REGISTER_NAMES[number] {
+        "i" | "pc" => 2,
+        _ => 1,
+    }
+}
+
+fn encode_register(state: &CpuState, number: usize) -> String {
+    let value = match number {
+        0..=15 => state.v[number] as u16,
+        16 => state.i,
+        17 => state.pc,
+        18 => state.sp as u16,
+        19 => state.delay_timer as u16,
+        _ => state.sound_timer as u16,
+    };
+    match register_size(number) {
+        2 => format!("{:04x}", value),
+        _ => format!("{:02x}", value),
+    }
+}
+
+fn set_register(state: &mut CpuState, number: usize, value: u16) {
+    match number {
+        0..=15 => state.v[number] = value as u8,
+        16 => state.i = value,
+        17 => state.pc = value,
+        18 => state.sp = value as u8,
+        19 => state.delay_timer = value as u8,
+        _ => state.sound_timer = value as u8,
+    }
+}
+
+// An "address,length" pair that lies within memory.
+fn parse_range(range: &str, memory_size: usize) -> Option<(usize, usize)> {
+    let (address, length) = range.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+    if address.checked_add(length)? > memory_size {
+        return None;
+    }
+    Some((address, length))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes().chunks(2).map(|pair| {
+        let pair = std::str::from_utf8(pair).ok().filter(|pair| pair.len() == 2)?;
+        u8::from_str_radix(pair, 16).ok()
+    }).collect()
+}
+
+fn ok_reply() -> String {
+    String::from("OK")
+}
+
+fn error_reply() -> String {
+    String::from("E01")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use chip8_emu::Chip8;
+
+    use rand::XorShiftRng;
+
+    use super::GdbStub;
+
+    // Plays the part of GDB, one packet at a time.
+    struct Client {
+        stream: TcpStream,
+        received: Vec<u8>,
+    }
+
+    impl Client {
+        fn connect(stub: &mut GdbStub, chip8: &mut Chip8<XorShiftRng>) -> Client {
+            let stream = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+            stream.set_nonblocking(true).unwrap();
+            let mut client = Client { stream, received: Vec::new() };
+            client.wait_for(stub, chip8, |stub| !stub.is_stopped() || stub.connection.is_some());
+            client
+        }
+
+        fn send(&mut self, bytes: &[u8]) {
+            self.stream.write_all(bytes).unwrap();
+        }
+
+        fn request(&mut self, stub: &mut GdbStub, chip8: &mut Chip8<XorShiftRng>, packet: &str) -> String {
+            let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            self.send(format!("${}#{:02x}", packet, checksum).as_bytes());
+            self.reply(stub, chip8)
+        }
+
+        // Polls the stub until it's sent a whole packet back.
+        fn reply(&mut self, stub: &mut GdbStub, chip8: &mut Chip8<XorShiftRng>) -> String {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                if let Some(reply) = self.take_reply() {
+                    return reply;
+                }
+                assert!(Instant::now() < deadline, "no reply from the stub");
+                stub.poll(chip8);
+                self.read();
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        fn take_reply(&mut self) -> Option<String> {
+            let start = self.received.iter().position(|&byte| byte == b'$')?;
+            let end = self.received.iter().position(|&byte| byte == b'#')?;
+            if self.received.len() < end + 3 {
+                return None;
+            }
+            let packet: Vec<u8> = self.received.drain(..end + 3).collect();
+            Some(String::from_utf8(packet[start + 1..end].to_vec()).unwrap())
+        }
+
+        fn read(&mut self) {
+            let mut buffer = [0; 256];
+            while let Ok(count) = self.stream.read(&mut buffer) {
+                if count == 0 {
+                    break;
+                }
+                self.received.extend_from_slice(&buffer[..count]);
+            }
+        }
+
+        fn wait_for<F: Fn(&GdbStub) -> bool>(&mut self, stub: &mut GdbStub, chip8: &mut Chip8<XorShiftRng>, done: F) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !done(stub) {
+                assert!(Instant::now() < deadline, "the stub never got there");
+                stub.poll(chip8);
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    fn machine() -> Chip8<XorShiftRng> {
+        // Count up in V0, forever.
+        let mut chip8 = Chip8::new_and_init_with_rng(XorShiftRng::new_unseeded());
+        chip8.load_rom_at(&[0x70, 0x01, 0x12, 0x00], 0x200);
+        chip8
+    }
+
+    #[test]
+    fn test_registers_memory_and_target_description() {
+        let mut stub = GdbStub::listen(0).unwrap();
+        let mut chip8 = machine();
+        let mut client = Client::connect(&mut stub, &mut chip8);
+
+        assert!(client.request(&mut stub, &mut chip8, "qSupported:swbreak+").contains("qXfer:features:read+"));
+        let description = client.request(&mut stub, &mut chip8, "qXfer:features:read:target.xml:0,1000");
+        assert!(description.starts_with("l<?xml"));
+        assert!(description.contains("<reg name=\"pc\" bitsize=\"16\""));
+        assert_eq!("S05", client.request(&mut stub, &mut chip8, "?"));
+
+        // V0 to VF, I, PC, SP, DT and ST.
+        let registers = client.request(&mut stub, &mut chip8, "g");
+        assert_eq!(format!("{}{}{}{}", "00".repeat(16), "0000", "0200", "000000"), registers);
+        assert_eq!("OK", client.request(&mut stub, &mut chip8, "P10=0345"));
+        assert_eq!("0345", client.request(&mut stub, &mut chip8, "p10"));
+        assert_eq!(0x345, chip8.cpu_state().i);
+
+        assert_eq!("70011200", client.request(&mut stub, &mut chip8, "m200,4"));
+        assert_eq!("OK", client.request(&mut stub, &mut chip8, "M300,2:abcd"));
+        assert_eq!([0xAB, 0xCD], chip8.memory()[0x300..0x302]);
+        assert_eq!("E01", client.request(&mut stub, &mut chip8, "mfff,2"));
+        assert_eq!("", client.request(&mut stub, &mut chip8, "vMustReplyEmpty"));
+    }
+
+    #[test]
+    fn test_breakpoints_steps_and_interrupts() {
+        let mut stub = GdbStub::listen(0).unwrap();
+        let mut chip8 = machine();
+        let mut client = Client::connect(&mut stub, &mut chip8);
+        assert!(!stub.execute_for_frame(&mut chip8, 10));
+        assert_eq!(0, chip8.cpu_state().v[0]);
+
+        assert_eq!("S05", client.request(&mut stub, &mut chip8, "s"));
+        assert_eq!(0x202, chip8.cpu_state().pc);
+        assert_eq!(1, chip8.cpu_state().v[0]);
+
+        // Continuing from the jump runs round to the breakpoint on it once.
+        assert_eq!("OK", client.request(&mut stub, &mut chip8, "Z0,202,2"));
+        client.send(b"$c#63");
+        client.wait_for(&mut stub, &mut chip8, |stub| !stub.is_stopped());
+        assert!(stub.execute_for_frame(&mut chip8, 10));
+        assert_eq!("T05swbreak:;", client.reply(&mut stub, &mut chip8));
+        assert_eq!(2, chip8.cpu_state().v[0]);
+
+        assert_eq!("OK", client.request(&mut stub, &mut chip8, "z0,202,2"));
+        client.send(b"$c#63");
+        client.wait_for(&mut stub, &mut chip8, |stub| !stub.is_stopped());
+        assert!(!stub.execute_for_frame(&mut chip8, 10));
+        client.send(&[0x03]);
+        assert_eq!("S02", client.reply(&mut stub, &mut chip8));
+        assert!(stub.is_stopped());
+
+        assert_eq!("OK", client.request(&mut stub, &mut chip8, "D"));
+        assert!(!stub.is_stopped());
+    }
+}
@@ -0,0 +1,109 @@
+use frontend::{is_lit, AudioSink, InputEvent, InputSource, Shades, VideoSink};
+
+// A frontend with no window, keyboard or sound card, for running ROMs in
+// scripts and on build machines. Combine it with a frame limit and the
+// recorders to capture a ROM's output without any user interaction.
+
+pub struct HeadlessInput {
+    // Events to feed in, keyed by the frame on which they should arrive.
+    scripted_events: Vec<(u64, InputEvent)>,
+    frame: u64,
+}
+
+impl HeadlessInput {
+    pub fn new() -> HeadlessInput {
+        HeadlessInput::with_script(Vec::new())
+    }
+
+    pub fn with_script(mut scripted_events: Vec<(u64, InputEvent)>) -> HeadlessInput {
+        scripted_events.sort_by_key(|&(frame, _)| frame);
+        scripted_events.reverse();
+        HeadlessInput {
+            scripted_events,
+            frame: 0,
+        }
+    }
+}
+
+impl Default for HeadlessInput {
+    fn default() -> HeadlessInput {
+        HeadlessInput::new()
+    }
+}
+
+impl InputSource for HeadlessInput {
+    fn poll_events(&mut self, events: &mut Vec<InputEvent>) {
+        while self.scripted_events.last().is_some_and(|&(frame, _)| frame <= self.frame) {
+            let (_, event) = self.scripted_events.pop().unwrap();
+            events.push(event);
+        }
+        self.frame += 1;
+    }
+}
+
+// Keeps hold of the last frame so that it can be inspected afterwards.
+pub struct HeadlessVideo {
+    pub frames_presented: u64,
+    // Frames in which something on the display changed.
+    pub frames_changed: u64,
+    pub last_screen: [[bool; 64]; 32],
+    pub title: String,
+}
+
+impl HeadlessVideo {
+    pub fn new() -> HeadlessVideo {
+        HeadlessVideo {
+            frames_presented: 0,
+            frames_changed: 0,
+            last_screen: [[false; 64]; 32],
+            title: String::new(),
+        }
+    }
+}
+
+impl Default for HeadlessVideo {
+    fn default() -> HeadlessVideo {
+        HeadlessVideo::new()
+    }
+}
+
+impl VideoSink for HeadlessVideo {
+    fn present(&mut self, shades: &Shades, has_changed: bool) {
+        self.frames_presented += 1;
+        if has_changed {
+            self.frames_changed += 1;
+        }
+        for (row, shade_row) in self.last_screen.iter_mut().zip(shades.iter()) {
+            for (pixel, &shade) in row.iter_mut().zip(shade_row.iter()) {
+                *pixel = is_lit(shade);
+            }
+        }
+    }
+
+    // There's no one watching, so run as fast as we can.
+    fn paces_frames(&self) -> bool {
+        true
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = String::from(title);
+    }
+}
+
+pub struct NullAudio {
+    sample_rate: u32,
+}
+
+impl NullAudio {
+    pub fn new(sample_rate: u32) -> NullAudio {
+        NullAudio { sample_rate }
+    }
+}
+
+impl AudioSink for NullAudio {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn queue_samples(&mut self, _samples: &[i16]) {}
+}
@@ -0,0 +1,523 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use chip8_emu::ResetKind;
+
+use toml;
+
+use frontend::{Key, PadInput};
+use gamepad;
+use runner::Command;
+use sound;
+use sound::SoundCommand;
+
+// Which host keys press which CHIP-8 keys, and which ones control the
+// emulator. The defaults can be changed with a TOML file like this:
+//
+//   # "position" (the default) matches keys by where they are on the
+//   # keyboard, named as on US QWERTY, so the keypad stays in the same place
+//   # on AZERTY or Dvorak. "label" matches them by what's printed on them.
+//   layout = "position"
+//
+//   # How far a controller's sticks and triggers have to move before they
+//   # count as pressed, from 0 to 1.
+//   deadzone = 0.3
+//
+//   # CHIP-8 keys 0 to F, and the host keys or controller inputs that press
+//   # them. Listing a key replaces all of its bindings.
+//   [keypad]
+//   5 = ["w", "up", "pad-up"]
+//
+//   # An empty list unbinds a hotkey.
+//   [hotkeys]
+//   quit = "f12"
+//   record = []
+//
+//   # Overrides for particular ROMs, by file name.
+//   [roms."PONG"]
+//   keypad = { 1 = ["w", "pad-up"], 4 = ["s", "pad-down"], C = "up", D = "down" }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyLayout {
+    Position,
+    Label,
+}
+
+struct Hotkey {
+    name: &'static str,
+    description: &'static str,
+    command: Command,
+    // Toggles ignore key repeat so that holding the key down doesn't flip
+    // them back and forth.
+    repeats: bool,
+    default_key: Key,
+}
+
+const HOTKEYS: [Hotkey; 20] = [
+    Hotkey { name: "quit", description: "Quit", command: Command::Quit,
+             repeats: false, default_key: Key::Escape },
+    Hotkey { name: "help", description: "Show the key bindings", command: Command::ShowBindings,
+             repeats: false, default_key: Key::Function(1) },
+    Hotkey { name: "pause", description: "Pause or resume", command: Command::TogglePause,
+             repeats: false, default_key: Key::Function(2) },
+    Hotkey { name: "launcher", description: "Choose another ROM", command: Command::OpenLauncher,
+             repeats: false, default_key: Key::Backspace },
+    Hotkey { name: "reset", description: "Restart the ROM, keeping memory", command: Command::Reset(ResetKind::Soft),
+             repeats: false, default_key: Key::Home },
+    Hotkey { name: "hard-reset", description: "Restart the machine and reload the ROM",
+             command: Command::Reset(ResetKind::Hard),
+             repeats: false, default_key: Key::End },
+    Hotkey { name: "focus", description: "Send keys to all the machines being compared, or one",
+             command: Command::CycleFocus,
+             repeats: false, default_key: Key::Char('`') },
+    Hotkey { name: "turbo", description: "Fast forward while held", command: Command::Turbo,
+             repeats: false, default_key: Key::Tab },
+    Hotkey { name: "slow-motion", description: "Slow motion on or off", command: Command::ToggleSlowMotion,
+             repeats: false, default_key: Key::Function(3) },
+    Hotkey { name: "stats", description: "Show or hide the frame rate and speed", command: Command::ToggleStats,
+             repeats: false, default_key: Key::Function(4) },
+    Hotkey { name: "waveform", description: "Cycle buzzer waveform",
+             command: Command::Sound(SoundCommand::CycleWaveform),
+             repeats: false, default_key: Key::Function(5) },
+    Hotkey { name: "mute", description: "Mute or unmute the buzzer",
+             command: Command::Sound(SoundCommand::ToggleMute),
+             repeats: false, default_key: Key::Function(6) },
+    Hotkey { name: "volume-down", description: "Buzzer volume down",
+             command: Command::Sound(SoundCommand::ChangeVolume(-sound::VOLUME_STEP)),
+             repeats: true, default_key: Key::Function(7) },
+    Hotkey { name: "volume-up", description: "Buzzer volume up",
+             command: Command::Sound(SoundCommand::ChangeVolume(sound::VOLUME_STEP)),
+             repeats: true, default_key: Key::Function(8) },
+    Hotkey { name: "pitch-down", description: "Buzzer pitch down",
+             command: Command::Sound(SoundCommand::ChangePitch(-1)),
+             repeats: true, default_key: Key::PageDown },
+    Hotkey { name: "pitch-up", description: "Buzzer pitch up",
+             command: Command::Sound(SoundCommand::ChangePitch(1)),
+             repeats: true, default_key: Key::PageUp },
+    Hotkey { name: "record", description: "Start or stop recording video and audio",
+             command: Command::ToggleRecording,
+             repeats: false, default_key: Key::Function(9) },
+    Hotkey { name: "palette", description: "Next colour palette", command: Command::CyclePalette,
+             repeats: false, default_key: Key::Function(10) },
+    Hotkey { name: "fullscreen", description: "Toggle fullscreen", command: Command::ToggleFullscreen,
+             repeats: false, default_key: Key::Function(11) },
+    Hotkey { name: "show-keys", description: "Show or hide the held keys", command: Command::ToggleKeyDisplay,
+             repeats: false, default_key: Key::Function(12) },
+];
+
+// The usual layout:
+//
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+const DEFAULT_KEYPAD: [(char, u8); 16] = [
+    ('1', 0x1), ('2', 0x2), ('3', 0x3), ('4', 0xC),
+    ('q', 0x4), ('w', 0x5), ('e', 0x6), ('r', 0xD),
+    ('a', 0x7), ('s', 0x8), ('d', 0x9), ('f', 0xE),
+    ('z', 0xA), ('x', 0x0), ('c', 0xB), ('v', 0xF),
+];
+
+// Controllers move with the D-pad or left stick, on the same keys as WASD
+// above, and fire with A or B, which is what most games expect.
+const DEFAULT_PAD: [(PadInput, u8); 10] = [
+    (PadInput::DPadUp, 0x5), (PadInput::DPadLeft, 0x7),
+    (PadInput::DPadDown, 0x8), (PadInput::DPadRight, 0x9),
+    (PadInput::LeftStickUp, 0x5), (PadInput::LeftStickLeft, 0x7),
+    (PadInput::LeftStickDown, 0x8), (PadInput::LeftStickRight, 0x9),
+    (PadInput::A, 0x6), (PadInput::B, 0x4),
+];
+
+// The names controller inputs go by in keymap files.
+const PAD_NAMES: [(&str, PadInput); 24] = [
+    ("pad-a", PadInput::A), ("pad-b", PadInput::B), ("pad-x", PadInput::X), ("pad-y", PadInput::Y),
+    ("pad-back", PadInput::Back), ("pad-start", PadInput::Start),
+    ("pad-lb", PadInput::LeftShoulder), ("pad-rb", PadInput::RightShoulder),
+    ("pad-ls", PadInput::LeftStickButton), ("pad-rs", PadInput::RightStickButton),
+    ("pad-up", PadInput::DPadUp), ("pad-down", PadInput::DPadDown),
+    ("pad-left", PadInput::DPadLeft), ("pad-right", PadInput::DPadRight),
+    ("pad-lstick-up", PadInput::LeftStickUp), ("pad-lstick-down", PadInput::LeftStickDown),
+    ("pad-lstick-left", PadInput::LeftStickLeft), ("pad-lstick-right", PadInput::LeftStickRight),
+    ("pad-rstick-up", PadInput::RightStickUp), ("pad-rstick-down", PadInput::RightStickDown),
+    ("pad-rstick-left", PadInput::RightStickLeft), ("pad-rstick-right", PadInput::RightStickRight),
+    ("pad-lt", PadInput::LeftTrigger), ("pad-rt", PadInput::RightTrigger),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    pub layout: KeyLayout,
+    // As a fraction of how far sticks and triggers can move.
+    pub deadzone: f32,
+    // Host keys and the CHIP-8 key each one presses.
+    keypad: Vec<(Key, u8)>,
+    // Host keys and the index of the hotkey each one triggers.
+    hotkeys: Vec<(Key, usize)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap {
+            layout: KeyLayout::Position,
+            deadzone: gamepad::DEFAULT_DEADZONE,
+            keypad: DEFAULT_KEYPAD.iter()
+                .map(|&(c, index)| (Key::Char(c), index))
+                .chain(DEFAULT_PAD.iter().map(|&(input, index)| (Key::Pad(input), index)))
+                .collect(),
+            hotkeys: HOTKEYS.iter().enumerate()
+                .map(|(index, hotkey)| (hotkey.default_key, index))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    pub fn chip8_key_for(&self, key: Key) -> Option<u8> {
+        self.keypad.iter().find(|binding| binding.0 == key).map(|binding| binding.1)
+    }
+
+    // Makes a host key press a CHIP-8 key, instead of whatever it did before.
+    pub fn bind(&mut self, key: Key, chip8_key: u8) {
+        self.keypad.retain(|binding| binding.0 != key);
+        self.hotkeys.retain(|binding| binding.0 != key);
+        self.keypad.push((key, chip8_key));
+    }
+
+    pub fn command_for(&self, key: Key, repeat: bool) -> Option<Command> {
+        self.hotkeys.iter()
+            .find(|binding| binding.0 == key)
+            .map(|binding| &HOTKEYS[binding.1])
+            .filter(|hotkey| hotkey.repeats || !repeat)
+            .map(|hotkey| hotkey.command)
+    }
+
+    // Lists the current bindings, one per line.
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+        for (index, hotkey) in HOTKEYS.iter().enumerate() {
+            let keys = key_names(self.hotkeys.iter().filter(|binding| binding.1 == index).map(|b| b.0));
+            if !keys.is_empty() {
+                lines.push(format!("  {:<12}{}", keys, hotkey.description));
+            }
+        }
+        lines.push(String::from("  CHIP-8 keypad:"));
+        for row in &[[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]] {
+            let mut line = String::from("   ");
+            for &chip8_key in row.iter() {
+                let keys = key_names(self.keypad.iter()
+                    .filter(|b| b.1 == chip8_key && !is_pad(b.0))
+                    .map(|b| b.0));
+                line.push_str(&format!(" {:X}={:<8}", chip8_key, if keys.is_empty() { "-" } else { &keys }));
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        // Controller bindings would never fit in the grid above.
+        let mut pad_lines = Vec::new();
+        for chip8_key in 0..16 {
+            let keys = key_names(self.keypad.iter()
+                .filter(|b| b.1 == chip8_key && is_pad(b.0))
+                .map(|b| b.0));
+            if !keys.is_empty() {
+                pad_lines.push(format!("    {:X}={}", chip8_key, keys));
+            }
+        }
+        if !pad_lines.is_empty() {
+            lines.push(String::from("  Controller:"));
+            lines.extend(pad_lines);
+        }
+        lines.join("\n")
+    }
+
+    // The same bindings, a short line each, for listing on the display.
+    pub fn binding_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (index, hotkey) in HOTKEYS.iter().enumerate() {
+            let keys = key_names(self.hotkeys.iter().filter(|binding| binding.1 == index).map(|b| b.0));
+            if !keys.is_empty() {
+                lines.push(format!("{}: {}", keys, hotkey.description));
+            }
+        }
+        for chip8_key in 0..16 {
+            let keys = key_names(self.keypad.iter().filter(|b| b.1 == chip8_key).map(|b| b.0));
+            if !keys.is_empty() {
+                lines.push(format!("Key {:X}: {}", chip8_key, keys));
+            }
+        }
+        lines
+    }
+
+    // Applies the settings from a keymap file, followed by any overrides for
+    // the named ROM.
+    pub fn apply_config(&mut self, config: &toml::Value, rom_name: Option<&str>) -> Result<(), String> {
+        if let Some(layout) = config.get("layout") {
+            self.layout = match layout.as_str() {
+                Some("position") => KeyLayout::Position,
+                Some("label") => KeyLayout::Label,
+                _ => return Err(String::from("layout should be \"position\" or \"label\"")),
+            };
+        }
+        self.apply_bindings(config)?;
+
+        let rom_config = rom_name.and_then(|name| config.get("roms").and_then(|roms| roms.get(name)));
+        if let Some(rom_config) = rom_config {
+            self.apply_bindings(rom_config)?;
+        }
+        Ok(())
+    }
+
+    fn apply_bindings(&mut self, config: &toml::Value) -> Result<(), String> {
+        if let Some(deadzone) = config.get("deadzone") {
+            self.deadzone = deadzone.as_float()
+                .or_else(|| deadzone.as_integer().map(|value| value as f64))
+                .filter(|value| (0.0..1.0).contains(value))
+                .ok_or("deadzone should be a number from 0 to 1")? as f32;
+        }
+        if let Some(keypad) = config.get("keypad") {
+            let keypad = keypad.as_table().ok_or("keypad should be a table")?;
+            for (chip8_key, keys) in keypad {
+                let chip8_key = u8::from_str_radix(chip8_key, 16).ok()
+                    .filter(|&index| index < 16)
+                    .ok_or_else(|| format!("{} isn't a CHIP-8 key", chip8_key))?;
+                let keys = parse_key_list(keys)?;
+                self.keypad.retain(|binding| binding.1 != chip8_key && !keys.contains(&binding.0));
+                self.hotkeys.retain(|binding| !keys.contains(&binding.0));
+                self.keypad.extend(keys.into_iter().map(|key| (key, chip8_key)));
+            }
+        }
+
+        if let Some(hotkeys) = config.get("hotkeys") {
+            let hotkeys = hotkeys.as_table().ok_or("hotkeys should be a table")?;
+            for (name, keys) in hotkeys {
+                let index = HOTKEYS.iter().position(|hotkey| hotkey.name == name)
+                    .ok_or_else(|| format!("{} isn't a hotkey", name))?;
+                let keys = parse_key_list(keys)?;
+                self.hotkeys.retain(|binding| binding.1 != index);
+                // A key can only do one thing, so take it away from anything
+                // else it was bound to.
+                self.hotkeys.retain(|binding| !keys.contains(&binding.0));
+                self.keypad.retain(|binding| !keys.contains(&binding.0));
+                self.hotkeys.extend(keys.into_iter().map(|key| (key, index)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Loads a keymap file on top of the given bindings, applying any overrides
+// for the named ROM.
+pub fn load(path: &Path, rom_name: Option<&str>, mut keymap: Keymap) -> Result<Keymap, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|error| error.to_string())?;
+    let config = text.parse::<toml::Value>().map_err(|error| error.to_string())?;
+    keymap.apply_config(&config, rom_name)?;
+    Ok(keymap)
+}
+
+fn parse_key_list(value: &toml::Value) -> Result<Vec<Key>, String> {
+    let names = match *value {
+        toml::Value::String(ref name) => vec![name.as_str()],
+        toml::Value::Array(ref names) => {
+            names.iter()
+                .map(|name| name.as_str().ok_or_else(|| String::from("key names should be strings")))
+                .collect::<Result<_, _>>()?
+        }
+        _ => return Err(String::from("expected a key name or a list of them")),
+    };
+    names.into_iter()
+        .map(|name| parse_key_name(name).ok_or_else(|| format!("unknown key: {}", name)))
+        .collect()
+}
+
+fn is_pad(key: Key) -> bool {
+    matches!(key, Key::Pad(_))
+}
+
+fn key_names<I: Iterator<Item = Key>>(keys: I) -> String {
+    keys.map(key_name).collect::<Vec<_>>().join("/")
+}
+
+// Parses a key name, ignoring case and spaces. These are the names used in
+// keymap files, and also cover the names SDL gives its keys.
+pub fn parse_key_name(name: &str) -> Option<Key> {
+    let name = name.to_lowercase().replace(' ', "");
+    let key = match name.as_str() {
+        "escape" | "esc" => Key::Escape,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "enter" | "return" => Key::Enter,
+        "tab" => Key::Tab,
+        "backspace" => Key::Backspace,
+        "space" => Key::Char(' '),
+        "comma" => Key::Char(','),
+        "period" => Key::Char('.'),
+        "slash" => Key::Char('/'),
+        "semicolon" => Key::Char(';'),
+        "apostrophe" => Key::Char('\''),
+        "minus" => Key::Char('-'),
+        "equals" => Key::Char('='),
+        "leftbracket" => Key::Char('['),
+        "rightbracket" => Key::Char(']'),
+        "backslash" => Key::Char('\\'),
+        "grave" => Key::Char('`'),
+        _ => {
+            if let Some(&(_, input)) = PAD_NAMES.iter().find(|pad| pad.0 == name) {
+                return Some(Key::Pad(input));
+            }
+            if let Some(number) = name.strip_prefix('f').and_then(|number| number.parse::<u8>().ok()) {
+                if (1..=12).contains(&number) {
+                    return Some(Key::Function(number));
+                }
+            }
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_graphic() => Key::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(key)
+}
+
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(' ') => String::from("Space"),
+        Key::Char(c) => c.to_uppercase().to_string(),
+        Key::Function(number) => format!("F{}", number),
+        Key::PageUp => String::from("PgUp"),
+        Key::PageDown => String::from("PgDn"),
+        Key::Home => String::from("Home"),
+        Key::End => String::from("End"),
+        Key::Escape => String::from("Esc"),
+        Key::Up => String::from("Up"),
+        Key::Down => String::from("Down"),
+        Key::Left => String::from("Left"),
+        Key::Right => String::from("Right"),
+        Key::Enter => String::from("Enter"),
+        Key::Tab => String::from("Tab"),
+        Key::Backspace => String::from("Backspace"),
+        Key::Pad(input) => {
+            let name = PAD_NAMES.iter().find(|pad| pad.1 == input).map_or("pad-?", |pad| pad.0);
+            String::from(name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::{Key, PadInput};
+    use runner::Command;
+    use super::{key_name, parse_key_name, KeyLayout, Keymap};
+
+    fn keymap_from(text: &str, rom_name: Option<&str>) -> Result<Keymap, String> {
+        let mut keymap = Keymap::default();
+        keymap.apply_config(&text.parse().unwrap(), rom_name)?;
+        Ok(keymap)
+    }
+
+    #[test]
+    fn test_default_bindings() {
+        let keymap = Keymap::default();
+        assert_eq!(KeyLayout::Position, keymap.layout);
+        assert_eq!(Some(0xC), keymap.chip8_key_for(Key::Char('4')));
+        assert_eq!(Some(0x0), keymap.chip8_key_for(Key::Char('x')));
+        assert_eq!(None, keymap.chip8_key_for(Key::Char('p')));
+        assert!(matches!(keymap.command_for(Key::Escape, false), Some(Command::Quit)));
+        // Toggles ignore key repeat, but the volume can be held down.
+        assert!(keymap.command_for(Key::Function(6), true).is_none());
+        assert!(keymap.command_for(Key::Function(8), true).is_some());
+    }
+
+    #[test]
+    fn test_config_overrides_bindings() {
+        let keymap = keymap_from("layout = \"label\"\n\
+                                  [keypad]\n\
+                                  5 = [\"up\", \"k\"]\n\
+                                  [hotkeys]\n\
+                                  quit = \"f12\"\n\
+                                  record = []\n", None).unwrap();
+        assert_eq!(KeyLayout::Label, keymap.layout);
+        assert_eq!(Some(0x5), keymap.chip8_key_for(Key::Up));
+        assert_eq!(Some(0x5), keymap.chip8_key_for(Key::Char('k')));
+        // The old binding for 5 has gone.
+        assert_eq!(None, keymap.chip8_key_for(Key::Char('w')));
+        assert!(matches!(keymap.command_for(Key::Function(12), false), Some(Command::Quit)));
+        assert!(keymap.command_for(Key::Escape, false).is_none());
+        assert!(keymap.command_for(Key::Function(9), false).is_none());
+    }
+
+    #[test]
+    fn test_keys_only_do_one_thing() {
+        let keymap = keymap_from("[keypad]\n5 = \"tab\"\n[hotkeys]\npause = \"q\"\n", None).unwrap();
+        assert_eq!(Some(0x5), keymap.chip8_key_for(Key::Tab));
+        assert!(keymap.command_for(Key::Tab, false).is_none());
+        assert!(matches!(keymap.command_for(Key::Char('q'), false), Some(Command::TogglePause)));
+        assert_eq!(None, keymap.chip8_key_for(Key::Char('q')));
+    }
+
+    #[test]
+    fn test_rom_overrides() {
+        let text = "[keypad]\n1 = \"j\"\n[roms.PONG]\nkeypad = { 1 = \"up\" }\n";
+        let keymap = keymap_from(text, Some("PONG")).unwrap();
+        assert_eq!(Some(0x1), keymap.chip8_key_for(Key::Up));
+        assert_eq!(None, keymap.chip8_key_for(Key::Char('j')));
+        let keymap = keymap_from(text, Some("BRIX")).unwrap();
+        assert_eq!(Some(0x1), keymap.chip8_key_for(Key::Char('j')));
+    }
+
+    #[test]
+    fn test_controller_bindings() {
+        let keymap = Keymap::default();
+        assert_eq!(Some(0x5), keymap.chip8_key_for(Key::Pad(PadInput::DPadUp)));
+        assert_eq!(Some(0x7), keymap.chip8_key_for(Key::Pad(PadInput::LeftStickLeft)));
+        assert_eq!(Some(0x6), keymap.chip8_key_for(Key::Pad(PadInput::A)));
+
+        let text = "deadzone = 0.5\n[roms.PONG]\ndeadzone = 0.1\nkeypad = { 1 = [\"w\", \"pad-lstick-up\"] }\n";
+        let keymap = keymap_from(text, Some("PONG")).unwrap();
+        assert_eq!(0.1, keymap.deadzone);
+        assert_eq!(Some(0x1), keymap.chip8_key_for(Key::Pad(PadInput::LeftStickUp)));
+        assert_eq!(Some(0x5), keymap.chip8_key_for(Key::Pad(PadInput::DPadUp)));
+        assert_eq!(0.5, keymap_from(text, None).unwrap().deadzone);
+        assert!(keymap_from("deadzone = 2\n", None).is_err());
+    }
+
+    #[test]
+    fn test_config_errors() {
+        assert!(keymap_from("[keypad]\nG = \"a\"\n", None).is_err());
+        assert!(keymap_from("[keypad]\n1 = \"nope\"\n", None).is_err());
+        assert!(keymap_from("[hotkeys]\nexplode = \"a\"\n", None).is_err());
+        assert!(keymap_from("layout = \"sideways\"\n", None).is_err());
+    }
+
+    #[test]
+    fn test_parse_key_name() {
+        assert_eq!(Some(Key::Char('q')), parse_key_name("Q"));
+        assert_eq!(Some(Key::Function(10)), parse_key_name("F10"));
+        assert_eq!(Some(Key::PageUp), parse_key_name("Page Up"));
+        assert_eq!(Some(Key::Enter), parse_key_name("Return"));
+        assert_eq!(Some(Key::Char(',')), parse_key_name("Comma"));
+        assert_eq!(None, parse_key_name("F13"));
+        assert_eq!(None, parse_key_name("Keypad 1"));
+        assert_eq!(Some(Key::Pad(PadInput::RightTrigger)), parse_key_name("pad-rt"));
+        assert_eq!("pad-lb", key_name(Key::Pad(PadInput::LeftShoulder)));
+    }
+
+    #[test]
+    fn test_describe_lists_bindings() {
+        let description = Keymap::default().describe();
+        assert!(description.contains("Esc         Quit"));
+        assert!(description.contains(" 1=1        2=2        3=3        C=4"));
+        assert!(description.contains("    5=pad-up/pad-lstick-up"));
+
+        let lines = Keymap::default().binding_lines();
+        assert_eq!("Esc: Quit", lines[0]);
+        assert!(lines.contains(&String::from("Key 5: W/pad-up/pad-lstick-up")));
+    }
+}
@@ -0,0 +1,485 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use chip8_emu::Chip8;
+
+use rand::XorShiftRng;
+
+use frontend::{Key, PadInput, Shades};
+use osd;
+use osd::{Menu, MENU_ENTRIES, MENU_LINE_CHARS};
+use romdb::RomDatabase;
+use runner;
+use runner::DEFAULT_CYCLES_PER_FRAME;
+use LoadOptions;
+
+// Lists the ROMs in a directory, along with the ones played recently, and
+// lets the user pick one to run. The selected ROM runs in the background as a
+// preview, so you can see what it is before starting it. The list can also be
+// shown a page at a time as thumbnails, each taken from a short headless run
+// of its ROM, with the selected one running.
+
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "chip8", "sc8", "xo8"];
+// Files without an extension are only taken for ROMs if they'd fit in memory,
+// as plenty of the classic ones don't have one.
+const MAX_PLAIN_ROM_SIZE: u64 = 3584;
+const MAX_RECENT: usize = 10;
+// Previews skip past the first couple of seconds, which are often blank.
+const PREVIEW_WARM_UP_FRAMES: u32 = 120;
+// Previews are drawn at half brightness, so the list stands out.
+const PREVIEW_SHADE: u8 = 0x80;
+// Thumbnails are the display at half size, four to a page.
+const THUMBNAIL_WIDTH: usize = 32;
+const THUMBNAIL_HEIGHT: usize = 16;
+const THUMBNAIL_COLUMNS: usize = 2;
+
+type Thumbnail = [[bool; THUMBNAIL_WIDTH]; THUMBNAIL_HEIGHT];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LauncherAction {
+    Moved,
+    Load(PathBuf),
+    Close,
+    Quit,
+}
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    is_recent: bool,
+    // Made the first time its page of thumbnails is shown. ROMs that can't be
+    // loaded get a blank one.
+    thumbnail: Option<Thumbnail>,
+}
+
+pub struct Launcher {
+    directory: PathBuf,
+    database: RomDatabase,
+    // Where the recently played list is kept, if anywhere, and what's on it,
+    // newest first.
+    recent_path: Option<PathBuf>,
+    recent: Vec<PathBuf>,
+    entries: Vec<Entry>,
+    selected: usize,
+    // Frames since the selection last moved, for scrolling long names.
+    frames_selected: u32,
+    // The machine that previews and thumbnails run on, started over for each
+    // ROM, and how many instructions a frame the preview runs at. There's no
+    // preview until a ROM's been loaded into it.
+    chip8: Chip8<XorShiftRng>,
+    preview_cycles_per_frame: Option<u32>,
+    shows_thumbnails: bool,
+    is_open: bool,
+}
+
+impl Launcher {
+    pub fn new(directory: &Path, database: RomDatabase, recent_path: Option<PathBuf>) -> Launcher {
+        let recent = recent_path.as_ref().map(|path| load_recent(path)).unwrap_or_default();
+        Launcher {
+            directory: directory.to_path_buf(),
+            database,
+            recent_path,
+            recent,
+            entries: Vec::new(),
+            selected: 0,
+            frames_selected: 0,
+            chip8: Chip8::new_and_init(),
+            preview_cycles_per_frame: None,
+            shows_thumbnails: false,
+            is_open: false,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    // Looks through the directory again, so that new ROMs show up.
+    pub fn open(&mut self) {
+        self.entries = scan(&self.directory, &self.database, &self.recent);
+        self.is_open = true;
+        self.preview_cycles_per_frame = None;
+        self.select(0);
+    }
+
+    pub fn close(&mut self) {
+        self.is_open = false;
+        self.preview_cycles_per_frame = None;
+    }
+
+    // Puts a ROM at the top of the recently played list.
+    pub fn add_recent(&mut self, path: &Path) -> io::Result<()> {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.recent.retain(|recent| *recent != path);
+        self.recent.insert(0, path);
+        self.recent.truncate(MAX_RECENT);
+        match self.recent_path {
+            Some(ref recent_path) => save_recent(recent_path, &self.recent),
+            None => Ok(()),
+        }
+    }
+
+    // Moves the selection, or picks an entry. Quitting rather than closing
+    // is for when there's no game to go back to. Keys the launcher doesn't
+    // use give None, so they can do whatever they usually do.
+    pub fn handle_key(&mut self, key: Key, can_close: bool) -> Option<LauncherAction> {
+        let last = self.entries.len().saturating_sub(1);
+        // Thumbnails go left to right in rows; the list goes a page at a time
+        // sideways.
+        let (row_step, sideways_step) = if self.shows_thumbnails {
+            (THUMBNAIL_COLUMNS, 1)
+        } else {
+            (1, MENU_ENTRIES)
+        };
+        let index = match key {
+            Key::Up | Key::Pad(PadInput::DPadUp) | Key::Pad(PadInput::LeftStickUp) => {
+                self.selected.saturating_sub(row_step)
+            }
+            Key::Down | Key::Pad(PadInput::DPadDown) | Key::Pad(PadInput::LeftStickDown) => {
+                (self.selected + row_step).min(last)
+            }
+            Key::Left | Key::Pad(PadInput::DPadLeft) => self.selected.saturating_sub(sideways_step),
+            Key::Right | Key::Pad(PadInput::DPadRight) => (self.selected + sideways_step).min(last),
+            Key::PageUp => self.selected.saturating_sub(MENU_ENTRIES),
+            Key::PageDown => (self.selected + MENU_ENTRIES).min(last),
+            Key::Tab | Key::Pad(PadInput::X) => {
+                // Loading the preview again makes the thumbnails first.
+                self.shows_thumbnails = !self.shows_thumbnails;
+                self.preview_cycles_per_frame = None;
+                self.selected
+            }
+            Key::Enter | Key::Char(' ') | Key::Pad(PadInput::A) | Key::Pad(PadInput::Start) => {
+                return Some(match self.entries.get(self.selected) {
+                    Some(entry) => LauncherAction::Load(entry.path.clone()),
+                    None => LauncherAction::Moved,
+                });
+            }
+            Key::Escape | Key::Backspace | Key::Pad(PadInput::B) | Key::Pad(PadInput::Back) => {
+                return Some(if can_close { LauncherAction::Close } else { LauncherAction::Quit });
+            }
+            _ => return None,
+        };
+        self.select(index);
+        Some(LauncherAction::Moved)
+    }
+
+    // The part of the list that's on screen, under a heading. Thumbnails only
+    // have the selected ROM's name over them.
+    pub fn menu(&self) -> Menu {
+        if self.shows_thumbnails {
+            if let Some(entry) = self.entries.get(self.selected) {
+                let offset = osd::scroll_offset(&entry.name, self.frames_selected);
+                return Menu {
+                    lines: vec![osd::truncate(&entry.name, offset, MENU_LINE_CHARS)],
+                    selected: None,
+                };
+            }
+        }
+        if self.entries.is_empty() {
+            let directory = self.directory.file_name().map_or_else(
+                || self.directory.display().to_string(),
+                |name| name.to_string_lossy().into_owned());
+            return Menu {
+                lines: vec![String::from("No ROMs in"), osd::truncate(&directory, 0, MENU_LINE_CHARS)],
+                selected: None,
+            };
+        }
+
+        let heading = format!("ROM {}/{}", self.selected + 1, self.entries.len());
+        let names: Vec<String> = self.entries.iter()
+            .map(|entry| if entry.is_recent { format!("*{}", entry.name) } else { entry.name.clone() })
+            .collect();
+        osd::list_menu(heading, &names, self.selected, self.frames_selected)
+    }
+
+    // Runs the preview on by a frame, and returns it for the background.
+    pub fn run_preview(&mut self) -> Shades {
+        self.frames_selected += 1;
+        if let Some(cycles_per_frame) = self.preview_cycles_per_frame {
+            runner::execute_for_frame(&mut self.chip8, cycles_per_frame);
+        }
+        if self.shows_thumbnails {
+            return self.thumbnail_page();
+        }
+        let mut shades = [[0; 64]; 32];
+        if self.preview_cycles_per_frame.is_some() {
+            for (shade_row, row) in shades.iter_mut().zip(self.chip8.get_screen_ref().iter()) {
+                for (shade, &pixel) in shade_row.iter_mut().zip(row.iter()) {
+                    *shade = if pixel { PREVIEW_SHADE } else { 0 };
+                }
+            }
+        }
+        shades
+    }
+
+    // The page of thumbnails with the selected ROM on it. The selected one is
+    // the running preview, at full brightness.
+    fn thumbnail_page(&self) -> Shades {
+        let mut shades = [[0; 64]; 32];
+        let first = page_start(self.selected);
+        for (index, entry) in self.entries.iter().enumerate().skip(first).take(MENU_ENTRIES) {
+            let (thumbnail, shade) = if index == self.selected && self.preview_cycles_per_frame.is_some() {
+                (shrink(self.chip8.get_screen_ref()), 0xFF)
+            } else {
+                match entry.thumbnail {
+                    Some(thumbnail) => (thumbnail, PREVIEW_SHADE),
+                    None => continue,
+                }
+            };
+            let left = (index - first) % THUMBNAIL_COLUMNS * THUMBNAIL_WIDTH;
+            let top = (index - first) / THUMBNAIL_COLUMNS * THUMBNAIL_HEIGHT;
+            for (y, row) in thumbnail.iter().enumerate() {
+                for (x, &pixel) in row.iter().enumerate() {
+                    if pixel {
+                        shades[top + y][left + x] = shade;
+                    }
+                }
+            }
+        }
+        shades
+    }
+
+    fn select(&mut self, index: usize) {
+        if index == self.selected && self.preview_cycles_per_frame.is_some() {
+            return;
+        }
+        self.selected = index;
+        self.frames_selected = 0;
+        self.make_thumbnails();
+        // After the thumbnails, since they use the same machine.
+        self.preview_cycles_per_frame = match self.entries.get(index) {
+            Some(entry) => warm_up(&mut self.chip8, &entry.path, &self.database),
+            None => None,
+        };
+    }
+
+    // Runs each ROM on the selected one's page that doesn't have a thumbnail
+    // yet, if the thumbnails are showing.
+    fn make_thumbnails(&mut self) {
+        if !self.shows_thumbnails {
+            return;
+        }
+        let first = page_start(self.selected);
+        for entry in self.entries.iter_mut().skip(first).take(MENU_ENTRIES) {
+            if entry.thumbnail.is_none() {
+                let is_loaded = warm_up(&mut self.chip8, &entry.path, &self.database).is_some();
+                entry.thumbnail = Some(if is_loaded {
+                    shrink(self.chip8.get_screen_ref())
+                } else {
+                    [[false; THUMBNAIL_WIDTH]; THUMBNAIL_HEIGHT]
+                });
+            }
+        }
+    }
+}
+
+fn page_start(index: usize) -> usize {
+    index - index % MENU_ENTRIES
+}
+
+// Starts the machine over with the ROM, and runs it past the first couple of
+// seconds. Returns how many instructions a frame it runs at, or None if it
+// can't be loaded.
+fn warm_up(chip8: &mut Chip8<XorShiftRng>, path: &Path, database: &RomDatabase) -> Option<u32> {
+    let rom = ::read_rom(path, database).ok()?;
+    ::load_rom(chip8, &rom, LoadOptions::default());
+    let cycles_per_frame = rom.info.and_then(|info| info.tickrate).unwrap_or(DEFAULT_CYCLES_PER_FRAME);
+    for _ in 0..PREVIEW_WARM_UP_FRAMES {
+        runner::execute_for_frame(chip8, cycles_per_frame);
+    }
+    Some(cycles_per_frame)
+}
+
+// Halves the display each way. A thumbnail pixel is lit if any of the four it
+// covers are, so that thin lines don't disappear.
+fn shrink(screen: &[[bool; 64]; 32]) -> Thumbnail {
+    let mut thumbnail = [[false; THUMBNAIL_WIDTH]; THUMBNAIL_HEIGHT];
+    for (y, row) in screen.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            thumbnail[y / 2][x / 2] |= pixel;
+        }
+    }
+    thumbnail
+}
+
+// The recent ROMs that are still around come first, then everything in the
+// directory by name.
+fn scan(directory: &Path, database: &RomDatabase, recent: &[PathBuf]) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = recent.iter()
+        .filter(|path| path.is_file())
+        .map(|path| Entry {
+            path: path.clone(),
+            name: rom_name(path, database),
+            is_recent: true,
+            thumbnail: None,
+        })
+        .collect();
+
+    let mut found: Vec<Entry> = fs::read_dir(directory).into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|dir_entry| dir_entry.metadata().map(|metadata| {
+            metadata.is_file() && is_rom_file(&dir_entry.path(), metadata.len())
+        }).unwrap_or(false))
+        .map(|dir_entry| {
+            let path = dir_entry.path();
+            Entry { name: rom_name(&path, database), path, is_recent: false, thumbnail: None }
+        })
+        .collect();
+    found.sort_by_key(|entry| entry.name.to_lowercase());
+    entries.extend(found);
+    entries
+}
+
+fn is_rom_file(path: &Path, size: u64) -> bool {
+    match path.extension() {
+        Some(extension) => {
+            let extension = extension.to_string_lossy().to_lowercase();
+            ROM_EXTENSIONS.contains(&extension.as_str())
+        }
+        None => {
+            let is_hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            !is_hidden && size > 0 && size <= MAX_PLAIN_ROM_SIZE
+        }
+    }
+}
+
+// The title from the ROM database, or failing that the file name.
+fn rom_name(path: &Path, database: &RomDatabase) -> String {
+    let mut rom = Vec::new();
+    let title = File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut rom))
+        .ok()
+        .and_then(|_| database.find(&rom).ok())
+        .and_then(|info| info.and_then(|info| info.title));
+    title.unwrap_or_else(|| {
+        path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+    })
+}
+
+// One path per line.
+fn load_recent(path: &Path) -> Vec<PathBuf> {
+    let mut text = String::new();
+    if File::open(path).and_then(|mut file| file.read_to_string(&mut text)).is_err() {
+        return Vec::new();
+    }
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .take(MAX_RECENT)
+        .collect()
+}
+
+fn save_recent(path: &Path, recent: &[PathBuf]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    for recent_path in recent {
+        writeln!(file, "{}", recent_path.display())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use frontend::Key;
+    use romdb::RomDatabase;
+    use super::{Entry, Launcher, LauncherAction};
+
+    fn launcher_with(names: &[&str]) -> Launcher {
+        let mut launcher = Launcher::new(Path::new("roms"), RomDatabase::new(), None);
+        launcher.entries = names.iter().map(|name| Entry {
+            path: PathBuf::from(format!("roms/{}.ch8", name)),
+            name: name.to_string(),
+            is_recent: false,
+            thumbnail: None,
+        }).collect();
+        launcher.is_open = true;
+        launcher
+    }
+
+    #[test]
+    fn test_moving_through_the_list() {
+        let mut launcher = launcher_with(&["Blinky", "Brix", "Pong", "Tetris", "UFO", "Vers"]);
+        assert_eq!(vec!["ROM 1/6", "Blinky", "Brix", "Pong", "Tetris"], launcher.menu().lines);
+        assert_eq!(Some(1), launcher.menu().selected);
+
+        assert_eq!(Some(LauncherAction::Moved), launcher.handle_key(Key::PageDown, true));
+        assert_eq!(None, launcher.handle_key(Key::Char('q'), true));
+        launcher.handle_key(Key::Down, true);
+        launcher.handle_key(Key::Down, true);
+        let menu = launcher.menu();
+        assert_eq!(vec!["ROM 6/6", "Pong", "Tetris", "UFO", "Vers"], menu.lines);
+        assert_eq!(Some(4), menu.selected);
+
+        assert_eq!(Some(LauncherAction::Load(PathBuf::from("roms/Vers.ch8"))),
+                   launcher.handle_key(Key::Enter, true));
+        assert_eq!(Some(LauncherAction::Close), launcher.handle_key(Key::Escape, true));
+        assert_eq!(Some(LauncherAction::Quit), launcher.handle_key(Key::Escape, false));
+    }
+
+    #[test]
+    fn test_long_names_scroll_when_selected() {
+        let mut launcher = launcher_with(&["Space Invaders [David Winter]"]);
+        assert_eq!("Space Invaders ", launcher.menu().lines[1]);
+        // A second's pause, and then six characters along.
+        for _ in 0..60 + 8 * 6 {
+            launcher.run_preview();
+        }
+        assert_eq!("Invaders [David", launcher.menu().lines[1]);
+    }
+
+    #[test]
+    fn test_thumbnails_go_a_page_at_a_time() {
+        let mut launcher = launcher_with(&["Blinky", "Brix", "Pong", "Tetris", "UFO"]);
+        // Each thumbnail has its top left pixel lit.
+        for entry in launcher.entries.iter_mut() {
+            let mut thumbnail = [[false; 32]; 16];
+            thumbnail[0][0] = true;
+            entry.thumbnail = Some(thumbnail);
+        }
+        assert_eq!(Some(LauncherAction::Moved), launcher.handle_key(Key::Tab, true));
+        assert_eq!(vec!["Blinky"], launcher.menu().lines);
+        let shades = launcher.run_preview();
+        assert_eq!([0x80, 0x80, 0x80, 0x80], [shades[0][0], shades[0][32], shades[16][0], shades[16][32]]);
+
+        // Down goes to the next row, and Right to the next thumbnail.
+        launcher.handle_key(Key::Down, true);
+        assert_eq!(2, launcher.selected);
+        launcher.handle_key(Key::Right, true);
+        launcher.handle_key(Key::Right, true);
+        assert_eq!(vec!["UFO"], launcher.menu().lines);
+        let shades = launcher.run_preview();
+        assert_eq!([0x80, 0, 0, 0], [shades[0][0], shades[0][32], shades[16][0], shades[16][32]]);
+
+        launcher.handle_key(Key::Tab, true);
+        assert_eq!("ROM 5/5", launcher.menu().lines[0]);
+    }
+
+    #[test]
+    fn test_thumbnails_keep_thin_lines() {
+        let mut screen = [[false; 64]; 32];
+        screen[3][..].copy_from_slice(&[true; 64]);
+        screen[10][7] = true;
+        let thumbnail = super::shrink(&screen);
+        assert!(thumbnail[1].iter().all(|&pixel| pixel));
+        assert!(thumbnail[5][3]);
+        assert_eq!(33, thumbnail.iter().flat_map(|row| row.iter()).filter(|&&pixel| pixel).count());
+    }
+
+    #[test]
+    fn test_rom_files() {
+        assert!(super::is_rom_file(Path::new("roms/pong.ch8"), 246));
+        assert!(super::is_rom_file(Path::new("roms/PONG.C8"), 246));
+        assert!(super::is_rom_file(Path::new("roms/PONG"), 246));
+        assert!(!super::is_rom_file(Path::new("roms/README.txt"), 246));
+        assert!(!super::is_rom_file(Path::new("roms/.DS_Store"), 246));
+        assert!(!super::is_rom_file(Path::new("roms/BIGFILE"), 100000));
+    }
+}
@@ -0,0 +1,101 @@
+extern crate chip8_emu;
+extern crate rand;
+#[cfg(feature = "sdl2")]
+extern crate sdl2;
+#[macro_use]
+extern crate serde_json;
+extern crate sha1_smol;
+extern crate toml;
+
+pub mod breakpoint;
+pub mod compare;
+pub mod crt;
+pub mod dap;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod expression;
+pub mod filter;
+pub mod frontend;
+pub mod gamepad;
+pub mod gdb;
+pub mod headless;
+pub mod keymap;
+pub mod launcher;
+pub mod osd;
+pub mod palette;
+pub mod recording;
+pub mod reload;
+pub mod romdb;
+pub mod runner;
+pub mod settings;
+#[cfg(feature = "sdl2")]
+pub mod sdl;
+pub mod sound;
+pub mod speed;
+pub mod symbols;
+pub mod terminal;
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+
+use chip8_emu::{Chip8, Quirks, ResetKind};
+
+use rand::{Rng, XorShiftRng};
+
+use romdb::{RomDatabase, RomInfo};
+
+// A ROM read from disk, along with what the ROM database knows about it.
+pub struct RomFile {
+    pub bytes: Vec<u8>,
+    pub info: Option<RomInfo>,
+    // Why the database's entry for the ROM couldn't be used, if it couldn't.
+    // The ROM is loaded without it.
+    pub database_error: Option<String>,
+}
+
+// Settings from the user, which take precedence over the ROM database.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions {
+    pub quirks: Option<Quirks>,
+    pub start_address: Option<u16>,
+    // Seeds the random number generator, so that runs can be repeated.
+    pub seed: Option<u64>,
+}
+
+pub fn read_rom(path: &Path, database: &RomDatabase) -> io::Result<RomFile> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let (info, database_error) = match database.find(&bytes) {
+        Ok(info) => (info, None),
+        Err(error) => (None, Some(error)),
+    };
+    Ok(RomFile { bytes, info, database_error })
+}
+
+// A machine with nothing loaded yet.
+pub fn new_chip8(options: LoadOptions) -> Chip8<XorShiftRng> {
+    match options.seed {
+        Some(seed) => Chip8::new_and_init_with_seed(seed),
+        None => Chip8::new_and_init(),
+    }
+}
+
+// Puts the ROM into the machine and starts it over, as if it had been switched
+// off and on with the ROM in. Returns where in memory the ROM went.
+pub fn load_rom<R: Rng>(chip8: &mut Chip8<R>, rom: &RomFile, options: LoadOptions) -> Range<usize> {
+    let info = rom.info.as_ref();
+    let quirks = options.quirks.or_else(|| info.and_then(|info| info.quirks));
+    chip8.set_quirks(quirks.unwrap_or_default());
+    // Anything that doesn't fit into memory is cut off.
+    let start_address = options.start_address
+        .or_else(|| info.and_then(|info| info.start_address))
+        .unwrap_or(0x200);
+    chip8.load_rom_at(&rom.bytes, start_address);
+    chip8.reset(ResetKind::Hard);
+    let start = (start_address & 0xFFF) as usize;
+    start..(start + rom.bytes.len()).min(chip8.memory().len())
+}
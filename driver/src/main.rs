@@ -1,178 +1,434 @@
 extern crate chip8_emu;
-extern crate sdl2;
+extern crate chip8_emu_driver;
 extern crate rand;
 
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::process;
 
 use chip8_emu::Chip8;
 
-use rand::{Rng, XorShiftRng};
+use chip8_emu_driver::LoadOptions;
+use chip8_emu_driver::compare;
+use chip8_emu_driver::compare::{Comparison, MachineSpec};
+use chip8_emu_driver::display::DisplaySettings;
+use chip8_emu_driver::filter::FilterSettings;
+use chip8_emu_driver::frontend::AudioSink;
+use chip8_emu_driver::dap::DapServer;
+use chip8_emu_driver::debugger::Debugger;
+use chip8_emu_driver::gdb::GdbStub;
+use chip8_emu_driver::headless::{HeadlessInput, HeadlessVideo, NullAudio};
+use chip8_emu_driver::keymap;
+use chip8_emu_driver::keymap::Keymap;
+use chip8_emu_driver::launcher::Launcher;
+use chip8_emu_driver::osd::OsdSettings;
+use chip8_emu_driver::palette;
+use chip8_emu_driver::palette::Palette;
+use chip8_emu_driver::reload::ReloadMode;
+use chip8_emu_driver::romdb::{RomDatabase, RomInfo};
+use chip8_emu_driver::runner::{Game, GameLoader, Runner};
+use chip8_emu_driver::settings;
+use chip8_emu_driver::settings::Settings;
+use chip8_emu_driver::sound::SoundControls;
+use chip8_emu_driver::speed::SpeedSettings;
+use chip8_emu_driver::terminal;
+use chip8_emu_driver::terminal::TerminalStyle;
 
-use sdl2::EventPump;
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Renderer;
+use rand::XorShiftRng;
+
+// Sample rate for recordings when there's no audio device to ask.
+const HEADLESS_SAMPLE_RATE: u32 = 44100;
+const SETTINGS_FILE: &str = "settings.toml";
+const ROM_DB_FILE: &str = "programs.json";
+const ROM_OVERRIDES_FILE: &str = "rom-overrides.json";
+const RECENT_FILE: &str = "recent.txt";
+
+// What goes into loading each ROM.
+#[derive(Clone)]
+struct GameSettings {
+    database: RomDatabase,
+    load_options: LoadOptions,
+    keymap_path: Option<PathBuf>,
+    // Instructions per frame from the command line, which beat the ROM
+    // database.
+    cycles_per_frame: Option<u32>,
+}
+
+// Runner settings that are the same whichever frontend we use.
+struct RunOptions {
+    // The machine to run, with the ROM from the command line in it if there
+    // was one.
+    chip8: Chip8<XorShiftRng>,
+    game: Option<Game>,
+    // Whether messages also go to stderr.
+    prints_messages: bool,
+    keymap: Keymap,
+    // For picking another ROM, or None when there's no one to pick.
+    launcher: Option<Launcher>,
+    loader: Option<GameLoader<XorShiftRng>>,
+    reload_mode: ReloadMode,
+    comparison: Option<Comparison<XorShiftRng>>,
+    debugger: Option<Box<dyn Debugger<XorShiftRng>>>,
+    filter_settings: FilterSettings,
+    osd: OsdSettings,
+    palettes: Vec<Palette>,
+    palette_index: usize,
+    speed: SpeedSettings,
+    start_paused: bool,
+    record_path: Option<PathBuf>,
+    record_audio_path: Option<PathBuf>,
+    frame_limit: Option<u64>,
+}
 
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: chip8_emu_driver rom");
+    let config_dir = settings::config_dir();
+    let settings_path = config_dir.as_ref().map(|dir| dir.join(SETTINGS_FILE));
+
+    let mut settings = Settings::default();
+    if let Some(ref path) = settings_path {
+        if path.exists() {
+            if let Err(error) = settings.load_file(path) {
+                exit_with_error(&format!("Couldn't read settings from {}: {}", path.display(), error));
+            }
+        } else if let Err(error) = Settings::default().save_file(path) {
+            // Not much of a problem; there just won't be a file to edit.
+            println!("Couldn't create {}: {}", path.display(), error);
+        }
+    }
+    if let Err(error) = settings.parse_args(env::args().skip(1)) {
+        exit_with_error(&error);
+    }
+
+    if settings.show_help {
+        print_usage(settings_path.as_deref());
         return;
     }
+    if settings.show_version {
+        println!("chip8_emu_driver {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    if settings.save_settings || settings.reset_settings {
+        let path = match settings_path {
+            Some(ref path) => path,
+            None => exit_with_error("Couldn't find a config directory to save settings in"),
+        };
+        let result = if settings.reset_settings {
+            Settings::default().save_file(path)
+        } else {
+            settings.save_file(path)
+        };
+        match result {
+            Ok(()) => println!("Saved settings to {}", path.display()),
+            Err(error) => exit_with_error(&format!("Couldn't save settings to {}: {}", path.display(), error)),
+        }
+        if settings.rom_path.is_none() {
+            return;
+        }
+    }
 
-    let mut chip8 = init_chip8_with_rom(&args[1]);
+    // The ROM database and overrides come from the config directory, unless
+    // we're told otherwise.
+    let default_path = |name: &str| {
+        config_dir.as_ref().map(|dir| dir.join(name)).filter(|path| path.exists())
+    };
+    let rom_db_paths = settings.rom_db_path.clone().or_else(|| default_path(ROM_DB_FILE)).into_iter()
+        .chain(settings.rom_overrides_path.clone().or_else(|| default_path(ROM_OVERRIDES_FILE)));
+    let mut database = RomDatabase::new();
+    for path in rom_db_paths {
+        if let Err(error) = database.load(&path) {
+            exit_with_error(&format!("Couldn't load the ROM database from {}: {}", path.display(), error));
+        }
+    }
 
-    let sdl = sdl2::init().unwrap();
-    let mut event_pump = sdl.event_pump().unwrap();
+    let game_settings = GameSettings {
+        database: database.clone(),
+        load_options: LoadOptions {
+            quirks: settings.quirks,
+            start_address: settings.load_address,
+            seed: settings.seed,
+        },
+        keymap_path: settings.keymap_path.clone(),
+        cycles_per_frame: settings.cycles_per_frame,
+    };
+    // Without a ROM, we start in the launcher.
+    let mut chip8 = chip8_emu_driver::new_chip8(game_settings.load_options);
+    let game = settings.rom_path.as_ref().map(|path| {
+        load_game(path, &mut chip8, &game_settings).unwrap_or_else(|error| exit_with_error(&error))
+    });
+    if game.is_none() && settings.frontend == "headless" {
+        exit_with_error("No ROM given");
+    }
+    let rom_info = game.as_ref().map(|game| game.info.clone()).unwrap_or_default();
+    // The bindings to go with the game, or for the launcher until one's
+    // picked.
+    let keymap = match game {
+        Some(ref game) => game.keymap.clone(),
+        None => load_keymap(&game_settings, None, &rom_info).unwrap_or_else(|error| exit_with_error(&error)),
+    };
 
-    let audio = sdl.audio().unwrap();
-    let video = sdl.video().unwrap();
-    let window = video.window("Chip 8 emu", 640, 320).build().unwrap();
-    let mut renderer = window.renderer().accelerated().present_vsync().build().unwrap();
+    // The other machines start out just like the first, apart from what
+    // they're told.
+    let comparison = if settings.compare.is_empty() {
+        None
+    } else {
+        let path = settings.rom_path.as_ref().unwrap_or_else(|| exit_with_error("--compare needs a ROM"));
+        let games = settings.compare.iter()
+            .map(|machine| {
+                let machine_settings = machine_settings(&game_settings, machine);
+                let mut chip8 = chip8_emu_driver::new_chip8(machine_settings.load_options);
+                load_game(path, &mut chip8, &machine_settings).map(|game| (chip8, game))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|error| exit_with_error(&error));
+        Some(games)
+    };
 
-    // Because audio playback happens on a separate thread, we need to use a
-    // thread-safe flag to tell the callback whether it should be playing
-    // anything or not.
-    let should_play_flag = AtomicBool::new(false);
+    let debugger: Option<Box<dyn Debugger<XorShiftRng>>> = match (settings.gdb_port, settings.dap_port) {
+        (None, None) => None,
+        (Some(_), Some(_)) => exit_with_error("--gdb and --dap can't be used together"),
+        _ if comparison.is_some() => exit_with_error("Debugging can't be used with --compare"),
+        (Some(port), None) => Some(Box::new(GdbStub::listen(port).unwrap_or_else(|error| {
+            exit_with_error(&format!("Couldn't listen for GDB on port {}: {}", port, error))
+        }))),
+        (None, Some(port)) => Some(Box::new(DapServer::listen(port).unwrap_or_else(|error| {
+            exit_with_error(&format!("Couldn't listen for an editor on port {}: {}", port, error))
+        }))),
+    };
 
-    struct SquareWaveGenerator<'a> {
-        frequency: f32,
-        phase: f32,
-        should_play_flag: &'a AtomicBool,
+    let mut display_settings = settings.display;
+    if let Some(ref games) = comparison {
+        display_settings.grid = compare::grid_size(games.len() + 1);
+    }
+    if let Some(rotation) = settings.rotation.or(rom_info.rotation) {
+        display_settings.rotation = rotation;
     }
 
-    impl<'a> AudioCallback for SquareWaveGenerator<'a> {
-        type Channel = i16;
+    let mut filter_settings = FilterSettings::new(settings.display_filter);
+    if let Some(strength) = settings.filter_strength {
+        filter_settings.strength = strength;
+    }
 
-        fn callback(&mut self, out: &mut [i16]) {
-            for x in out.iter_mut() {
-                let volume = if self.should_play_flag.load(Ordering::Acquire) { 3000 } else { 0 };
-                *x = if self.phase <= 0.5 { volume } else { -volume };                
-                self.phase = (self.phase + self.frequency) % 1.0;
+    let mut palettes = palette::built_in_palettes();
+    if let Some(ref path) = settings.palette_path {
+        match palette::load_palettes(path) {
+            Ok(loaded) => palettes.extend(loaded),
+            Err(error) => exit_with_error(&format!("Couldn't load palettes from {}: {}", path.display(), error)),
+        }
+    }
+    let mut palette_index = 0;
+    if let Some(ref name) = settings.palette_name {
+        match palette::find_palette(&palettes, name) {
+            Some(found) => {
+                palette_index = palettes.iter().position(|palette| *palette == found)
+                    .unwrap_or_else(|| {
+                        palettes.push(found);
+                        palettes.len() - 1
+                    });
             }
+            None => exit_with_error(&format!("Unknown palette: {}", name)),
         }
+    } else if let Some(colors) = rom_info.colors {
+        palettes.push(Palette {
+            name: rom_info.title.clone().unwrap_or_else(|| String::from("rom")),
+            colors,
+        });
+        palette_index = palettes.len() - 1;
     }
 
-    let requested_spec = AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1),
-        samples: None,
-    };
+    // The launcher looks next to the ROM we were given, unless told where.
+    let rom_dir = settings.rom_dir.clone()
+        .or_else(|| settings.rom_path.as_ref().and_then(|path| path.parent()).map(Path::to_path_buf))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let recent_path = config_dir.as_ref().map(|dir| dir.join(RECENT_FILE));
+    let launcher = Launcher::new(&rom_dir, database, recent_path);
+    let loader: GameLoader<XorShiftRng> = Box::new(move |path: &Path, chip8: &mut Chip8<XorShiftRng>| {
+        load_game(path, chip8, &game_settings)
+    });
 
-    let audio_device = audio.open_playback(None, &requested_spec, |spec| {
-            SquareWaveGenerator {
-                frequency: 125.0 / spec.freq as f32,
-                phase: 0.0,
-                should_play_flag: &should_play_flag,
-            }
-        })
-        .unwrap();
-    audio_device.resume();
+    let mut options = RunOptions {
+        chip8,
+        game,
+        prints_messages: true,
+        keymap,
+        launcher: Some(launcher),
+        loader: Some(loader),
+        reload_mode: settings.reload_mode,
+        comparison: comparison.map(|games| Comparison::new(games, filter_settings)),
+        debugger,
+        filter_settings,
+        osd: settings.osd,
+        palettes,
+        palette_index,
+        speed: settings.speed,
+        start_paused: settings.start_paused,
+        record_path: settings.record_path,
+        record_audio_path: settings.record_audio_path,
+        frame_limit: settings.frame_limit,
+    };
+    let sound = settings.sound;
+    if options.comparison.is_some() {
+        // Every machine would need the new ROM, with its own settings.
+        options.launcher = None;
+        options.loader = None;
+        options.reload_mode = ReloadMode::Off;
+    }
 
-    loop {
-        if let EventSignal::Quit = handle_events(&mut chip8, &mut event_pump) {
-            // We got a quit signal, time to exit.
-            return;
+    match settings.frontend.as_str() {
+        "headless" => {
+            // Without a limit, a headless run would never finish.
+            options.frame_limit = Some(options.frame_limit.unwrap_or(60 * 10));
+            // Runs should come out the same every time.
+            options.launcher = None;
+            options.reload_mode = ReloadMode::Off;
+            let mut runner = new_runner(options, sound, HEADLESS_SAMPLE_RATE);
+            let mut video = HeadlessVideo::new();
+            runner.run(&mut HeadlessInput::new(), &mut video, &mut NullAudio::new(HEADLESS_SAMPLE_RATE));
+            println!("Ran {} frames, {} of which changed the display",
+                     video.frames_presented, video.frames_changed);
         }
-
-        execute_for_frame(&mut chip8);
-        should_play_flag.store(chip8.should_play_sound(), Ordering::Release);
-        draw_emu_screen(&mut chip8, &mut renderer);
+        "terminal" | "terminal-braille" => {
+            let style = if settings.frontend == "terminal" {
+                TerminalStyle::HalfBlock
+            } else {
+                TerminalStyle::Braille
+            };
+            let (mut input, mut video, mut audio) = match terminal::init(style) {
+                Ok(terminal) => terminal,
+                Err(error) => exit_with_error(&format!("Couldn't set up the terminal: {}", error)),
+            };
+            // Anything printed would land in the middle of the picture.
+            options.prints_messages = false;
+            let mut runner = new_runner(options, sound, audio.sample_rate());
+            runner.run(&mut input, &mut video, &mut audio);
+        }
+        _ => run_sdl(sound, display_settings, options),
     }
 }
 
-fn init_chip8_with_rom(path: &String) -> Chip8<XorShiftRng> {
-    let mut buffer = [0u8; 3584];
-    let mut file = File::open(path).unwrap();
-    file.read(&mut buffer[..]).unwrap();
+// Loads a ROM into the machine with everything that goes with it. Used for
+// the ROM given on the command line, and for any picked in the launcher later.
+fn load_game(path: &Path, chip8: &mut Chip8<XorShiftRng>, settings: &GameSettings) -> Result<Game, String> {
+    let rom = chip8_emu_driver::read_rom(path, &settings.database)
+        .map_err(|error| format!("Couldn't load {}: {}", path.display(), error))?;
+    let info = rom.info.clone().unwrap_or_default();
+    let rom_name = path.file_name().and_then(|name| name.to_str());
+    // Before the machine's touched, so that the old game carries on if
+    // there's a problem.
+    let keymap = load_keymap(settings, rom_name, &info)?;
+    let program = chip8_emu_driver::load_rom(chip8, &rom, settings.load_options);
+    Ok(Game {
+        path: path.to_path_buf(),
+        cycles_per_frame: settings.cycles_per_frame.or(info.tickrate),
+        program,
+        database_error: rom.database_error,
+        info,
+        keymap,
+    })
+}
 
-    let mut chip8 = Chip8::new_and_init();
-    chip8.load_rom(&buffer);
-    return chip8;
+fn machine_settings(settings: &GameSettings, machine: &MachineSpec) -> GameSettings {
+    let mut settings = settings.clone();
+    if let Some(quirks) = machine.quirks {
+        settings.load_options.quirks = Some(quirks);
+    }
+    if let Some(seed) = machine.seed {
+        settings.load_options.seed = Some(seed);
+    }
+    if let Some(cycles_per_frame) = machine.cycles_per_frame {
+        settings.cycles_per_frame = Some(cycles_per_frame);
+    }
+    settings
 }
 
-enum EventSignal {
-    Quit,
-    DoNothing,
+// The database's bindings go first, so that the keymap file can override
+// them.
+fn load_keymap(settings: &GameSettings, rom_name: Option<&str>, info: &RomInfo) -> Result<Keymap, String> {
+    let mut keymap = Keymap::default();
+    info.apply_keys(&mut keymap);
+    match settings.keymap_path {
+        Some(ref path) => keymap::load(path, rom_name, keymap)
+            .map_err(|error| format!("Couldn't load the keymap from {}: {}", path.display(), error)),
+        None => Ok(keymap),
+    }
 }
 
-fn handle_events<R: Rng>(chip8: &mut Chip8<R>, event_pump: &mut EventPump) -> EventSignal {
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. } => {
-                return EventSignal::Quit;
-            }
-            Event::KeyDown { keycode: Some(key), .. } => {
-                pass_key_to_emu(chip8, key, true);
-            }
-            Event::KeyUp { keycode: Some(key), .. } => {
-                pass_key_to_emu(chip8, key, false);
-            }
-            _ => {
-                // Skip this event.
-            }
-        }
+fn print_usage(settings_path: Option<&Path>) {
+    println!("Usage: chip8_emu_driver [options] [rom]");
+    println!();
+    println!("Without a ROM, pick one from a list.");
+    println!();
+    println!("Options:");
+    println!("{}", settings::options_help());
+    println!();
+    if let Some(path) = settings_path {
+        println!("Options can also be set in {}.", path.display());
+        println!();
     }
+    println!("Default keys:");
+    println!("{}", Keymap::default().describe());
+}
 
-    return EventSignal::DoNothing;
+// Problems with the command line or the files it points to.
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("Run with --help for a list of options.");
+    process::exit(2);
 }
 
-fn pass_key_to_emu<R: Rng>(chip8: &mut Chip8<R>, key: Keycode, is_pressed: bool) {
-    match key {
-        Keycode::Num1       => chip8.set_key_state(0x1, is_pressed),
-        Keycode::Num2       => chip8.set_key_state(0x2, is_pressed),
-        Keycode::Num3       => chip8.set_key_state(0x3, is_pressed),
-        Keycode::Num4       => chip8.set_key_state(0xC, is_pressed),
-        Keycode::Q          => chip8.set_key_state(0x4, is_pressed),
-        Keycode::W          => chip8.set_key_state(0x5, is_pressed),
-        Keycode::E          => chip8.set_key_state(0x6, is_pressed),
-        Keycode::R          => chip8.set_key_state(0xD, is_pressed),
-        Keycode::A          => chip8.set_key_state(0x7, is_pressed),
-        Keycode::S          => chip8.set_key_state(0x8, is_pressed),
-        Keycode::D          => chip8.set_key_state(0x9, is_pressed),
-        Keycode::F          => chip8.set_key_state(0xE, is_pressed),
-        Keycode::Z          => chip8.set_key_state(0xA, is_pressed),
-        Keycode::X          => chip8.set_key_state(0x0, is_pressed),
-        Keycode::C          => chip8.set_key_state(0xB, is_pressed),
-        Keycode::V          => chip8.set_key_state(0xF, is_pressed),
-        _                   => {},
+fn new_runner(options: RunOptions, sound: SoundControls, sample_rate: u32) -> Runner<XorShiftRng> {
+    let mut runner = Runner::new(options.chip8, sound, sample_rate);
+    runner.set_prints_messages(options.prints_messages);
+    runner.set_display_filter(options.filter_settings);
+    runner.set_osd(options.osd);
+    // Before recording, so that recordings use the right colours.
+    runner.set_palettes(options.palettes, options.palette_index);
+    runner.set_frame_limit(options.frame_limit);
+    runner.set_speed(options.speed);
+    if let Some(launcher) = options.launcher {
+        runner.set_launcher(launcher);
+    }
+    if let Some(loader) = options.loader {
+        runner.set_loader(loader);
+    }
+    if let Some(comparison) = options.comparison {
+        runner.set_comparison(comparison);
+    }
+    if let Some(debugger) = options.debugger {
+        runner.set_debugger(debugger);
+    }
+    // Before loading the game, so that it's watched from the start.
+    runner.set_reload_mode(options.reload_mode);
+    runner.set_keymap(options.keymap);
+    match options.game {
+        Some(game) => runner.load_game(game),
+        None => runner.open_launcher(false),
     }
+    runner.set_paused(options.start_paused);
+    if let Some(path) = options.record_path {
+        runner.start_recording(&path);
+    }
+    if let Some(path) = options.record_audio_path {
+        runner.start_audio_recording(&path);
+    }
+    runner
 }
 
-// Note: The execution rate and timers are frame-rate dependent.
-// An improvement would be to measure elapsed time and execute based on that.
+#[cfg(feature = "sdl2")]
+fn run_sdl(sound: SoundControls,
+           display_settings: DisplaySettings,
+           options: RunOptions) {
+    use chip8_emu_driver::sdl;
 
-fn execute_for_frame<R: Rng>(chip8: &mut Chip8<R>) {
-    // Execute 10 opcodes every frame
-    for _ in 0..10 {
-        chip8.execute_next_opcode();
-    }
-    // Timers should execute at 60Hz.
-    chip8.update_timers();
+    let (mut input, mut video, mut audio) = sdl::init(display_settings);
+    let mut runner = new_runner(options, sound, audio.sample_rate());
+    runner.set_osd_rotation(display_settings.rotation);
+    runner.run(&mut input, &mut video, &mut audio);
 }
 
-fn draw_emu_screen<R: Rng>(chip8: &mut Chip8<R>, renderer: &mut Renderer) {
-    // Note that we're not making use of the emu draw flag since we're always
-    // drawing something on each frame.
-    renderer.clear();
-    let emu_screen = chip8.get_screen_ref();
-    for y in 0..32 {
-        for x in 0..64 {
-            let is_active_cell = emu_screen[y][x];
-            if is_active_cell {
-                renderer.set_draw_color(Color::RGB(255, 255, 224));
-            } else {
-                renderer.set_draw_color(Color::RGB(0, 0, 0));
-            }
-            let rect = Rect::new(x as i32 * 10, y as i32 * 10, 10, 10);
-            renderer.fill_rect(rect).unwrap();
-        }
-    }
-    renderer.present();
+#[cfg(not(feature = "sdl2"))]
+fn run_sdl(_sound: SoundControls,
+           _display_settings: DisplaySettings,
+           _options: RunOptions) {
+    println!("This build doesn't include the SDL frontend; try --frontend headless.");
 }
@@ -0,0 +1,536 @@
+use std::time::{Duration, Instant};
+
+use display::Rotation;
+use frontend::Shades;
+
+// The on-screen display: status messages, performance counters and the held
+// keys, drawn over the emulated display in a tiny font. It's drawn at the
+// display's own resolution so that every frontend shows it without having to
+// know about it, and onto a copy, so recordings don't pick it up. Text gets a
+// dark outline so that it stays readable whatever the game has drawn.
+
+// How long messages stay up, in frames.
+const MESSAGE_FRAMES: u32 = 120;
+// Older messages are dropped once there are more than this.
+const MAX_MESSAGES: usize = 3;
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+const TEXT_SHADE: u8 = 255;
+const OUTLINE_SHADE: u8 = 0;
+// Keys that aren't held still show, dimly, so the layout's clear.
+const RELEASED_KEY_SHADE: u8 = 0x50;
+// Lines of a list that fit on the display, under its heading.
+pub const MENU_ENTRIES: usize = 4;
+// Characters that fit across the display.
+pub const MENU_LINE_CHARS: usize = 15;
+// How long a long line waits before scrolling, and how fast it scrolls, in
+// frames.
+const SCROLL_PAUSE: u32 = 60;
+const SCROLL_FRAMES_PER_CHAR: u32 = 8;
+
+// Three pixels wide, top row first, with the leftmost pixel in bit 2. Lower
+// case letters are drawn as capitals, apart from x, which is mostly used for
+// multiplying.
+const FONT: [(char, [u8; 5]); 60] = [
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('x', [0b000, 0b101, 0b010, 0b101, 0b000]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b110, 0b101, 0b010]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b010, 0b101, 0b010, 0b101, 0b010]),
+    ('9', [0b010, 0b101, 0b011, 0b001, 0b110]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    (';', [0b000, 0b010, 0b000, 0b010, 0b100]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('\\', [0b100, 0b100, 0b010, 0b001, 0b001]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('<', [0b001, 0b010, 0b100, 0b010, 0b001]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('"', [0b101, 0b101, 0b000, 0b000, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+];
+
+// The keypad as it's laid out on the original hardware.
+const KEYPAD_ROWS: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OsdSettings {
+    // Frames and instructions per second, and how long each frame takes.
+    pub show_stats: bool,
+    // Which of the 16 keys are held.
+    pub show_keys: bool,
+}
+
+// A list drawn down from the top of the display, with one line picked out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Menu {
+    pub lines: Vec<String>,
+    pub selected: Option<usize>,
+}
+
+// Shows the part of a list around the selected item, under a heading. The
+// selected item scrolls along if it's too long to fit, starting over once
+// it's been all the way through.
+pub fn list_menu(heading: String, items: &[String], selected: usize, frames_selected: u32) -> Menu {
+    let first = selected.saturating_sub(MENU_ENTRIES - 1)
+        .min(items.len().saturating_sub(MENU_ENTRIES));
+    let mut lines = vec![heading];
+    for (index, item) in items.iter().enumerate().skip(first).take(MENU_ENTRIES) {
+        let offset = if index == selected { scroll_offset(item, frames_selected) } else { 0 };
+        lines.push(truncate(item, offset, MENU_LINE_CHARS));
+    }
+    Menu {
+        lines,
+        selected: Some(1 + selected - first),
+    }
+}
+
+// How far along a line that's too long to fit has scrolled.
+pub fn scroll_offset(text: &str, frames_selected: u32) -> usize {
+    let overflow = text.chars().count().saturating_sub(MENU_LINE_CHARS) as u32;
+    if overflow == 0 {
+        return 0;
+    }
+    let steps = overflow + 2 * SCROLL_PAUSE / SCROLL_FRAMES_PER_CHAR;
+    let step = (frames_selected / SCROLL_FRAMES_PER_CHAR) % steps;
+    step.saturating_sub(SCROLL_PAUSE / SCROLL_FRAMES_PER_CHAR).min(overflow) as usize
+}
+
+pub fn truncate(text: &str, offset: usize, max_chars: usize) -> String {
+    text.chars().skip(offset).take(max_chars).collect()
+}
+
+struct Message {
+    text: String,
+    frames_left: u32,
+}
+
+// Counts frames and instructions, and works out the rates once a second so
+// that they're steady enough to read.
+struct Stats {
+    since: Option<Instant>,
+    frames: u32,
+    instructions: u64,
+    busy_time: Duration,
+    lines: Vec<String>,
+}
+
+impl Stats {
+    fn record_frame(&mut self, instructions: u64, busy_time: Duration) {
+        let now = Instant::now();
+        let since = *self.since.get_or_insert(now);
+        self.frames += 1;
+        self.instructions += instructions;
+        self.busy_time += busy_time;
+
+        let elapsed = now.duration_since(since);
+        if elapsed >= Duration::from_secs(1) {
+            let seconds = elapsed.as_secs_f64();
+            let frame_ms = self.busy_time.as_secs_f64() * 1000.0 / self.frames as f64;
+            self.lines = vec![
+                format!("{:.0} FPS", self.frames as f64 / seconds),
+                format!("{:.0} IPS", self.instructions as f64 / seconds),
+                format!("{:.1} MS", frame_ms),
+            ];
+            self.since = Some(now);
+            self.frames = 0;
+            self.instructions = 0;
+            self.busy_time = Duration::from_secs(0);
+        }
+    }
+}
+
+pub struct Osd {
+    settings: OsdSettings,
+    // The display's rotation, so that text can be drawn the right way up.
+    rotation: Rotation,
+    messages: Vec<Message>,
+    // Shown for as long as it's set, under any messages.
+    status: Option<String>,
+    // Takes the place of the counters and keys while it's up.
+    menu: Option<Menu>,
+    stats: Stats,
+    // What to draw over each pixel of the rotated display, if anything, for
+    // this frame and the last.
+    canvas: Vec<Option<u8>>,
+    previous_canvas: Vec<Option<u8>>,
+    shades: Shades,
+}
+
+impl Osd {
+    pub fn new(settings: OsdSettings) -> Osd {
+        Osd {
+            settings,
+            rotation: Rotation::None,
+            messages: Vec::new(),
+            status: None,
+            menu: None,
+            stats: Stats {
+                since: None,
+                frames: 0,
+                instructions: 0,
+                busy_time: Duration::from_secs(0),
+                lines: Vec::new(),
+            },
+            canvas: Vec::new(),
+            previous_canvas: Vec::new(),
+            shades: [[0; 64]; 32],
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: OsdSettings) {
+        self.settings = settings;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.settings.show_stats = !self.settings.show_stats;
+    }
+
+    pub fn toggle_keys(&mut self) {
+        self.settings.show_keys = !self.settings.show_keys;
+    }
+
+    pub fn show_message(&mut self, text: &str) {
+        self.messages.push(Message {
+            text: String::from(text),
+            frames_left: MESSAGE_FRAMES,
+        });
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+    pub fn set_status(&mut self, status: Option<String>) {
+        self.status = status;
+    }
+
+    pub fn set_menu(&mut self, menu: Option<Menu>) {
+        self.menu = menu;
+    }
+
+    // Called once per displayed frame, with the number of instructions run
+    // and how long it took, not counting any time spent waiting.
+    pub fn record_frame(&mut self, instructions: u64, busy_time: Duration) {
+        if self.settings.show_stats {
+            self.stats.record_frame(instructions, busy_time);
+        }
+    }
+
+    // Draws the OSD over the display, and counts down the messages. Returns
+    // the shades to show, and whether they look any different from the last
+    // frame's.
+    pub fn draw<'a>(&'a mut self, shades: &'a Shades, keys: [bool; 16], has_changed: bool) -> (&'a Shades, bool) {
+        let (width, height) = (shades[0].len(), shades.len());
+        let (image_width, image_height) = self.rotation.rotated_size(width, height);
+        ::std::mem::swap(&mut self.canvas, &mut self.previous_canvas);
+        self.canvas.clear();
+        self.canvas.resize(image_width * image_height, None);
+
+        let mut canvas = Canvas { pixels: &mut self.canvas, width: image_width, height: image_height };
+        if let Some(ref menu) = self.menu {
+            for (line, text) in menu.lines.iter().enumerate() {
+                if menu.selected == Some(line) {
+                    canvas.draw_highlighted_text(text, 1, 1 + line * LINE_HEIGHT);
+                } else {
+                    canvas.draw_text(text, 1, 1 + line * LINE_HEIGHT);
+                }
+            }
+        } else {
+            if self.settings.show_stats {
+                for (line, text) in self.stats.lines.iter().enumerate() {
+                    canvas.draw_text(text, 1, 1 + line * LINE_HEIGHT);
+                }
+            }
+            if self.settings.show_keys {
+                canvas.draw_keys(keys);
+            }
+        }
+        // Messages stack up from the bottom, newest last.
+        let mut lines = Vec::new();
+        for message in self.messages.iter() {
+            lines.extend(wrap(&message.text, image_width));
+        }
+        if let Some(ref status) = self.status {
+            lines.extend(wrap(status, image_width));
+        }
+        let bottom = image_height.saturating_sub(GLYPH_HEIGHT + 1);
+        for (line, text) in lines.iter().rev().enumerate() {
+            match bottom.checked_sub(line * LINE_HEIGHT) {
+                Some(y) => canvas.draw_text(text, 1, y),
+                None => break,
+            }
+        }
+
+        for message in self.messages.iter_mut() {
+            message.frames_left -= 1;
+        }
+        self.messages.retain(|message| message.frames_left > 0);
+
+        let is_visible = self.canvas.iter().any(Option::is_some);
+        let was_visible = self.previous_canvas.iter().any(Option::is_some);
+        let osd_changed = (is_visible || was_visible) && self.canvas != self.previous_canvas;
+        if !is_visible {
+            return (shades, has_changed || osd_changed);
+        }
+        self.shades = *shades;
+        for y in 0..height {
+            for x in 0..width {
+                let (image_x, image_y) = self.rotation.rotate_position(x, y, width, height);
+                if let Some(shade) = self.canvas[image_y * image_width + image_x] {
+                    self.shades[y][x] = shade;
+                }
+            }
+        }
+        (&self.shades, has_changed || osd_changed)
+    }
+}
+
+struct Canvas<'a> {
+    pixels: &'a mut Vec<Option<u8>>,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Canvas<'a> {
+    fn set(&mut self, x: usize, y: usize, shade: u8) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = Some(shade);
+        }
+    }
+
+    // Dark pixels go under the text first, so outlines never cover text.
+    fn fill_outline(&mut self, x: usize, y: usize) {
+        for outline_y in y.saturating_sub(1)..y + 2 {
+            for outline_x in x.saturating_sub(1)..x + 2 {
+                if outline_x < self.width && outline_y < self.height
+                    && self.pixels[outline_y * self.width + outline_x].is_none() {
+                    self.pixels[outline_y * self.width + outline_x] = Some(OUTLINE_SHADE);
+                }
+            }
+        }
+    }
+
+    fn draw_text(&mut self, text: &str, x: usize, y: usize) {
+        for (pixel_x, pixel_y) in glyph_pixels(text, x, y) {
+            self.fill_outline(pixel_x, pixel_y);
+        }
+        for (pixel_x, pixel_y) in glyph_pixels(text, x, y) {
+            self.set(pixel_x, pixel_y, TEXT_SHADE);
+        }
+    }
+
+    // Dark text on a bar of light that runs right across.
+    fn draw_highlighted_text(&mut self, text: &str, x: usize, y: usize) {
+        for bar_y in y.saturating_sub(1)..y + GLYPH_HEIGHT + 1 {
+            for bar_x in 0..self.width {
+                self.set(bar_x, bar_y, TEXT_SHADE);
+            }
+        }
+        for (pixel_x, pixel_y) in glyph_pixels(text, x, y) {
+            self.set(pixel_x, pixel_y, OUTLINE_SHADE);
+        }
+    }
+
+    // A 4x4 grid in the top right corner, with a 2x2 block per key.
+    fn draw_keys(&mut self, keys: [bool; 16]) {
+        let size = KEYPAD_ROWS.len() * 3 + 1;
+        let left = self.width.saturating_sub(size);
+        for y in 0..size {
+            for x in 0..size {
+                self.set(left + x, y, OUTLINE_SHADE);
+            }
+        }
+        for (row, row_keys) in KEYPAD_ROWS.iter().enumerate() {
+            for (column, &key) in row_keys.iter().enumerate() {
+                let shade = if keys[key] { TEXT_SHADE } else { RELEASED_KEY_SHADE };
+                for offset in 0..4 {
+                    self.set(left + 1 + column * 3 + offset % 2, 1 + row * 3 + offset / 2, shade);
+                }
+            }
+        }
+    }
+}
+
+// Where the lit pixels of some text go, with its top left corner at (x, y).
+fn glyph_pixels<'a>(text: &'a str, x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> + 'a {
+    text.chars().enumerate().flat_map(move |(index, c)| {
+        let rows = glyph(c);
+        let left = x + index * (GLYPH_WIDTH + 1);
+        (0..GLYPH_HEIGHT).flat_map(move |row| {
+            (0..GLYPH_WIDTH)
+                .filter(move |column| rows[row] & (0b100 >> column) != 0)
+                .map(move |column| (left + column, y + row))
+        })
+    })
+}
+
+fn glyph(c: char) -> [u8; 5] {
+    let c = if c == 'x' { c } else { c.to_ascii_uppercase() };
+    FONT.iter()
+        .find(|&&(font_char, _)| font_char == c)
+        .or_else(|| FONT.iter().find(|&&(font_char, _)| font_char == '?'))
+        .map(|&(_, rows)| rows)
+        .unwrap()
+}
+
+// Splits text into lines that fit across the display, breaking at spaces
+// where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let max_chars = ((width.saturating_sub(1)) / (GLYPH_WIDTH + 1)).max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word;
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > max_chars {
+            lines.push(line);
+            line = String::new();
+        }
+        while word.chars().count() > max_chars {
+            let split = word.char_indices().nth(max_chars).map_or(word.len(), |(index, _)| index);
+            lines.push(String::from(&word[..split]));
+            word = &word[split..];
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use display::Rotation;
+    use frontend::Shades;
+    use super::{Osd, OsdSettings, MESSAGE_FRAMES};
+
+    fn lit_pixels(shades: &Shades) -> usize {
+        shades.iter().flat_map(|row| row.iter()).filter(|&&shade| shade == 255).count()
+    }
+
+    #[test]
+    fn test_messages_are_drawn_until_they_expire() {
+        let mut osd = Osd::new(OsdSettings::default());
+        let blank = [[0; 64]; 32];
+        let (_, has_changed) = osd.draw(&blank, [false; 16], false);
+        assert!(!has_changed);
+
+        osd.show_message("1");
+        let (shades, has_changed) = osd.draw(&blank, [false; 16], false);
+        assert!(has_changed);
+        // The "1" glyph, in the bottom left corner.
+        assert_eq!([0, 0, 255, 0, 0], shades[26][..5]);
+        assert_eq!([0, 255, 255, 255, 0], shades[30][..5]);
+        assert_eq!(8, lit_pixels(shades));
+
+        let (_, has_changed) = osd.draw(&blank, [false; 16], false);
+        assert!(!has_changed);
+        for _ in 2..MESSAGE_FRAMES {
+            osd.draw(&blank, [false; 16], false);
+        }
+        let (shades, has_changed) = osd.draw(&blank, [false; 16], false);
+        assert!(has_changed);
+        assert_eq!(0, lit_pixels(shades));
+    }
+
+    #[test]
+    fn test_text_has_a_dark_outline() {
+        let mut osd = Osd::new(OsdSettings::default());
+        osd.set_status(Some(String::from("-")));
+        let (shades, _) = osd.draw(&[[255; 64]; 32], [false; 16], false);
+        assert_eq!([0, 0, 0, 0, 0, 255, 255], shades[27][..7]);
+        assert_eq!([0, 255, 255, 255, 0, 255, 255], shades[28][..7]);
+        assert_eq!([0, 0, 0, 0, 0, 255, 255], shades[29][..7]);
+    }
+
+    #[test]
+    fn test_held_keys_are_shown() {
+        let mut osd = Osd::new(OsdSettings { show_keys: true, ..OsdSettings::default() });
+        let mut keys = [false; 16];
+        keys[0x1] = true;
+        keys[0xF] = true;
+        let (shades, _) = osd.draw(&[[0; 64]; 32], keys, false);
+        // 1 is top left of the keypad, and F bottom right.
+        assert_eq!([255, 255, 0, 0x50, 0x50], shades[1][52..57]);
+        assert_eq!([0x50, 0, 255, 255, 0], shades[11][59..64]);
+        assert_eq!(8, lit_pixels(shades));
+    }
+
+    #[test]
+    fn test_text_stays_upright_when_rotated() {
+        let mut osd = Osd::new(OsdSettings::default());
+        osd.set_rotation(Rotation::Quarter);
+        osd.set_status(Some(String::from("-")));
+        let (shades, _) = osd.draw(&[[0; 64]; 32], [false; 16], false);
+        // The bottom left of the turned display is the bottom right of the
+        // unturned one, and the dash runs down it.
+        let lit: Vec<(usize, usize)> = (0..32).flat_map(|y| (0..64).map(move |x| (x, y)))
+            .filter(|&(x, y)| shades[y][x] == 255)
+            .collect();
+        assert_eq!(vec![(60, 28), (60, 29), (60, 30)], lit);
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(vec!["Palette:", "high-contrast"], super::wrap("Palette: high-contrast", 64));
+        assert_eq!(vec!["Buzzer: square", "125Hz"], super::wrap("Buzzer: square 125Hz", 64));
+        assert_eq!(vec!["ABCDEFG", "HIJ"], super::wrap("ABCDEFGHIJ", 32));
+    }
+}
@@ -0,0 +1,183 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+// Colour schemes for the display. Each palette has four colours so that
+// displays with two bit planes can be themed as well: the background, the
+// first plane, the second plane, and pixels set in both. Plain CHIP-8 only
+// ever uses the first two.
+//
+// Custom palettes can be loaded from a file with one palette per line:
+//
+//   # Comments start with a hash.
+//   gameboy = #0f380f #9bbc0f #8bac0f #306230
+
+pub type Rgb = [u8; 3];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Rgb; 4],
+}
+
+impl Palette {
+    fn new(name: &str, colors: [Rgb; 4]) -> Palette {
+        Palette {
+            name: name.to_string(),
+            colors,
+        }
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.colors[1]
+    }
+
+    // The colour for a pixel of the given shade, between the background at 0
+    // and the foreground at 255.
+    pub fn shade_color(&self, shade: u8) -> Rgb {
+        mix(self.background(), self.foreground(), shade)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        built_in_palettes().remove(0)
+    }
+}
+
+fn mix(from: Rgb, to: Rgb, amount: u8) -> Rgb {
+    let mut color = [0; 3];
+    for (i, channel) in color.iter_mut().enumerate() {
+        let (from, to, amount) = (from[i] as u32, to[i] as u32, amount as u32);
+        *channel = ((from * (255 - amount) + to * amount) / 255) as u8;
+    }
+    color
+}
+
+// The first one is the default.
+pub fn built_in_palettes() -> Vec<Palette> {
+    vec![
+        Palette::new("classic", [[0, 0, 0], [255, 255, 224], [128, 128, 112], [255, 255, 255]]),
+        Palette::new("green", [[0, 20, 0], [51, 255, 102], [26, 128, 51], [179, 255, 198]]),
+        Palette::new("amber", [[26, 15, 0], [255, 176, 0], [128, 88, 0], [255, 216, 128]]),
+        Palette::new("lcd", [[184, 192, 176], [48, 56, 48], [120, 128, 112], [16, 24, 16]]),
+        // The colours Octo uses out of the box.
+        Palette::new("octo", [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]]),
+        Palette::new("high-contrast", [[0, 0, 0], [255, 255, 255], [255, 255, 0], [0, 255, 255]]),
+    ]
+}
+
+// Parses a colour written as #rrggbb, with or without the hash.
+pub fn parse_color(value: &str) -> Option<Rgb> {
+    let hex = value.trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let mut color = [0; 3];
+    for (i, channel) in color.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(color)
+}
+
+// Parses a list of two or four colours, separated by commas or spaces. With
+// only two, the other two are made up from them.
+pub fn parse_colors(value: &str) -> Option<[Rgb; 4]> {
+    let colors = value.split([',', ' '])
+        .filter(|part| !part.is_empty())
+        .map(parse_color)
+        .collect::<Option<Vec<_>>>()?;
+    match colors.len() {
+        2 => Some([colors[0], colors[1], mix(colors[0], colors[1], 128), colors[1]]),
+        4 => Some([colors[0], colors[1], colors[2], colors[3]]),
+        _ => None,
+    }
+}
+
+// Parses a palette file, as described at the top.
+pub fn parse_palettes(text: &str) -> Result<Vec<Palette>, String> {
+    let mut palettes = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.split_once('=').and_then(|(name, colors)| {
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            parse_colors(colors).map(|colors| Palette::new(name, colors))
+        });
+        match parsed {
+            Some(palette) => palettes.push(palette),
+            None => return Err(format!("line {}: expected name = two or four colours", number + 1)),
+        }
+    }
+    Ok(palettes)
+}
+
+pub fn load_palettes(path: &Path) -> io::Result<Vec<Palette>> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    parse_palettes(&text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// Finds a palette by name, or makes one up from a list of colours.
+pub fn find_palette(palettes: &[Palette], value: &str) -> Option<Palette> {
+    palettes.iter()
+        .find(|palette| palette.name == value)
+        .cloned()
+        .or_else(|| parse_colors(value).map(|colors| Palette::new("custom", colors)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{built_in_palettes, find_palette, parse_color, parse_colors, parse_palettes, Palette};
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(Some([0x12, 0xAB, 0xFF]), parse_color("#12abFF"));
+        assert_eq!(Some([0, 0, 0]), parse_color("000000"));
+        assert_eq!(None, parse_color("#12345"));
+        assert_eq!(None, parse_color("#12345g"));
+    }
+
+    #[test]
+    fn test_two_colors_fill_in_the_planes() {
+        let colors = parse_colors("#000000,#ffffff").unwrap();
+        assert_eq!([[0, 0, 0], [255, 255, 255], [128, 128, 128], [255, 255, 255]], colors);
+        assert_eq!(None, parse_colors("#000000"));
+        assert_eq!(None, parse_colors("#000000 #ffffff #ff0000"));
+    }
+
+    #[test]
+    fn test_parse_palette_file() {
+        let palettes = parse_palettes("# Mine\n\nmine = #000000 #ff0000 #00ff00 #0000ff\n").unwrap();
+        assert_eq!(1, palettes.len());
+        assert_eq!("mine", palettes[0].name);
+        assert_eq!([0, 0, 255], palettes[0].colors[3]);
+        assert!(parse_palettes("ok = #000000 #ffffff\nbroken\n").unwrap_err().starts_with("line 2"));
+    }
+
+    #[test]
+    fn test_find_palette() {
+        let palettes = built_in_palettes();
+        assert_eq!("amber", find_palette(&palettes, "amber").unwrap().name);
+        assert_eq!("custom", find_palette(&palettes, "#000000,#00ff00").unwrap().name);
+        assert_eq!(None, find_palette(&palettes, "purple"));
+    }
+
+    #[test]
+    fn test_shade_color() {
+        let palette = Palette::default();
+        assert_eq!(palette.background(), palette.shade_color(0));
+        assert_eq!(palette.foreground(), palette.shade_color(255));
+        assert_eq!([128, 128, 112], palette.shade_color(128));
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use chip8_emu::wav::WavWriter;
+
+// Captures the emulated display to disk, either as an animated GIF or as a raw
+// Y4M stream that can be handed to an external encoder such as ffmpeg. The
+// buzzer can be captured alongside it to a WAV file.
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+pub type Rgb = [u8; 3];
+
+enum Encoder<W: Write> {
+    Gif(GifEncoder<W>),
+    Y4m(Y4mEncoder<W>),
+}
+
+pub struct Recorder<W: Write = BufWriter<File>> {
+    encoder: Encoder<W>,
+    // The frame that was last handed to us, along with how many times in a row
+    // we've seen it. GIF frames carry their own delay, so identical frames are
+    // merged into one longer frame instead of being written out again.
+    pending_frame: Option<[[bool; 64]; 32]>,
+    pending_repeats: u32,
+}
+
+impl Recorder {
+    // The format is picked from the file extension: ".y4m" gives a raw video
+    // stream, anything else gives an animated GIF.
+    pub fn create(path: &Path, scale: usize, on_color: Rgb, off_color: Rgb) -> io::Result<Recorder> {
+        let file = BufWriter::new(File::create(path)?);
+        let is_y4m = path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.to_lowercase() == "y4m");
+        Recorder::new(file, is_y4m, scale, on_color, off_color)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    fn new(out: W, is_y4m: bool, scale: usize, on_color: Rgb, off_color: Rgb) -> io::Result<Recorder<W>> {
+        let encoder = if is_y4m {
+            Encoder::Y4m(Y4mEncoder::new(out, scale, on_color, off_color)?)
+        } else {
+            Encoder::Gif(GifEncoder::new(out, scale, on_color, off_color)?)
+        };
+
+        Ok(Recorder {
+            encoder,
+            pending_frame: None,
+            pending_repeats: 0,
+        })
+    }
+
+    pub fn capture_frame(&mut self, screen: &[[bool; 64]; 32]) -> io::Result<()> {
+        match self.encoder {
+            Encoder::Gif(ref mut gif) => {
+                if self.pending_frame.as_ref() == Some(screen) {
+                    self.pending_repeats += 1;
+                    return Ok(());
+                }
+                if let Some(ref frame) = self.pending_frame {
+                    gif.write_frame(frame, self.pending_repeats)?;
+                }
+                self.pending_frame = Some(*screen);
+                self.pending_repeats = 1;
+                Ok(())
+            }
+            // Y4M streams have a constant frame rate, so every frame is kept and
+            // de-duplication is left to the external encoder.
+            Encoder::Y4m(ref mut y4m) => y4m.write_frame(screen),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self.encoder {
+            Encoder::Gif(mut gif) => {
+                if let Some(ref frame) = self.pending_frame {
+                    gif.write_frame(frame, self.pending_repeats)?;
+                }
+                gif.finish()
+            }
+            Encoder::Y4m(y4m) => y4m.finish(),
+        }
+    }
+}
+
+// Writes each frame's worth of buzzer samples to a WAV file. These are the
+// same samples that get queued for playback, so the recording stays in step
+// with the video frames whatever the audio device is doing.
+pub struct AudioRecorder {
+    wav: WavWriter<BufWriter<File>>,
+}
+
+impl AudioRecorder {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<AudioRecorder> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(AudioRecorder {
+            wav: WavWriter::new(file, sample_rate)?,
+        })
+    }
+
+    pub fn capture_frame(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.wav.write_samples(samples)
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.wav.finish().map(|_| ())
+    }
+}
+
+struct GifEncoder<W: Write> {
+    out: W,
+    scale: usize,
+    // GIF delays are in hundredths of a second, which doesn't divide evenly
+    // into 60Hz frames. We keep track of the total time written so far so that
+    // rounding errors don't accumulate.
+    frames_written: u64,
+    centiseconds_written: u64,
+}
+
+impl<W: Write> GifEncoder<W> {
+    fn new(mut out: W, scale: usize, on_color: Rgb, off_color: Rgb) -> io::Result<GifEncoder<W>> {
+        let width = (64 * scale) as u16;
+        let height = (32 * scale) as u16;
+
+        out.write_all(b"GIF89a")?;
+        // Logical screen descriptor, with a two-entry global colour table.
+        write_u16_le(&mut out, width)?;
+        write_u16_le(&mut out, height)?;
+        out.write_all(&[0b1000_0000, 0, 0])?;
+        // Global colour table: index 0 is off, index 1 is on.
+        out.write_all(&off_color)?;
+        out.write_all(&on_color)?;
+        // Netscape application extension so that the animation loops forever.
+        out.write_all(&[0x21, 0xFF, 0x0B])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+        Ok(GifEncoder {
+            out,
+            scale,
+            frames_written: 0,
+            centiseconds_written: 0,
+        })
+    }
+
+    fn write_frame(&mut self, screen: &[[bool; 64]; 32], frame_count: u32) -> io::Result<()> {
+        self.frames_written += frame_count as u64;
+        let end_time = (self.frames_written * 100 + FRAMES_PER_SECOND as u64 / 2) / FRAMES_PER_SECOND as u64;
+        let mut delay = end_time - self.centiseconds_written;
+        self.centiseconds_written = end_time;
+
+        // A delay only goes up to about eleven minutes, so a picture that's
+        // held for longer is written out as several frames.
+        loop {
+            let frame_delay = delay.min(u16::MAX as u64);
+            self.write_image(screen, frame_delay as u16)?;
+            delay -= frame_delay;
+            if delay == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn write_image(&mut self, screen: &[[bool; 64]; 32], delay: u16) -> io::Result<()> {
+        let width = 64 * self.scale;
+        let height = 32 * self.scale;
+
+        // Graphic control extension, holding the delay for this frame.
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        write_u16_le(&mut self.out, delay)?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor covering the whole canvas, with no local colour table.
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        write_u16_le(&mut self.out, width as u16)?;
+        write_u16_le(&mut self.out, height as u16)?;
+        self.out.write_all(&[0x00])?;
+
+        let mut indices = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let is_active_cell = screen[y / self.scale][x / self.scale];
+                indices.push(if is_active_cell { 1 } else { 0 });
+            }
+        }
+
+        // The minimum code size can't be less than 2, even for two colours.
+        let min_code_size = 2;
+        self.out.write_all(&[min_code_size])?;
+        let compressed = lzw_encode(&indices, min_code_size);
+        for block in compressed.chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn write_u16_le<W: Write>(out: &mut W, value: u16) -> io::Result<()> {
+    out.write_all(&[(value & 0xFF) as u8, (value >> 8) as u8])
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    bit_count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, code_size: u8) {
+        // GIF packs codes starting from the least significant bit.
+        self.current |= (code as u32) << self.bit_count;
+        self.bit_count += code_size;
+        while self.bit_count >= 8 {
+            self.bytes.push((self.current & 0xFF) as u8);
+            self.current >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push((self.current & 0xFF) as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear_code: u16 = 1 << min_code_size;
+    let end_code: u16 = clear_code + 1;
+
+    let mut writer = BitWriter { bytes: Vec::new(), current: 0, bit_count: 0 };
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end_code + 1;
+
+    writer.write(clear_code, code_size);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let current = match prefix {
+            None => {
+                prefix = Some(index as u16);
+                continue;
+            }
+            Some(current) => current,
+        };
+
+        if let Some(&code) = dictionary.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+
+        writer.write(current, code_size);
+        dictionary.insert((current, index), next_code);
+        next_code += 1;
+
+        if next_code == 4096 {
+            // The table is full: start over with a fresh dictionary.
+            writer.write(clear_code, code_size);
+            dictionary.clear();
+            code_size = min_code_size + 1;
+            next_code = end_code + 1;
+        } else if next_code > (1 << code_size) {
+            // The decoder lags one code behind us, so we only grow the code
+            // size once the code we just added no longer fits.
+            code_size += 1;
+        }
+
+        prefix = Some(index as u16);
+    }
+
+    if let Some(current) = prefix {
+        writer.write(current, code_size);
+    }
+    writer.write(end_code, code_size);
+    writer.finish()
+}
+
+struct Y4mEncoder<W: Write> {
+    out: W,
+    scale: usize,
+    on_yuv: [u8; 3],
+    off_yuv: [u8; 3],
+}
+
+impl<W: Write> Y4mEncoder<W> {
+    fn new(mut out: W, scale: usize, on_color: Rgb, off_color: Rgb) -> io::Result<Y4mEncoder<W>> {
+        writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                 64 * scale, 32 * scale, FRAMES_PER_SECOND)?;
+        Ok(Y4mEncoder {
+            out,
+            scale,
+            on_yuv: rgb_to_yuv(on_color),
+            off_yuv: rgb_to_yuv(off_color),
+        })
+    }
+
+    fn write_frame(&mut self, screen: &[[bool; 64]; 32]) -> io::Result<()> {
+        let width = 64 * self.scale;
+        let height = 32 * self.scale;
+
+        self.out.write_all(b"FRAME\n")?;
+        // C444 stores full-resolution Y, then Cb, then Cr planes.
+        let mut plane = vec![0u8; width * height];
+        for component in 0..3 {
+            for y in 0..height {
+                for x in 0..width {
+                    let is_active_cell = screen[y / self.scale][x / self.scale];
+                    let yuv = if is_active_cell { self.on_yuv } else { self.off_yuv };
+                    plane[y * width + x] = yuv[component];
+                }
+            }
+            self.out.write_all(&plane)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// BT.601 studio-swing conversion, which is what Y4M consumers assume by default.
+fn rgb_to_yuv(color: Rgb) -> [u8; 3] {
+    let r = color[0] as f32;
+    let g = color[1] as f32;
+    let b = color[2] as f32;
+    let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+    let cb = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+    let cr = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lzw_encode, GifEncoder, Recorder};
+
+    // A plain GIF LZW decoder, to check the encoder against.
+    fn lzw_decode(bytes: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear_code = 1u16 << min_code_size;
+        let end_code = clear_code + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut output = Vec::new();
+        let (mut current, mut bit_count, mut position) = (0u32, 0u8, 0);
+        loop {
+            while bit_count < code_size {
+                current |= (bytes[position] as u32) << bit_count;
+                position += 1;
+                bit_count += 8;
+            }
+            let code = (current & ((1 << code_size) - 1)) as u16;
+            current >>= code_size;
+            bit_count -= code_size;
+
+            if code == clear_code {
+                table = (0..clear_code).map(|index| vec![index as u8]).collect();
+                table.push(Vec::new());
+                table.push(Vec::new());
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end_code {
+                return output;
+            }
+            let entry = match (table.get(code as usize), previous.as_ref()) {
+                (Some(entry), _) => entry.clone(),
+                // The one code the decoder can't have seen yet.
+                (None, Some(previous)) => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                }
+                (None, None) => panic!("code {} before anything else", code),
+            };
+            output.extend_from_slice(&entry);
+            if let Some(mut previous) = previous {
+                previous.push(entry[0]);
+                table.push(previous);
+                if table.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+            previous = Some(entry);
+        }
+    }
+
+    // Pulls the delays out of the graphic control extensions.
+    fn gif_delays(gif: &[u8]) -> Vec<u16> {
+        gif.windows(6)
+            .filter(|bytes| bytes[..4] == [0x21, 0xF9, 0x04, 0x00])
+            .map(|bytes| bytes[4] as u16 | (bytes[5] as u16) << 8)
+            .collect()
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        // Enough noisy pixels to fill the dictionary and start over a few times.
+        let mut seed = 1u32;
+        let indices: Vec<u8> = (0..20_000).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            ((seed >> 16) & 1) as u8
+        }).collect();
+        assert_eq!(indices, lzw_decode(&lzw_encode(&indices, 2), 2));
+
+        let blank = vec![0u8; 64 * 32];
+        assert_eq!(blank, lzw_decode(&lzw_encode(&blank, 2), 2));
+        assert_eq!(Vec::<u8>::new(), lzw_decode(&lzw_encode(&[], 2), 2));
+    }
+
+    #[test]
+    fn test_repeated_frames_are_merged() {
+        let mut recorder = Recorder::new(Vec::new(), false, 1, [255; 3], [0; 3]).unwrap();
+        let blank = [[false; 64]; 32];
+        let mut dot = blank;
+        dot[0][0] = true;
+        for screen in &[blank, blank, blank, dot, blank] {
+            recorder.capture_frame(screen).unwrap();
+        }
+        let gif = recorder.finish().unwrap();
+        assert_eq!(b"GIF89a", &gif[..6]);
+        // Three frames are 5/100ths of a second, and the rounding is caught up
+        // with rather than adding up.
+        assert_eq!(vec![5, 2, 1], gif_delays(&gif));
+        assert_eq!(Some(&0x3B), gif.last());
+    }
+
+    #[test]
+    fn test_long_holds_are_split() {
+        let mut gif = GifEncoder::new(Vec::new(), 1, [255; 3], [0; 3]).unwrap();
+        // Twenty minutes of the same picture.
+        gif.write_frame(&[[false; 64]; 32], 20 * 60 * 60).unwrap();
+        assert_eq!(vec![65535, 54465], gif_delays(&gif.finish().unwrap()));
+    }
+
+    #[test]
+    fn test_y4m_header_and_frames() {
+        let mut recorder = Recorder::new(Vec::new(), true, 2, [255; 3], [0; 3]).unwrap();
+        let mut screen = [[false; 64]; 32];
+        screen[0][0] = true;
+        recorder.capture_frame(&screen).unwrap();
+        recorder.capture_frame(&screen).unwrap();
+        let y4m = recorder.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+        assert_eq!(&header[..], &y4m[..header.len()]);
+        // Identical frames are all kept, each with three full planes.
+        let frame_size = b"FRAME\n".len() + 3 * 128 * 64;
+        assert_eq!(header.len() + 2 * frame_size, y4m.len());
+        let frame = &y4m[header.len()..header.len() + frame_size];
+        assert_eq!(b"FRAME\n", &frame[..6]);
+        // The lit pixel is scaled up to two by two, in white.
+        assert_eq!([235, 235, 16], frame[6..9]);
+        assert_eq!([235, 235, 16], frame[6 + 128..9 + 128]);
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chip8_emu::Chip8;
+
+use rand::Rng;
+
+// Reloading the ROM when its file changes, so that a program can be tried out
+// each time it's assembled without restarting the emulator.
+
+// How often to look at the file, in frames.
+const CHECK_INTERVAL: u32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReloadMode {
+    Off,
+    // Start the new ROM from scratch.
+    Reset,
+    // Start the new ROM, but with whatever the old one left in memory outside
+    // the program, such as saved levels or high scores.
+    KeepRam,
+}
+
+// When the file was last written, and how big it is.
+type Stamp = (SystemTime, u64);
+
+pub struct RomWatcher {
+    path: PathBuf,
+    // What the file looked like when it was loaded, and the last time we
+    // looked.
+    loaded: Option<Stamp>,
+    seen: Option<Stamp>,
+    frames_until_check: u32,
+}
+
+impl RomWatcher {
+    pub fn new(path: &Path) -> RomWatcher {
+        let stamp = stamp(path);
+        RomWatcher {
+            path: path.to_path_buf(),
+            loaded: stamp,
+            seen: stamp,
+            frames_until_check: CHECK_INTERVAL,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Called once a frame. Returns true once the file has changed and then
+    // stayed the same for a check, so that we don't load it half written.
+    pub fn poll(&mut self) -> bool {
+        self.frames_until_check -= 1;
+        if self.frames_until_check > 0 {
+            return false;
+        }
+        self.frames_until_check = CHECK_INTERVAL;
+        let stamp = stamp(&self.path);
+        self.check(stamp)
+    }
+
+    fn check(&mut self, stamp: Option<Stamp>) -> bool {
+        let has_settled = stamp.is_some() && stamp == self.seen && stamp != self.loaded;
+        self.seen = stamp;
+        if has_settled {
+            self.loaded = stamp;
+        }
+        has_settled
+    }
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+// Copies the memory around the new ROM's program back from before it was
+// loaded.
+pub fn keep_ram<R: Rng>(memory: &[u8], chip8: &mut Chip8<R>, program: Range<usize>) {
+    chip8.write_memory(0, &memory[..program.start]);
+    if program.end < memory.len() {
+        chip8.write_memory(program.end as u16, &memory[program.end..]);
+    }
+}
+
+pub fn parse_reload_mode(value: &str) -> Option<ReloadMode> {
+    match value {
+        "off" => Some(ReloadMode::Off),
+        "reset" => Some(ReloadMode::Reset),
+        "keep-ram" => Some(ReloadMode::KeepRam),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use chip8_emu::{Chip8, ResetKind};
+
+    use super::RomWatcher;
+
+    #[test]
+    fn test_watcher_waits_for_the_file_to_settle() {
+        let mut watcher = RomWatcher::new(Path::new("no such file.ch8"));
+        let written = |seconds| Some((UNIX_EPOCH + Duration::from_secs(seconds), 2));
+        assert!(!watcher.check(written(1)));
+        assert!(watcher.check(written(1)));
+        assert!(!watcher.check(written(1)));
+        // Still being written to.
+        assert!(!watcher.check(written(2)));
+        assert!(!watcher.check(written(3)));
+        assert!(watcher.check(written(3)));
+        // Deleting the file doesn't count as a change.
+        assert!(!watcher.check(None));
+        assert!(!watcher.check(None));
+    }
+
+    #[test]
+    fn test_keep_ram_leaves_the_new_program_alone() {
+        let mut chip8 = Chip8::new_and_init();
+        chip8.write_memory(0x200, &[1, 2, 3, 4]);
+        chip8.write_memory(0xF00, &[5]);
+        let old_memory = chip8.memory().to_vec();
+        chip8.load_rom_at(&[9, 9], 0x200);
+        chip8.reset(ResetKind::Hard);
+        super::keep_ram(&old_memory, &mut chip8, 0x200..0x202);
+        assert_eq!([9, 9, 3, 4], chip8.memory()[0x200..0x204]);
+        assert_eq!(5, chip8.memory()[0xF00]);
+    }
+}
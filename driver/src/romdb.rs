@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use chip8_emu::Quirks;
+
+use serde_json;
+use serde_json::Value;
+use sha1_smol::Sha1;
+
+use display;
+use display::Rotation;
+use frontend::{Key, PadInput};
+use keymap::Keymap;
+use palette;
+use palette::Rgb;
+
+// Settings for particular ROMs, looked up by the SHA-1 of the ROM file. The
+// database uses the programs.json format from the community chip-8-database
+// (https://github.com/chip-8/chip-8-database): a list of programs, each with
+// a title and the ROMs that belong to it, keyed by hash:
+//
+//   [{
+//     "title": "Pong",
+//     "roms": {
+//       "<sha1>": {
+//         "platforms": ["originalChip8"],
+//         "tickrate": 15,
+//         "quirkyPlatforms": { "originalChip8": { "vblank": false } },
+//         "keys": { "up": 1, "down": 4 },
+//         "colors": { "pixels": ["#000000", "#ffffff"] },
+//         "startAddress": 512,
+//         "screenRotation": 0
+//       }
+//     }
+//   }]
+//
+// Override files use the same format. Anything they set replaces what the
+// database says, and everything else is left as it was.
+
+// The quirks of each platform, as in the database's platforms.json.
+const PLATFORMS: [(&str, Quirks); 9] = [
+    ("originalChip8", Quirks { shift: false, memory_increment_by_x: false, memory_leave_i_unchanged: false,
+                               wrap: false, jump: false, vblank: true, logic: true }),
+    ("hybridVIP", Quirks { shift: false, memory_increment_by_x: false, memory_leave_i_unchanged: false,
+                           wrap: false, jump: false, vblank: true, logic: true }),
+    ("modernChip8", Quirks { shift: false, memory_increment_by_x: false, memory_leave_i_unchanged: false,
+                             wrap: false, jump: false, vblank: false, logic: false }),
+    ("chip8x", Quirks { shift: false, memory_increment_by_x: false, memory_leave_i_unchanged: false,
+                        wrap: false, jump: false, vblank: true, logic: true }),
+    ("chip48", Quirks { shift: true, memory_increment_by_x: true, memory_leave_i_unchanged: false,
+                        wrap: false, jump: true, vblank: false, logic: false }),
+    ("superchip1", Quirks { shift: true, memory_increment_by_x: true, memory_leave_i_unchanged: false,
+                            wrap: false, jump: true, vblank: false, logic: false }),
+    ("superchip", Quirks { shift: true, memory_increment_by_x: false, memory_leave_i_unchanged: true,
+                           wrap: false, jump: true, vblank: false, logic: false }),
+    ("megachip8", Quirks { shift: true, memory_increment_by_x: false, memory_leave_i_unchanged: true,
+                           wrap: false, jump: true, vblank: false, logic: false }),
+    ("xochip", Quirks { shift: false, memory_increment_by_x: false, memory_leave_i_unchanged: false,
+                        wrap: true, jump: false, vblank: false, logic: false }),
+];
+
+pub fn platform_quirks(platform: &str) -> Option<Quirks> {
+    PLATFORMS.iter().find(|entry| entry.0 == platform).map(|entry| entry.1)
+}
+
+pub fn platform_names() -> Vec<&'static str> {
+    PLATFORMS.iter().map(|entry| entry.0).collect()
+}
+
+// Everything the database knows about a ROM. Anything it doesn't say is left
+// at the emulator's defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    // The first platform listed, which the quirks come from.
+    pub platform: Option<String>,
+    pub quirks: Option<Quirks>,
+    // Instructions per frame.
+    pub tickrate: Option<u32>,
+    // CHIP-8 keys by what they do in the game, as the database names them:
+    // up, down, left, right, a and b.
+    pub keys: Vec<(String, u8)>,
+    pub colors: Option<[Rgb; 4]>,
+    pub start_address: Option<u16>,
+    pub rotation: Option<Rotation>,
+}
+
+impl RomInfo {
+    // Binds the arrow keys, space bar and controllers to whatever the game
+    // uses for moving and firing, on top of the usual keypad.
+    pub fn apply_keys(&self, keymap: &mut Keymap) {
+        for &(ref name, chip8_key) in self.keys.iter() {
+            let keys: &[Key] = match name.as_str() {
+                "up" => &[Key::Up, Key::Pad(PadInput::DPadUp), Key::Pad(PadInput::LeftStickUp)],
+                "down" => &[Key::Down, Key::Pad(PadInput::DPadDown), Key::Pad(PadInput::LeftStickDown)],
+                "left" => &[Key::Left, Key::Pad(PadInput::DPadLeft), Key::Pad(PadInput::LeftStickLeft)],
+                "right" => &[Key::Right, Key::Pad(PadInput::DPadRight), Key::Pad(PadInput::LeftStickRight)],
+                "a" => &[Key::Char(' '), Key::Pad(PadInput::A)],
+                "b" => &[Key::Pad(PadInput::B)],
+                // Second players are left on the keypad.
+                _ => &[],
+            };
+            for &key in keys {
+                keymap.bind(key, chip8_key);
+            }
+        }
+    }
+
+    // A short description of the settings, for showing when the ROM loads.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(ref platform) = self.platform {
+            parts.push(platform.clone());
+        }
+        if let Some(tickrate) = self.tickrate {
+            parts.push(format!("{} instructions per frame", tickrate));
+        }
+        if let Some(address) = self.start_address {
+            parts.push(format!("starts at {:#05X}", address));
+        }
+        parts.join(", ")
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RomDatabase {
+    // Each ROM's entry, with the program's title added.
+    roms: HashMap<String, Value>,
+}
+
+impl RomDatabase {
+    pub fn new() -> RomDatabase {
+        RomDatabase::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    // Adds the programs from a programs.json file, on top of any that are
+    // already there.
+    pub fn add_programs(&mut self, text: &str) -> Result<(), String> {
+        let programs: Value = serde_json::from_str(text).map_err(|error| error.to_string())?;
+        let programs = programs.as_array().ok_or("expected a list of programs")?;
+        for program in programs {
+            let roms = program.get("roms").and_then(Value::as_object)
+                .ok_or("each program should have a roms object")?;
+            for (hash, rom) in roms {
+                let mut rom = rom.clone();
+                if !rom.is_object() {
+                    return Err(format!("{}: expected an object", hash));
+                }
+                if let Some(title) = program.get("title") {
+                    rom["title"] = title.clone();
+                }
+                let hash = hash.to_lowercase();
+                match self.roms.get_mut(&hash) {
+                    Some(existing) => merge(existing, rom),
+                    None => {
+                        self.roms.insert(hash, rom);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|error| error.to_string())?;
+        self.add_programs(&text)
+    }
+
+    // Looks up a ROM by its contents.
+    pub fn find(&self, rom: &[u8]) -> Result<Option<RomInfo>, String> {
+        let hash = Sha1::from(rom).digest().to_string();
+        match self.roms.get(&hash) {
+            Some(entry) => parse_rom_info(entry).map(Some).map_err(|error| format!("{}: {}", hash, error)),
+            None => Ok(None),
+        }
+    }
+}
+
+// Merges objects key by key, so that overrides only need to mention what
+// they change. Anything else is replaced outright.
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (&mut Value::Object(ref mut base), Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+fn parse_rom_info(entry: &Value) -> Result<RomInfo, String> {
+    let mut info = RomInfo {
+        title: entry.get("title").and_then(Value::as_str).map(String::from),
+        ..RomInfo::default()
+    };
+
+    if let Some(platforms) = entry.get("platforms") {
+        info.platform = platforms.as_array()
+            .and_then(|platforms| platforms.first())
+            .and_then(Value::as_str)
+            .map(String::from);
+        if info.platform.is_none() {
+            return Err(String::from("platforms should be a list of names"));
+        }
+    }
+    if let Some(ref platform) = info.platform {
+        let mut quirks = platform_quirks(platform);
+        let overrides = entry.get("quirkyPlatforms").and_then(|quirky| quirky.get(platform.as_str()));
+        if let (Some(ref mut quirks), Some(overrides)) = (quirks.as_mut(), overrides) {
+            apply_quirks(quirks, overrides)?;
+        }
+        info.quirks = quirks;
+    }
+
+    if let Some(tickrate) = entry.get("tickrate") {
+        info.tickrate = tickrate.as_u64()
+            .filter(|&tickrate| tickrate > 0 && tickrate <= u32::MAX as u64)
+            .map(|tickrate| tickrate as u32);
+        if info.tickrate.is_none() {
+            return Err(String::from("tickrate should be a positive whole number"));
+        }
+    }
+
+    if let Some(keys) = entry.get("keys") {
+        let keys = keys.as_object().ok_or("keys should be an object")?;
+        for (name, chip8_key) in keys {
+            let chip8_key = chip8_key.as_u64()
+                .filter(|&chip8_key| chip8_key < 16)
+                .ok_or_else(|| format!("key {} should be from 0 to 15", name))?;
+            info.keys.push((name.clone(), chip8_key as u8));
+        }
+    }
+
+    if let Some(pixels) = entry.get("colors").and_then(|colors| colors.get("pixels")) {
+        let colors = pixels.as_array()
+            .map(|pixels| pixels.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(" "));
+        info.colors = colors.as_ref().and_then(|colors| palette::parse_colors(colors));
+        if info.colors.is_none() {
+            return Err(String::from("colors.pixels should be a list of two or four colours"));
+        }
+    }
+
+    if let Some(address) = entry.get("startAddress") {
+        info.start_address = address.as_u64()
+            .filter(|&address| address < 0x1000)
+            .map(|address| address as u16);
+        if info.start_address.is_none() {
+            return Err(String::from("startAddress should be below 4096"));
+        }
+    }
+
+    if let Some(rotation) = entry.get("screenRotation") {
+        info.rotation = rotation.as_u64().and_then(|rotation| display::parse_rotation(&rotation.to_string()));
+        if info.rotation.is_none() {
+            return Err(String::from("screenRotation should be 0, 90, 180 or 270"));
+        }
+    }
+
+    Ok(info)
+}
+
+fn apply_quirks(quirks: &mut Quirks, overrides: &Value) -> Result<(), String> {
+    let overrides = overrides.as_object().ok_or("quirkyPlatforms should hold objects")?;
+    for (name, value) in overrides {
+        let value = value.as_bool().ok_or_else(|| format!("quirk {} should be true or false", name))?;
+        match name.as_str() {
+            "shift" => quirks.shift = value,
+            "memoryIncrementByX" => quirks.memory_increment_by_x = value,
+            "memoryLeaveIUnchanged" => quirks.memory_leave_i_unchanged = value,
+            "wrap" => quirks.wrap = value,
+            "jump" => quirks.jump = value,
+            "vblank" => quirks.vblank = value,
+            "logic" => quirks.logic = value,
+            // Other quirks are for platforms we don't emulate.
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_emu::Quirks;
+
+    use display::Rotation;
+    use frontend::{Key, PadInput};
+    use keymap::Keymap;
+    use super::{platform_quirks, RomDatabase};
+
+    // The SHA-1 of the bytes 00 E0.
+    const CLS_HASH: &str = "159ba69f4c40be3042fc54c7fbb2025f7e49f8e0";
+
+    fn database_with(text: &str) -> RomDatabase {
+        let mut database = RomDatabase::new();
+        database.add_programs(&text.replace("HASH", CLS_HASH)).unwrap();
+        database
+    }
+
+    #[test]
+    fn test_find_by_hash() {
+        let database = database_with(r##"[{
+            "title": "Clear",
+            "roms": { "HASH": {
+                "platforms": ["originalChip8", "modernChip8"],
+                "tickrate": 15,
+                "quirkyPlatforms": { "originalChip8": { "vblank": false } },
+                "keys": { "up": 1, "a": 6 },
+                "colors": { "pixels": ["#000000", "#00ff00"] },
+                "startAddress": 1536,
+                "screenRotation": 90
+            } }
+        }]"##);
+
+        let info = database.find(&[0x00, 0xE0]).unwrap().unwrap();
+        assert_eq!(Some(String::from("Clear")), info.title);
+        assert_eq!(Some(String::from("originalChip8")), info.platform);
+        let quirks = info.quirks.unwrap();
+        assert!(!quirks.vblank);
+        assert!(quirks.logic);
+        assert_eq!(Some(15), info.tickrate);
+        assert_eq!([0, 255, 0], info.colors.unwrap()[1]);
+        assert_eq!(Some(0x600), info.start_address);
+        assert_eq!(Some(Rotation::Quarter), info.rotation);
+
+        let mut keymap = Keymap::default();
+        info.apply_keys(&mut keymap);
+        assert_eq!(Some(0x1), keymap.chip8_key_for(Key::Up));
+        assert_eq!(Some(0x1), keymap.chip8_key_for(Key::Pad(PadInput::DPadUp)));
+        assert_eq!(Some(0x6), keymap.chip8_key_for(Key::Char(' ')));
+        // The keypad is still there.
+        assert_eq!(Some(0x5), keymap.chip8_key_for(Key::Char('w')));
+
+        assert_eq!(None, database.find(&[0x00, 0xEE]).unwrap());
+    }
+
+    #[test]
+    fn test_overrides_merge_into_entries() {
+        let mut database = database_with(r#"[{
+            "title": "Clear",
+            "roms": { "HASH": { "platforms": ["chip48"], "tickrate": 15 } }
+        }]"#);
+        database.add_programs(&r#"[{
+            "roms": { "HASH": { "tickrate": 30, "quirkyPlatforms": { "chip48": { "jump": false } } } }
+        }]"#.replace("HASH", CLS_HASH)).unwrap();
+
+        let info = database.find(&[0x00, 0xE0]).unwrap().unwrap();
+        assert_eq!(Some(String::from("Clear")), info.title);
+        assert_eq!(Some(30), info.tickrate);
+        let expected = Quirks { jump: false, ..platform_quirks("chip48").unwrap() };
+        assert_eq!(Some(expected), info.quirks);
+    }
+
+    #[test]
+    fn test_bad_entries() {
+        let mut database = RomDatabase::new();
+        assert!(database.add_programs("{}").is_err());
+        assert!(database.add_programs("[{ \"title\": \"No ROMs\" }]").is_err());
+
+        let database = database_with(r#"[{ "roms": { "HASH": { "startAddress": 5000 } } }]"#);
+        assert!(database.find(&[0x00, 0xE0]).is_err());
+    }
+}
@@ -0,0 +1,1083 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chip8_emu::{Chip8, ResetKind};
+use chip8_emu::audio::FrameSampleCounter;
+
+use rand::Rng;
+
+use compare::Comparison;
+use display::Rotation;
+use debugger::{DebugEvent, Debugger};
+use filter::{FilterSettings, FrameFilter};
+use frontend::{AudioSink, InputEvent, InputSource, Key, VideoSink};
+use keymap::Keymap;
+use launcher::{Launcher, LauncherAction};
+use osd;
+use osd::{Osd, OsdSettings, MENU_ENTRIES};
+use palette::Palette;
+use recording::{AudioRecorder, Recorder};
+use reload;
+use reload::{ReloadMode, RomWatcher};
+use romdb::RomInfo;
+use sound::{SoundCommand, SoundControls};
+use speed::{FrameBudget, FramePacer, SpeedSettings};
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+const DEFAULT_TITLE: &str = "Chip 8 emu";
+// Recordings are written at the same size as the SDL window.
+const RECORDING_SCALE: usize = 10;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Command {
+    Quit,
+    ShowBindings,
+    ToggleRecording,
+    ToggleFullscreen,
+    CyclePalette,
+    TogglePause,
+    // Runs fast for as long as the key is held.
+    Turbo,
+    ToggleSlowMotion,
+    ToggleStats,
+    ToggleKeyDisplay,
+    OpenLauncher,
+    Reset(ResetKind),
+    // Moves the keys between the machines being compared.
+    CycleFocus,
+    Sound(SoundCommand),
+}
+
+// A ROM that's been loaded into a machine and is ready to go, with the
+// settings that belong to it.
+pub struct Game {
+    pub path: PathBuf,
+    // What the ROM database knows about it, if anything.
+    pub info: RomInfo,
+    pub keymap: Keymap,
+    pub cycles_per_frame: Option<u32>,
+    // Where in memory the ROM went.
+    pub program: Range<usize>,
+    pub database_error: Option<String>,
+}
+
+// The key bindings, listed over the game until they're put away.
+struct BindingsList {
+    lines: Vec<String>,
+    selected: usize,
+    // Frames since the selection last moved, for scrolling long lines.
+    frames_selected: u32,
+}
+
+// Loads the ROMs picked in the launcher, dropped on the window or changed on
+// disk into the machine, starting it over. The machine is left alone if the
+// ROM can't be loaded.
+pub type GameLoader<R> = Box<dyn FnMut(&Path, &mut Chip8<R>) -> Result<Game, String>>;
+
+pub struct Runner<R: Rng> {
+    chip8: Chip8<R>,
+    sound: SoundControls,
+    recorder: Option<Recorder>,
+    audio_recorder: Option<AudioRecorder>,
+    keymap: Keymap,
+    // Whether the input source needs to hear about the keymap.
+    keymap_changed: bool,
+    filter: FrameFilter,
+    osd: Osd,
+    // The palettes to cycle through, and which one is in use.
+    palettes: Vec<Palette>,
+    palette_index: usize,
+    // Whether the video sink needs to hear about the palette.
+    palette_changed: bool,
+    // What to call the window, and whether the video sink needs to hear it.
+    // The speed goes on the end, if it isn't normal.
+    title: String,
+    title_changed: bool,
+    speed_description: Option<String>,
+    cycles_per_frame: u32,
+    // While paused, the display and sound carry on but the emulator doesn't.
+    pacer: FramePacer,
+    samples_per_frame: FrameSampleCounter,
+    sample_rate: u32,
+    // Stop after this many frames, if set. Mostly useful when running headless.
+    frame_limit: Option<u64>,
+    frames_run: u64,
+    events: Vec<InputEvent>,
+    // For picking another ROM while running, if there's somewhere to pick
+    // from. Until the first one's picked, there's no game to go back to.
+    launcher: Option<Launcher>,
+    loader: Option<GameLoader<R>>,
+    has_game: bool,
+    // Keeps an eye on the game's file, to load it again when it changes.
+    reload_mode: ReloadMode,
+    watcher: Option<RomWatcher>,
+    // Other machines running alongside this one, if we're comparing them.
+    comparison: Option<Comparison<R>>,
+    // A debugger that decides when the machine runs, if one's listening.
+    debugger: Option<Box<dyn Debugger<R>>>,
+    bindings: Option<BindingsList>,
+    // Whether messages also go to stderr, which isn't somewhere to write
+    // while the terminal frontend is drawing.
+    prints_messages: bool,
+}
+
+impl<R: Rng> Runner<R> {
+    pub fn new(chip8: Chip8<R>, sound: SoundControls, sample_rate: u32) -> Runner<R> {
+        let mut runner = Runner {
+            chip8,
+            sound,
+            recorder: None,
+            audio_recorder: None,
+            keymap: Keymap::default(),
+            keymap_changed: true,
+            filter: FrameFilter::new(FilterSettings::default()),
+            osd: Osd::new(OsdSettings::default()),
+            palettes: vec![Palette::default()],
+            palette_index: 0,
+            palette_changed: true,
+            title: String::from(DEFAULT_TITLE),
+            title_changed: true,
+            speed_description: None,
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            pacer: FramePacer::new(SpeedSettings::default()),
+            samples_per_frame: FrameSampleCounter::new(sample_rate, FRAMES_PER_SECOND),
+            sample_rate,
+            frame_limit: None,
+            frames_run: 0,
+            events: Vec::new(),
+            launcher: None,
+            loader: None,
+            has_game: true,
+            reload_mode: ReloadMode::Off,
+            watcher: None,
+            comparison: None,
+            debugger: None,
+            bindings: None,
+            prints_messages: true,
+        };
+        runner.chip8.set_buzzer_settings(runner.sound.settings);
+        runner
+    }
+
+    pub fn set_frame_limit(&mut self, frame_limit: Option<u64>) {
+        self.frame_limit = frame_limit;
+    }
+
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u32) {
+        self.cycles_per_frame = cycles_per_frame;
+    }
+
+    pub fn set_paused(&mut self, is_paused: bool) {
+        self.pacer.set_paused(is_paused);
+    }
+
+    pub fn set_speed(&mut self, settings: SpeedSettings) {
+        self.pacer.set_settings(settings);
+    }
+
+    // Shows the name of the game after the emulator's own.
+    pub fn set_game_title(&mut self, game_title: &str) {
+        self.title = format!("{} - {}", DEFAULT_TITLE, game_title);
+        self.title_changed = true;
+    }
+
+    pub fn set_launcher(&mut self, launcher: Launcher) {
+        self.launcher = Some(launcher);
+    }
+
+    // Without a loader, the game can't be changed or reloaded.
+    pub fn set_loader(&mut self, loader: GameLoader<R>) {
+        self.loader = Some(loader);
+    }
+
+    pub fn set_prints_messages(&mut self, prints_messages: bool) {
+        self.prints_messages = prints_messages;
+    }
+
+    // Takes effect from the next game loaded.
+    pub fn set_reload_mode(&mut self, reload_mode: ReloadMode) {
+        self.reload_mode = reload_mode;
+    }
+
+    pub fn set_comparison(&mut self, comparison: Comparison<R>) {
+        self.comparison = Some(comparison);
+    }
+
+    pub fn set_debugger(&mut self, debugger: Box<dyn Debugger<R>>) {
+        if let Ok(address) = debugger.local_addr() {
+            self.notify(&format!("Waiting for a debugger on port {}", address.port()));
+        }
+        self.debugger = Some(debugger);
+    }
+
+    // Takes on the title, speed and key bindings of the ROM that's just been
+    // loaded into the machine.
+    pub fn load_game(&mut self, game: Game) {
+        self.filter.clear();
+        self.keymap = game.keymap;
+        self.keymap_changed = true;
+        self.cycles_per_frame = game.cycles_per_frame.unwrap_or(DEFAULT_CYCLES_PER_FRAME);
+        if let Some(ref error) = game.database_error {
+            self.notify(&format!("Couldn't use the ROM database's entry for {}", error));
+        }
+        // The database's settings go in the title along with its name for the
+        // game, so that it's clear where they came from.
+        let info = &game.info;
+        let database_title = info.title.as_ref().map(|title| {
+            let description = info.describe();
+            if description.is_empty() {
+                title.clone()
+            } else {
+                format!("{} ({})", title, description)
+            }
+        });
+        if let Some(ref title) = database_title {
+            self.notify(&format!("Found {} in the ROM database", title));
+        }
+        let file_name = game.path.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+        match database_title.or(file_name) {
+            Some(title) => self.set_game_title(&title),
+            None => {
+                self.title = String::from(DEFAULT_TITLE);
+                self.title_changed = true;
+            }
+        }
+        self.has_game = true;
+        self.watcher = match self.reload_mode {
+            ReloadMode::Off => None,
+            _ => Some(RomWatcher::new(&game.path)),
+        };
+        let path = &game.path;
+        let saved_recent = self.launcher.as_mut().map(|launcher| launcher.add_recent(path));
+        if let Some(Err(error)) = saved_recent {
+            self.notify(&format!("Couldn't save the list of recent ROMs: {}", error));
+        }
+    }
+
+    // Loads a ROM the user's asked for, and gets it going.
+    fn switch_game(&mut self, path: &Path) {
+        if let Some(game) = self.load_with_loader(path) {
+            self.load_game(game);
+            self.close_launcher();
+            self.pacer.set_paused(false);
+        }
+    }
+
+    fn load_with_loader(&mut self, path: &Path) -> Option<Game> {
+        let result = match self.loader {
+            Some(ref mut loader) => loader(path, &mut self.chip8),
+            None => return None,
+        };
+        match result {
+            Ok(game) => Some(game),
+            Err(error) => {
+                self.notify(&error);
+                None
+            }
+        }
+    }
+
+    // If it can't be loaded, perhaps because it's half written, the old one
+    // carries on until the file changes again.
+    fn reload_game(&mut self) {
+        let path = match self.watcher {
+            Some(ref watcher) => watcher.path().to_path_buf(),
+            None => return,
+        };
+        let old_memory = self.chip8.memory().to_vec();
+        if let Some(game) = self.load_with_loader(&path) {
+            if self.reload_mode == ReloadMode::KeepRam {
+                reload::keep_ram(&old_memory, &mut self.chip8, game.program.clone());
+            }
+            self.load_game(game);
+            self.notify(&format!("Reloaded {}", path.display()));
+        }
+    }
+
+    // Shows the launcher, if there is one. Without a game to go back to,
+    // leaving it quits.
+    pub fn open_launcher(&mut self, has_game: bool) {
+        if let Some(ref mut launcher) = self.launcher {
+            launcher.open();
+            self.has_game = has_game;
+            self.bindings = None;
+        }
+    }
+
+    fn is_launcher_open(&self) -> bool {
+        self.launcher.as_ref().is_some_and(Launcher::is_open)
+    }
+
+    fn close_launcher(&mut self) {
+        if let Some(ref mut launcher) = self.launcher {
+            launcher.close();
+        }
+        self.osd.set_menu(None);
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+        self.keymap_changed = true;
+    }
+
+    pub fn set_display_filter(&mut self, settings: FilterSettings) {
+        self.filter = FrameFilter::new(settings);
+    }
+
+    pub fn set_osd(&mut self, settings: OsdSettings) {
+        self.osd.set_settings(settings);
+    }
+
+    // For frontends that rotate the display, so that the OSD can be turned
+    // the other way to stay upright.
+    pub fn set_osd_rotation(&mut self, rotation: Rotation) {
+        self.osd.set_rotation(rotation);
+    }
+
+    pub fn set_palettes(&mut self, palettes: Vec<Palette>, selected: usize) {
+        self.palettes = palettes;
+        self.palette_index = selected;
+        self.palette_changed = true;
+    }
+
+    fn palette(&self) -> &Palette {
+        &self.palettes[self.palette_index]
+    }
+
+    // Prints a message, and shows it on the OSD for a while.
+    fn notify(&mut self, message: &str) {
+        if self.prints_messages {
+            eprintln!("{}", message);
+        }
+        self.osd.show_message(message);
+    }
+
+    pub fn start_recording(&mut self, path: &Path) {
+        let (on_color, off_color) = (self.palette().foreground(), self.palette().background());
+        match Recorder::create(path, RECORDING_SCALE, on_color, off_color) {
+            Ok(recorder) => {
+                self.recorder = Some(recorder);
+                self.notify(&format!("Recording to {}", path.display()));
+            }
+            Err(error) => self.notify(&format!("Couldn't record to {}: {}", path.display(), error)),
+        }
+    }
+
+    pub fn start_audio_recording(&mut self, path: &Path) {
+        match AudioRecorder::create(path, self.sample_rate) {
+            Ok(audio_recorder) => {
+                self.audio_recorder = Some(audio_recorder);
+                self.notify(&format!("Recording audio to {}", path.display()));
+            }
+            Err(error) => self.notify(&format!("Couldn't record audio to {}: {}", path.display(), error)),
+        }
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(_) => self.notify("Recording stopped"),
+                Err(error) => self.notify(&format!("Couldn't finish the recording: {}", error)),
+            }
+        }
+        if let Some(audio_recorder) = self.audio_recorder.take() {
+            match audio_recorder.finish() {
+                Ok(()) => self.notify("Audio recording stopped"),
+                Err(error) => self.notify(&format!("Couldn't finish the audio recording: {}", error)),
+            }
+        }
+    }
+
+    // Runs frames until we're asked to quit or hit the frame limit. Frames are
+    // paced at 60Hz unless the video sink takes care of that itself.
+    pub fn run<I, V, A>(&mut self, input: &mut I, video: &mut V, audio: &mut A)
+        where I: InputSource, V: VideoSink, A: AudioSink
+    {
+        let frame_duration = frame_duration();
+        let should_pace = !video.paces_frames();
+        let mut next_frame_time = Instant::now();
+
+        while self.run_frame(input, video, audio) {
+            if should_pace {
+                next_frame_time += frame_duration;
+                let now = Instant::now();
+                if next_frame_time > now {
+                    thread::sleep(next_frame_time - now);
+                } else {
+                    // We've fallen behind; don't try to catch up all at once.
+                    next_frame_time = now;
+                }
+            }
+        }
+
+        self.stop_recording();
+    }
+
+    // Handles input and runs a single frame. Returns false once it's time to
+    // stop.
+    pub fn run_frame<I, V, A>(&mut self, input: &mut I, video: &mut V, audio: &mut A) -> bool
+        where I: InputSource, V: VideoSink, A: AudioSink
+    {
+        if self.frame_limit.is_some_and(|limit| self.frames_run >= limit) {
+            return false;
+        }
+
+        if self.keymap_changed {
+            input.set_keymap(&self.keymap);
+            self.keymap_changed = false;
+        }
+        self.events.clear();
+        input.poll_events(&mut self.events);
+        for i in 0..self.events.len() {
+            let event = self.events[i].clone();
+            if !self.handle_event(event, video) {
+                return false;
+            }
+        }
+
+        if self.palette_changed {
+            video.set_palette(self.palette());
+            self.palette_changed = false;
+        }
+        let speed_description = self.pacer.describe();
+        if speed_description != self.speed_description {
+            self.osd.set_status(speed_description.clone());
+            self.speed_description = speed_description;
+            self.title_changed = true;
+        }
+        if self.title_changed {
+            match self.speed_description {
+                Some(ref speed) => video.set_title(&format!("{} ({})", self.title, speed)),
+                None => video.set_title(&self.title),
+            }
+            self.title_changed = false;
+        }
+
+        // Even from the launcher, since an editor can ask for a ROM.
+        self.poll_debugger();
+
+        if self.is_launcher_open() {
+            self.run_launcher_frame(video, audio);
+            self.frames_run += 1;
+            return true;
+        }
+
+        if self.watcher.as_mut().is_some_and(RomWatcher::poll) {
+            self.reload_game();
+        }
+
+        let start = Instant::now();
+        let mut must_stop = false;
+        let frames_executed = match self.pacer.next_budget(frame_duration()) {
+            FrameBudget::Frames(frames) => {
+                let mut executed = 0;
+                while executed < frames && !must_stop {
+                    must_stop = self.execute_frame();
+                    executed += 1;
+                }
+                executed
+            }
+            FrameBudget::Time(budget) => {
+                let mut frames = 0;
+                loop {
+                    must_stop = self.execute_frame();
+                    frames += 1;
+                    if must_stop || start.elapsed() >= budget {
+                        break frames;
+                    }
+                }
+            }
+        };
+        if let Some(ref mut bindings) = self.bindings {
+            bindings.frames_selected += 1;
+            let heading = format!("Keys {}/{}", bindings.selected + 1, bindings.lines.len());
+            let menu = osd::list_menu(heading, &bindings.lines, bindings.selected, bindings.frames_selected);
+            self.osd.set_menu(Some(menu));
+        }
+        let dirty_rows = self.chip8.take_dirty_rows();
+        let has_changed = self.filter.apply(self.chip8.get_screen_ref(), dirty_rows);
+        let (shades, has_changed) = self.osd.draw(self.filter.shades(), self.chip8.key_states(), has_changed);
+        match self.comparison {
+            Some(ref mut comparison) => {
+                let (grid, has_changed) = comparison.grid(shades, has_changed);
+                video.present_grid(&grid, has_changed);
+            }
+            None => video.present(shades, has_changed),
+        }
+        let instructions = frames_executed as u64 * self.cycles_per_frame as u64;
+        self.osd.record_frame(instructions, start.elapsed());
+
+        let mut samples = vec![0i16; self.samples_per_frame.next_frame()];
+        if self.pacer.is_paused() {
+            // Nothing's happening, so there's nothing to hear or record.
+            audio.queue_samples(&samples);
+            self.frames_run += 1;
+            return true;
+        }
+        // However many frames just ran, their sound is fitted into one
+        // frame's worth of samples, so the pitch stays the same whatever the
+        // speed.
+        self.chip8.render_audio(&mut samples, self.sample_rate);
+        if self.sound.is_muted || self.pacer.mutes_audio() {
+            // Muting only silences playback; recordings still get the buzzer.
+            audio.queue_samples(&vec![0i16; samples.len()]);
+        } else {
+            audio.queue_samples(&samples);
+        }
+
+        let video_error = match self.recorder {
+            Some(ref mut recorder) => recorder.capture_frame(self.chip8.get_screen_ref()).err(),
+            None => None,
+        };
+        if let Some(error) = video_error {
+            self.recorder = None;
+            self.notify(&format!("Recording stopped: {}", error));
+        }
+        let audio_error = match self.audio_recorder {
+            Some(ref mut audio_recorder) => audio_recorder.capture_frame(&samples).err(),
+            None => None,
+        };
+        if let Some(error) = audio_error {
+            self.audio_recorder = None;
+            self.notify(&format!("Audio recording stopped: {}", error));
+        }
+
+        self.frames_run += 1;
+        true
+    }
+
+    fn poll_debugger(&mut self) {
+        let mut events = Vec::new();
+        match self.debugger {
+            Some(ref mut debugger) => debugger.poll(&mut self.chip8, &mut events),
+            None => return,
+        }
+        for event in events {
+            match event {
+                DebugEvent::Message(message) => self.notify(&message),
+                DebugEvent::Launch(path) => {
+                    let result = match self.loader {
+                        Some(ref mut loader) => loader(&path, &mut self.chip8),
+                        None => Err(String::from("ROMs can't be loaded here")),
+                    };
+                    let result = match result {
+                        Ok(game) => {
+                            self.load_game(game);
+                            self.close_launcher();
+                            self.pacer.set_paused(false);
+                            Ok(())
+                        }
+                        Err(error) => {
+                            self.notify(&error);
+                            Err(error)
+                        }
+                    };
+                    if let Some(ref mut debugger) = self.debugger {
+                        debugger.launched(result);
+                    }
+                }
+            }
+        }
+    }
+
+    // Runs a frame on every machine. Returns true if there's no point
+    // running any more this time round: the debugger has the machine
+    // stopped, or the machines being compared have just started to differ.
+    fn execute_frame(&mut self) -> bool {
+        let (has_hit_breakpoint, is_stopped) = match self.debugger {
+            Some(ref mut debugger) => {
+                let has_hit_breakpoint = debugger.execute_for_frame(&mut self.chip8, self.cycles_per_frame);
+                (has_hit_breakpoint, debugger.is_stopped())
+            }
+            None => {
+                execute_for_frame(&mut self.chip8, self.cycles_per_frame);
+                (false, false)
+            }
+        };
+        if has_hit_breakpoint {
+            let pc = self.chip8.cpu_state().pc;
+            self.notify(&format!("Stopped at breakpoint {:#05X}", pc));
+        }
+        if is_stopped {
+            return true;
+        }
+        let has_diverged = match self.comparison {
+            Some(ref mut comparison) => comparison.execute_frame(self.chip8.get_screen_ref()),
+            None => false,
+        };
+        // Machines being compared stop on the first frame they differ.
+        if has_diverged {
+            self.pacer.set_paused(true);
+            let frame = self.comparison.as_ref().and_then(Comparison::first_difference).unwrap_or(0);
+            self.notify(&format!("The displays differ from frame {}", frame));
+        }
+        has_diverged
+    }
+
+    // Sends a key to whichever machines have the focus.
+    fn set_key_state(&mut self, index: u8, state: bool) {
+        let is_ours = match self.comparison {
+            Some(ref mut comparison) => comparison.set_key_state(index, state),
+            None => true,
+        };
+        if is_ours {
+            self.chip8.set_key_state(index, state);
+        }
+    }
+
+    // The game stays where it is while the launcher is up, with the
+    // highlighted ROM running behind the list instead.
+    fn run_launcher_frame<V: VideoSink, A: AudioSink>(&mut self, video: &mut V, audio: &mut A) {
+        let preview = match self.launcher {
+            Some(ref mut launcher) => {
+                self.osd.set_menu(Some(launcher.menu()));
+                launcher.run_preview()
+            }
+            None => return,
+        };
+        let (shades, _) = self.osd.draw(&preview, [false; 16], true);
+        video.present(shades, true);
+        audio.queue_samples(&vec![0i16; self.samples_per_frame.next_frame()]);
+    }
+
+    fn handle_launcher_action(&mut self, action: LauncherAction) -> bool {
+        match action {
+            LauncherAction::Moved => {}
+            LauncherAction::Load(path) => self.switch_game(&path),
+            LauncherAction::Close => self.close_launcher(),
+            LauncherAction::Quit => return false,
+        }
+        true
+    }
+
+    fn handle_event<V: VideoSink>(&mut self, event: InputEvent, video: &mut V) -> bool {
+        match event {
+            InputEvent::Quit => return false,
+            InputEvent::KeyDown { key, repeat } => {
+                // The launcher gets first go at keys, and keeps the game from
+                // seeing any.
+                let is_choosing = self.is_launcher_open();
+                if is_choosing {
+                    let can_close = self.has_game;
+                    let action = self.launcher.as_mut().and_then(|launcher| launcher.handle_key(key, can_close));
+                    if let Some(action) = action {
+                        return self.handle_launcher_action(action);
+                    }
+                }
+                if self.handle_bindings_key(key) {
+                    return true;
+                }
+                if let Some(command) = self.keymap.command_for(key, repeat) {
+                    return self.handle_command(command, video);
+                }
+                if let Some(index) = self.keymap.chip8_key_for(key).filter(|_| !is_choosing) {
+                    self.set_key_state(index, true);
+                }
+            }
+            InputEvent::KeyUp { key } => {
+                if let Some(Command::Turbo) = self.keymap.command_for(key, false) {
+                    self.pacer.set_turbo(false);
+                }
+                if let Some(index) = self.keymap.chip8_key_for(key) {
+                    self.set_key_state(index, false);
+                }
+            }
+            InputEvent::FileDropped(path) => self.switch_game(&path),
+            InputEvent::Message(message) => self.notify(&message),
+        }
+        true
+    }
+
+    // While the bindings are listed, the arrow keys scroll through them and
+    // Esc or Enter puts them away. Returns whether the key was used.
+    fn handle_bindings_key(&mut self, key: Key) -> bool {
+        let bindings = match self.bindings {
+            Some(ref mut bindings) => bindings,
+            None => return false,
+        };
+        let last = bindings.lines.len().saturating_sub(1);
+        let index = match key {
+            Key::Up => bindings.selected.saturating_sub(1),
+            Key::Down => (bindings.selected + 1).min(last),
+            Key::PageUp => bindings.selected.saturating_sub(MENU_ENTRIES),
+            Key::PageDown => (bindings.selected + MENU_ENTRIES).min(last),
+            Key::Escape | Key::Enter | Key::Backspace => {
+                self.bindings = None;
+                self.osd.set_menu(None);
+                return true;
+            }
+            _ => return false,
+        };
+        if index != bindings.selected {
+            bindings.selected = index;
+            bindings.frames_selected = 0;
+        }
+        true
+    }
+
+    fn handle_command<V: VideoSink>(&mut self, command: Command, video: &mut V) -> bool {
+        match command {
+            Command::Quit => return false,
+            Command::ShowBindings => {
+                if self.bindings.take().is_some() {
+                    self.osd.set_menu(None);
+                } else {
+                    self.bindings = Some(BindingsList {
+                        lines: self.keymap.binding_lines(),
+                        selected: 0,
+                        frames_selected: 0,
+                    });
+                }
+            }
+            Command::ToggleRecording => {
+                if self.recorder.is_some() || self.audio_recorder.is_some() {
+                    self.stop_recording();
+                } else {
+                    let base_name = recording_base_name();
+                    self.start_recording(&PathBuf::from(format!("{}.gif", base_name)));
+                    self.start_audio_recording(&PathBuf::from(format!("{}.wav", base_name)));
+                }
+            }
+            Command::ToggleFullscreen => {
+                video.toggle_fullscreen();
+            }
+            Command::CyclePalette => {
+                self.palette_index = (self.palette_index + 1) % self.palettes.len();
+                self.palette_changed = true;
+                let message = format!("Palette: {}", self.palette().name);
+                self.notify(&message);
+            }
+            Command::TogglePause => {
+                let is_paused = !self.pacer.is_paused();
+                self.pacer.set_paused(is_paused);
+                self.notify(if is_paused { "Paused" } else { "Resumed" });
+            }
+            Command::Turbo => {
+                self.pacer.set_turbo(true);
+            }
+            Command::ToggleSlowMotion => {
+                self.pacer.toggle_slow_motion();
+            }
+            Command::ToggleStats => {
+                self.osd.toggle_stats();
+            }
+            Command::ToggleKeyDisplay => {
+                self.osd.toggle_keys();
+            }
+            Command::OpenLauncher => {
+                let has_game = self.has_game;
+                self.open_launcher(has_game);
+            }
+            Command::Reset(kind) => {
+                self.chip8.reset(kind);
+                if let Some(ref mut comparison) = self.comparison {
+                    comparison.reset(kind);
+                }
+                self.notify(if kind == ResetKind::Hard { "Hard reset" } else { "Reset" });
+            }
+            Command::CycleFocus => {
+                let message = match self.comparison {
+                    Some(ref mut comparison) => comparison.cycle_focus(),
+                    None => return true,
+                };
+                for index in 0..16 {
+                    self.chip8.set_key_state(index, false);
+                }
+                self.notify(&message);
+            }
+            Command::Sound(command) => {
+                self.sound.apply(command);
+                self.chip8.set_buzzer_settings(self.sound.settings);
+                let message = format!("Buzzer: {}", self.sound.describe());
+                self.notify(&message);
+            }
+        }
+        true
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Named after the time, with a number on the end if there's already a
+// recording from the same second.
+fn recording_base_name() -> String {
+    let base_name = format!("chip8-{}", unix_timestamp());
+    let is_taken = |name: &str| {
+        Path::new(&format!("{}.gif", name)).exists() || Path::new(&format!("{}.wav", name)).exists()
+    };
+    if !is_taken(&base_name) {
+        return base_name;
+    }
+    (2..).map(|number| format!("{}-{}", base_name, number))
+        .find(|name| !is_taken(name))
+        .unwrap()
+}
+
+fn frame_duration() -> Duration {
+    Duration::new(0, 1_000_000_000 / FRAMES_PER_SECOND)
+}
+
+pub fn execute_for_frame<R: Rng>(chip8: &mut Chip8<R>, cycles: u32) {
+    for _ in 0..cycles {
+        chip8.execute_next_opcode();
+    }
+    // Timers should execute at 60Hz.
+    chip8.update_timers();
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::{Path, PathBuf};
+
+    use chip8_emu::{Chip8, ResetKind};
+    use chip8_emu::audio::BuzzerSettings;
+
+    use rand::Rng;
+
+    use frontend::{InputEvent, InputSource, Key};
+    use headless::{HeadlessInput, HeadlessVideo, NullAudio};
+    use keymap::Keymap;
+    use launcher::Launcher;
+    use romdb::{RomDatabase, RomInfo};
+    use sound::SoundControls;
+    use super::{Game, Runner};
+
+    fn runner_with_rom(rom_bytes: &[u8]) -> Runner<::rand::XorShiftRng> {
+        let mut rom = [0u8; 3584];
+        rom[..rom_bytes.len()].copy_from_slice(rom_bytes);
+        let mut chip8 = Chip8::new_and_init();
+        chip8.load_rom(&rom);
+        let sound = SoundControls {
+            settings: BuzzerSettings::default(),
+            is_muted: false,
+        };
+        Runner::new(chip8, sound, 44100)
+    }
+
+    // Loads the ROM into the machine at 0x200, with no database entry or
+    // keymap of its own. The other modules' tests use it too.
+    pub fn test_game<R: Rng>(rom: &[u8], path: &Path, chip8: &mut Chip8<R>) -> Game {
+        chip8.load_rom_at(rom, 0x200);
+        chip8.reset(ResetKind::Hard);
+        Game {
+            path: path.to_path_buf(),
+            info: RomInfo::default(),
+            keymap: Keymap::default(),
+            cycles_per_frame: None,
+            program: 0x200..0x200 + rom.len(),
+            database_error: None,
+        }
+    }
+
+    #[test]
+    fn test_headless_run_stops_at_frame_limit() {
+        // Draw the "0" glyph at the top left, then loop forever.
+        let mut runner = runner_with_rom(&[0x00, 0xE0, 0xF0, 0x29, 0xD0, 0x15, 0x12, 0x06]);
+        runner.set_frame_limit(Some(5));
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut HeadlessInput::new(), &mut video, &mut NullAudio::new(44100));
+
+        assert_eq!(5, video.frames_presented);
+        // Only the first frame draws anything.
+        assert_eq!(1, video.frames_changed);
+        assert_eq!([true, true, true, true, false], video.last_screen[0][..5]);
+        assert_eq!([true, false, false, true, false], video.last_screen[1][..5]);
+    }
+
+    #[test]
+    fn test_scripted_keys_reach_the_emulator() {
+        // Wait for a key into V0, then draw that digit at (V1, V1).
+        let mut runner = runner_with_rom(&[0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06]);
+        runner.set_frame_limit(Some(10));
+        let mut input = HeadlessInput::with_script(vec![
+            (3, InputEvent::KeyDown { key: Key::Char('2'), repeat: false }),
+            (5, InputEvent::KeyUp { key: Key::Char('2') }),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+
+        // The top row of the "2" glyph.
+        assert_eq!([true, true, true, true], video.last_screen[0][..4]);
+        // Second row is only lit on the right.
+        assert_eq!([false, false, false, true], video.last_screen[1][..4]);
+    }
+
+    #[test]
+    fn test_holding_turbo_runs_more_frames() {
+        // Count the delay timer down from 50, then draw the "0" glyph.
+        let rom = [0x60, 0x32, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04,
+                   0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x10];
+        let mut runner = runner_with_rom(&rom);
+        runner.set_frame_limit(Some(20));
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut HeadlessInput::new(), &mut video, &mut NullAudio::new(44100));
+        assert_eq!(0, video.frames_changed);
+
+        let mut runner = runner_with_rom(&rom);
+        runner.set_frame_limit(Some(20));
+        let mut input = HeadlessInput::with_script(vec![
+            (0, InputEvent::KeyDown { key: Key::Tab, repeat: false }),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+        assert_eq!([true, true, true, true], video.last_screen[0][..4]);
+    }
+
+    #[test]
+    fn test_leaving_the_launcher_without_a_game_quits() {
+        let mut runner = runner_with_rom(&[0x12, 0x00]);
+        let launcher = Launcher::new(Path::new("no such directory"), RomDatabase::new(), None);
+        runner.set_launcher(launcher);
+        runner.set_loader(Box::new(|path: &Path, _: &mut Chip8<_>| Err(format!("{} isn't there", path.display()))));
+        runner.open_launcher(false);
+        let mut input = HeadlessInput::with_script(vec![
+            (3, InputEvent::KeyDown { key: Key::Escape, repeat: false }),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+        assert_eq!(3, video.frames_presented);
+        // "No ROMs in" is written across the top.
+        assert!(video.last_screen[1..6].iter().any(|row| row.iter().any(|&pixel| pixel)));
+    }
+
+    #[test]
+    fn test_dropped_files_are_loaded() {
+        let mut runner = runner_with_rom(&[0x12, 0x00]);
+        runner.set_frame_limit(Some(5));
+        runner.set_loader(Box::new(|path: &Path, chip8: &mut Chip8<_>| {
+            // Draw the "0" glyph at the top left, then loop forever.
+            Ok(test_game(&[0x00, 0xE0, 0xF0, 0x29, 0xD0, 0x15, 0x12, 0x06], path, chip8))
+        }));
+        let mut input = HeadlessInput::with_script(vec![
+            (2, InputEvent::FileDropped(PathBuf::from("zero.ch8"))),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+        assert_eq!([true, true, true, true], video.last_screen[0][..4]);
+    }
+
+    #[test]
+    fn test_input_messages_are_shown() {
+        let mut runner = runner_with_rom(&[0x12, 0x00]);
+        runner.set_frame_limit(Some(3));
+        runner.set_prints_messages(false);
+        let mut input = HeadlessInput::with_script(vec![
+            (1, InputEvent::Message(String::from("Controller connected"))),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+        // Messages go along the bottom.
+        assert!(video.last_screen[26..31].iter().any(|row| row.iter().any(|&pixel| pixel)));
+    }
+
+    #[test]
+    fn test_loading_another_game_clears_the_screen() {
+        // Draw the "0" glyph at the top left, then loop forever.
+        let mut runner = runner_with_rom(&[0x00, 0xE0, 0xF0, 0x29, 0xD0, 0x15, 0x12, 0x06]);
+        runner.set_frame_limit(Some(5));
+        runner.set_loader(Box::new(|path: &Path, chip8: &mut Chip8<_>| {
+            // Loop forever without drawing anything.
+            Ok(test_game(&[0x12, 0x00], path, chip8))
+        }));
+        let mut input = HeadlessInput::with_script(vec![
+            (3, InputEvent::FileDropped(PathBuf::from("blank.ch8"))),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+        assert_eq!(2, video.frames_changed);
+        assert!(video.last_screen.iter().all(|row| row.iter().all(|&pixel| !pixel)));
+    }
+
+    #[test]
+    fn test_database_settings_are_in_the_title() {
+        let mut runner = runner_with_rom(&[0x12, 0x00]);
+        runner.set_frame_limit(Some(3));
+        runner.set_loader(Box::new(|path: &Path, chip8: &mut Chip8<_>| {
+            Ok(Game {
+                info: RomInfo {
+                    title: Some(String::from("Pong")),
+                    platform: Some(String::from("originalChip8")),
+                    tickrate: Some(15),
+                    ..RomInfo::default()
+                },
+                cycles_per_frame: Some(15),
+                ..test_game(&[0x12, 0x00], path, chip8)
+            })
+        }));
+        let mut input = HeadlessInput::with_script(vec![
+            (1, InputEvent::FileDropped(PathBuf::from("pong.ch8"))),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+        assert_eq!("Chip 8 emu - Pong (originalChip8, 15 instructions per frame)", video.title);
+    }
+
+    #[test]
+    fn test_bindings_are_listed_on_the_display() {
+        let mut runner = runner_with_rom(&[0x12, 0x00]);
+        runner.set_frame_limit(Some(5));
+        let mut input = HeadlessInput::with_script(vec![
+            (1, InputEvent::KeyDown { key: Key::Function(1), repeat: false }),
+            (2, InputEvent::KeyDown { key: Key::Down, repeat: false }),
+            (3, InputEvent::KeyDown { key: Key::Down, repeat: false }),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+        assert_eq!(2, runner.bindings.as_ref().unwrap().selected);
+        assert!(video.last_screen[1..6].iter().any(|row| row.iter().any(|&pixel| pixel)));
+
+        // Esc puts the list away rather than quitting.
+        let event = InputEvent::KeyDown { key: Key::Escape, repeat: false };
+        assert!(runner.handle_event(event, &mut video));
+        assert!(runner.bindings.is_none());
+    }
+
+    // Keeps track of the deadzones it's been given.
+    struct DeadzoneInput {
+        deadzones: Vec<f32>,
+        events: Vec<InputEvent>,
+    }
+
+    impl InputSource for DeadzoneInput {
+        fn poll_events(&mut self, events: &mut Vec<InputEvent>) {
+            events.append(&mut self.events);
+        }
+
+        fn set_keymap(&mut self, keymap: &Keymap) {
+            self.deadzones.push(keymap.deadzone);
+        }
+    }
+
+    #[test]
+    fn test_input_hears_about_each_games_keymap() {
+        let mut runner = runner_with_rom(&[0x12, 0x00]);
+        runner.set_frame_limit(Some(3));
+        runner.set_loader(Box::new(|path: &Path, chip8: &mut Chip8<_>| {
+            let mut game = test_game(&[0x12, 0x00], path, chip8);
+            game.keymap.deadzone = 0.1;
+            Ok(game)
+        }));
+        let mut input = DeadzoneInput {
+            deadzones: Vec::new(),
+            events: vec![InputEvent::FileDropped(PathBuf::from("other.ch8"))],
+        };
+        runner.run(&mut input, &mut HeadlessVideo::new(), &mut NullAudio::new(44100));
+        assert_eq!(vec![Keymap::default().deadzone, 0.1], input.deadzones);
+    }
+
+    #[test]
+    fn test_escape_quits() {
+        let mut runner = runner_with_rom(&[0x12, 0x00]);
+        let mut input = HeadlessInput::with_script(vec![
+            (2, InputEvent::KeyDown { key: Key::Escape, repeat: false }),
+        ]);
+        let mut video = HeadlessVideo::new();
+        runner.run(&mut input, &mut video, &mut NullAudio::new(44100));
+        assert_eq!(2, video.frames_presented);
+    }
+}
@@ -0,0 +1,358 @@
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use sdl2;
+use sdl2::{EventPump, Sdl};
+use sdl2::GameControllerSubsystem;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Renderer, Texture};
+use sdl2::video::FullscreenType;
+
+use compare;
+use crt;
+use display;
+use display::DisplaySettings;
+use frontend::{AudioSink, Grid, InputEvent, InputSource, Key, PadInput, Shades, VideoSink};
+use gamepad;
+use gamepad::{PadAxis, PadState};
+use keymap;
+use keymap::{KeyLayout, Keymap};
+use palette::Palette;
+
+// SDL 2 frontend: a window for video, the keyboard and any game controllers
+// for input, and a queued audio device for the buzzer.
+
+const SAMPLE_RATE: i32 = 44100;
+// If playback falls behind by more than this many frames of audio, new frames
+// are dropped instead of queued so that the sound doesn't lag the picture.
+const MAX_QUEUED_AUDIO_FRAMES: u32 = 4;
+
+pub struct SdlInput {
+    // Keeps SDL alive for as long as the frontend is in use.
+    _sdl: Sdl,
+    event_pump: EventPump,
+    layout: KeyLayout,
+    // None if SDL couldn't start its controller support, in which case we
+    // make do with the keyboard.
+    controller_subsystem: Option<GameControllerSubsystem>,
+    // Controllers stay open for as long as they're plugged in.
+    controllers: Vec<GameController>,
+    pad_state: PadState,
+    // Shared with the video sink; set when the window needs repainting even
+    // though the emulated display hasn't changed.
+    window_damaged: Rc<Cell<bool>>,
+}
+
+pub struct SdlVideo {
+    renderer: Renderer<'static>,
+    settings: DisplaySettings,
+    palette: Palette,
+    // The emulated display lives in a streaming texture, already rotated,
+    // which is scaled up to the window in a single copy. With CRT effects on,
+    // the texture holds the scaled up image instead.
+    texture: Texture,
+    texture_size: (usize, usize),
+    pixels: Vec<u8>,
+    crt_pixels: Vec<u8>,
+    window_damaged: Rc<Cell<bool>>,
+}
+
+pub struct SdlAudio {
+    audio_queue: AudioQueue<i16>,
+}
+
+pub fn init(settings: DisplaySettings) -> (SdlInput, SdlVideo, SdlAudio) {
+    let sdl = sdl2::init().unwrap();
+    let event_pump = sdl.event_pump().unwrap();
+    // SDL reports controllers that are already plugged in as being added, so
+    // they get opened along with any that turn up later.
+    let controller_subsystem = sdl.game_controller().ok();
+
+    let audio = sdl.audio().unwrap();
+    let video = sdl.video().unwrap();
+    let mut texture_size = settings.rotation.rotated_size(64, 32);
+    if settings.grid != (1, 1) {
+        texture_size = compare::grid_image_size(texture_size, settings.grid);
+    }
+    let mut window_builder = video.window("Chip 8 emu",
+                                          texture_size.0 as u32 * settings.scale,
+                                          texture_size.1 as u32 * settings.scale);
+    window_builder.resizable().position_centered();
+    if settings.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().unwrap();
+    // We only present when something has changed, so frames are paced by the
+    // runner rather than by vsync.
+    let renderer = window.renderer().accelerated().build().unwrap();
+    let texture = create_texture(&renderer, texture_size);
+    // Make sure the first frame gets drawn.
+    let window_damaged = Rc::new(Cell::new(true));
+
+    // The emulator renders the buzzer itself, a frame at a time, so all we
+    // need to do is queue up those samples for playback.
+    let requested_spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: None,
+    };
+    let audio_queue: AudioQueue<i16> = audio.open_queue(None, &requested_spec).unwrap();
+    audio_queue.resume();
+
+    let input = SdlInput {
+        _sdl: sdl,
+        event_pump,
+        // Until the runner hands over its keymap.
+        layout: KeyLayout::Position,
+        controller_subsystem,
+        controllers: Vec::new(),
+        pad_state: PadState::new(gamepad::DEFAULT_DEADZONE),
+        window_damaged: window_damaged.clone(),
+    };
+    (input,
+     SdlVideo {
+         renderer,
+         settings,
+         palette: Palette::default(),
+         texture,
+         texture_size,
+         pixels: vec![0; texture_size.0 * texture_size.1 * 3],
+         crt_pixels: Vec::new(),
+         window_damaged,
+     },
+     SdlAudio { audio_queue })
+}
+
+impl InputSource for SdlInput {
+    fn set_keymap(&mut self, keymap: &Keymap) {
+        self.layout = keymap.layout;
+        self.pad_state.set_deadzone(keymap.deadzone);
+    }
+
+    fn poll_events(&mut self, events: &mut Vec<InputEvent>) {
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => {
+                    events.push(InputEvent::Quit);
+                }
+                Event::Window { win_event: WindowEvent::Exposed, .. } |
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } => {
+                    self.window_damaged.set(true);
+                }
+                Event::KeyDown { keycode, scancode, repeat, .. } => {
+                    if let Some(key) = key_for(keycode, scancode, self.layout) {
+                        events.push(InputEvent::KeyDown { key, repeat });
+                    }
+                }
+                Event::KeyUp { keycode, scancode, .. } => {
+                    if let Some(key) = key_for(keycode, scancode, self.layout) {
+                        events.push(InputEvent::KeyUp { key });
+                    }
+                }
+                Event::DropFile { filename, .. } => {
+                    events.push(InputEvent::FileDropped(PathBuf::from(filename)));
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(ref subsystem) = self.controller_subsystem {
+                        open_controller(subsystem, which, &mut self.controllers, events);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|controller| controller.instance_id() != which);
+                    self.pad_state.disconnect(which, events);
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let Some(input) = pad_input_for(button) {
+                        self.pad_state.button(which, input, true, events);
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(input) = pad_input_for(button) {
+                        self.pad_state.button(which, input, false, events);
+                    }
+                }
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    self.pad_state.axis(which, pad_axis_for(axis), value, events);
+                }
+                _ => {
+                    // Skip this event.
+                }
+            }
+        }
+    }
+}
+
+fn open_controller(subsystem: &GameControllerSubsystem,
+                   index: i32,
+                   controllers: &mut Vec<GameController>,
+                   events: &mut Vec<InputEvent>) {
+    match subsystem.open(index as u32) {
+        Ok(controller) => {
+            let id = controller.instance_id();
+            if controllers.iter().all(|open| open.instance_id() != id) {
+                events.push(InputEvent::Message(format!("Controller connected: {}", controller.name())));
+                controllers.push(controller);
+            }
+        }
+        Err(error) => events.push(InputEvent::Message(format!("Couldn't open controller {}: {}", index, error))),
+    }
+}
+
+// The guide button is left alone, since the system often claims it.
+fn pad_input_for(button: Button) -> Option<PadInput> {
+    let input = match button {
+        Button::A => PadInput::A,
+        Button::B => PadInput::B,
+        Button::X => PadInput::X,
+        Button::Y => PadInput::Y,
+        Button::Back => PadInput::Back,
+        Button::Start => PadInput::Start,
+        Button::LeftShoulder => PadInput::LeftShoulder,
+        Button::RightShoulder => PadInput::RightShoulder,
+        Button::LeftStick => PadInput::LeftStickButton,
+        Button::RightStick => PadInput::RightStickButton,
+        Button::DPadUp => PadInput::DPadUp,
+        Button::DPadDown => PadInput::DPadDown,
+        Button::DPadLeft => PadInput::DPadLeft,
+        Button::DPadRight => PadInput::DPadRight,
+        Button::Guide => return None,
+    };
+    Some(input)
+}
+
+fn pad_axis_for(axis: Axis) -> PadAxis {
+    match axis {
+        Axis::LeftX => PadAxis::LeftX,
+        Axis::LeftY => PadAxis::LeftY,
+        Axis::RightX => PadAxis::RightX,
+        Axis::RightY => PadAxis::RightY,
+        Axis::TriggerLeft => PadAxis::LeftTrigger,
+        Axis::TriggerRight => PadAxis::RightTrigger,
+    }
+}
+
+// SDL's key names are close enough to ours that we can go by those. Scancodes
+// are named after the key in that position on a US keyboard.
+fn key_for(keycode: Option<Keycode>, scancode: Option<Scancode>, layout: KeyLayout) -> Option<Key> {
+    match layout {
+        KeyLayout::Position => scancode.and_then(|scancode| keymap::parse_key_name(scancode.name())),
+        KeyLayout::Label => keycode.and_then(|keycode| keymap::parse_key_name(&keycode.name())),
+    }
+}
+
+fn create_texture(renderer: &Renderer, size: (usize, usize)) -> Texture {
+    renderer.create_texture_streaming(PixelFormatEnum::RGB24, size.0 as u32, size.1 as u32).unwrap()
+}
+
+impl SdlVideo {
+    // Scales the picture in self.pixels up to fit the window, and shows it.
+    fn show_pixels(&mut self, image_size: (usize, usize)) {
+        let (output_width, output_height) = self.renderer.output_size().unwrap();
+        let viewport = display::fit(output_width, output_height,
+                                    image_size.0 as u32, image_size.1 as u32,
+                                    self.settings.integer_scaling);
+
+        let effects = self.settings.crt;
+        let (texture_size, texture_pixels) = if effects.is_enabled() {
+            let scale = crt::output_scale(viewport.width as usize, image_size.0);
+            crt::render(effects, &self.pixels, image_size.0, image_size.1, scale, &mut self.crt_pixels);
+            ((image_size.0 * scale, image_size.1 * scale), &self.crt_pixels)
+        } else {
+            (image_size, &self.pixels)
+        };
+        if texture_size != self.texture_size {
+            self.texture = create_texture(&self.renderer, texture_size);
+            self.texture_size = texture_size;
+        }
+        self.texture.update(None, texture_pixels, texture_size.0 * 3).unwrap();
+        // Anything outside of the viewport is border, in the background colour.
+        let border = self.palette.background();
+        self.renderer.set_draw_color(Color::RGB(border[0], border[1], border[2]));
+        self.renderer.clear();
+        let destination = Rect::new(viewport.x, viewport.y, viewport.width, viewport.height);
+        self.renderer.copy(&self.texture, None, destination).unwrap();
+        self.renderer.present();
+    }
+}
+
+impl VideoSink for SdlVideo {
+    fn present(&mut self, shades: &Shades, has_changed: bool) {
+        let window_damaged = self.window_damaged.replace(false);
+        if !has_changed && !window_damaged {
+            return;
+        }
+
+        // Size everything from the screen we're given rather than assuming
+        // 64x32, so that a change of resolution just means a new texture.
+        let (width, height) = (shades[0].len(), shades.len());
+        let rotation = self.settings.rotation;
+        let image_size = rotation.rotated_size(width, height);
+        self.pixels.resize(image_size.0 * image_size.1 * 3, 0);
+        for (y, row) in shades.iter().enumerate() {
+            for (x, &shade) in row.iter().enumerate() {
+                let color = self.palette.shade_color(shade);
+                let (image_x, image_y) = rotation.rotate_position(x, y, width, height);
+                let offset = (image_y * image_size.0 + image_x) * 3;
+                self.pixels[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+        self.show_pixels(image_size);
+    }
+
+    fn present_grid(&mut self, grid: &Grid, has_changed: bool) {
+        let window_damaged = self.window_damaged.replace(false);
+        if !has_changed && !window_damaged {
+            return;
+        }
+        let image_size = compare::render_grid(grid, &self.palette, self.settings.rotation, &mut self.pixels);
+        self.show_pixels(image_size);
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        self.settings.fullscreen = !self.settings.fullscreen;
+        // Desktop fullscreen keeps the current video mode, so switching is
+        // quick and the scaling above takes care of the rest.
+        let fullscreen_type = if self.settings.fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        if let Some(window) = self.renderer.window_mut() {
+            if let Err(error) = window.set_fullscreen(fullscreen_type) {
+                println!("Couldn't change fullscreen mode: {}", error);
+            }
+        }
+        self.window_damaged.set(true);
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+        self.window_damaged.set(true);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Some(window) = self.renderer.window_mut() {
+            // Only fails if the title has a nul in it.
+            let _ = window.set_title(title);
+        }
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn sample_rate(&self) -> u32 {
+        self.audio_queue.spec().freq as u32
+    }
+
+    fn queue_samples(&mut self, samples: &[i16]) {
+        let max_queued_bytes = MAX_QUEUED_AUDIO_FRAMES * samples.len() as u32 * 2;
+        if self.audio_queue.size() < max_queued_bytes {
+            self.audio_queue.queue(samples);
+        }
+    }
+}
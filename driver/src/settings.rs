@@ -0,0 +1,647 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use chip8_emu::Quirks;
+use chip8_emu::audio::BuzzerSettings;
+
+use toml;
+
+use compare::MachineSpec;
+use crt;
+use display;
+use display::{DisplaySettings, Rotation};
+use filter;
+use filter::DisplayFilter;
+use osd::OsdSettings;
+use reload;
+use reload::ReloadMode;
+use romdb;
+use runner::FRAMES_PER_SECOND;
+use sound;
+use sound::SoundControls;
+use speed;
+use speed::SpeedSettings;
+
+// Everything the driver can be told, from the command line or from
+// settings.toml in the user's config directory. The settings file takes the
+// same options, without the dashes:
+//
+//   scale = 8
+//   palette = "amber"
+//   mute = true
+//
+// Anything given on the command line wins.
+
+const MIN_IPS: u32 = 60;
+const MAX_IPS: u32 = 60000;
+
+struct OptionSpec {
+    name: &'static str,
+    // What the value looks like, for options that take one.
+    value: Option<&'static str>,
+    // Lines of help, for --help.
+    help: &'static [&'static str],
+    // An example for the settings file, or None for options that only make
+    // sense for a single run.
+    example: Option<&'static str>,
+}
+
+static OPTIONS: [OptionSpec; 39] = [
+    OptionSpec { name: "help", value: None, example: None,
+                 help: &["Show this help"] },
+    OptionSpec { name: "frontend", value: Some("sdl|terminal|terminal-braille|headless"), example: Some("\"sdl\""),
+                 help: &["Where to run the ROM (default sdl)"] },
+    OptionSpec { name: "frames", value: Some("count"), example: None,
+                 help: &["Stop after this many frames"] },
+    OptionSpec { name: "paused", value: None, example: Some("true"),
+                 help: &["Start paused"] },
+    OptionSpec { name: "ips", value: Some("count"), example: Some("600"),
+                 help: &["Instructions per second, from 60 to 60000 (default 600)"] },
+    OptionSpec { name: "turbo", value: Some("times|uncapped"), example: Some("4"),
+                 help: &["How much faster to run while the turbo key (Tab)",
+                         "is held, from 2 to 50 times, or uncapped (default 4)"] },
+    OptionSpec { name: "turbo-audio", value: Some("mute|pitch"), example: Some("\"mute\""),
+                 help: &["Mute the buzzer in turbo, or keep playing it at its",
+                         "normal pitch (default mute)"] },
+    OptionSpec { name: "slow-motion", value: Some("speed"), example: Some("0.25"),
+                 help: &["Speed in slow motion (F3), from 0.05 to 0.9", "(default 0.25)"] },
+    OptionSpec { name: "quirks", value: Some("platform"), example: Some("\"chip48\""),
+                 help: &["Emulate the quirks of another interpreter: default,",
+                         "originalChip8, hybridVIP, modernChip8, chip8x, chip48,",
+                         "superchip1, superchip, megachip8 or xochip"] },
+    OptionSpec { name: "seed", value: Some("number"), example: Some("1234"),
+                 help: &["Seed the random number generator, for repeatable runs"] },
+    OptionSpec { name: "compare", value: Some("machines"), example: None,
+                 help: &["Run more machines alongside, separated by ;, each",
+                         "with its own quirks=, ips= or seed=, like",
+                         "\"quirks=chip48;ips=1200,seed=7\""] },
+    OptionSpec { name: "gdb", value: Some("port"), example: None,
+                 help: &["Wait for GDB to attach on this local port, with",
+                         "target remote localhost:port"] },
+    OptionSpec { name: "dap", value: Some("port"), example: None,
+                 help: &["Wait for an editor to debug the ROM over the Debug",
+                         "Adapter Protocol, on this local port"] },
+    OptionSpec { name: "load-address", value: Some("address"), example: Some("\"0x200\""),
+                 help: &["Where to load the ROM and start running (default 0x200)"] },
+    OptionSpec { name: "record", value: Some("file.gif|file.y4m"), example: None,
+                 help: &["Record the display from the start"] },
+    OptionSpec { name: "record-audio", value: Some("file.wav"), example: None,
+                 help: &["Record the buzzer from the start"] },
+    OptionSpec { name: "waveform", value: Some("square|triangle|sine|vip"), example: Some("\"square\""),
+                 help: &["Buzzer waveform (default square)"] },
+    OptionSpec { name: "tone", value: Some("hz"), example: Some("125"),
+                 help: &["Buzzer frequency (default 125)"] },
+    OptionSpec { name: "volume", value: Some("percent"), example: Some("50"),
+                 help: &["Buzzer volume, 0 to 100"] },
+    OptionSpec { name: "mute", value: None, example: Some("true"),
+                 help: &["Start with the buzzer muted"] },
+    OptionSpec { name: "scale", value: Some("factor"), example: Some("10"),
+                 help: &["Initial window size, in pixels per CHIP-8 pixel", "(default 10)"] },
+    OptionSpec { name: "integer-scaling", value: None, example: Some("true"),
+                 help: &["Only scale the display by whole numbers"] },
+    OptionSpec { name: "rotate", value: Some("0|90|180|270"), example: Some("0"),
+                 help: &["Rotate the display clockwise"] },
+    OptionSpec { name: "fullscreen", value: None, example: Some("true"),
+                 help: &["Start in fullscreen"] },
+    OptionSpec { name: "crt", value: Some("effect,..."), example: Some("\"scanlines,bloom\""),
+                 help: &["CRT look: any of scanlines, gaps, bloom and", "curvature, or all"] },
+    OptionSpec { name: "filter", value: Some("none|phosphor|merge"), example: Some("\"phosphor\""),
+                 help: &["Reduce flicker by fading pixels out slowly,", "or by merging each frame with the last"] },
+    OptionSpec { name: "filter-strength", value: Some("percent"), example: Some("60"),
+                 help: &["How strongly the filter applies (default 60", "for phosphor, 100 for merge)"] },
+    OptionSpec { name: "show-stats", value: None, example: Some("true"),
+                 help: &["Show the frame rate, instructions per second and", "time per frame (F4)"] },
+    OptionSpec { name: "show-keys", value: None, example: Some("true"),
+                 help: &["Show which CHIP-8 keys are held (F12)"] },
+    OptionSpec { name: "keymap", value: Some("file.toml"), example: Some("\"keymap.toml\""),
+                 help: &["Load key bindings, with optional per-ROM", "overrides"] },
+    OptionSpec { name: "palette", value: Some("name|colours"), example: Some("\"classic\""),
+                 help: &["Colour palette: classic, green, amber, lcd,",
+                         "octo, high-contrast, one from a palette file,",
+                         "or two or four colours like #000000,#ffffff"] },
+    OptionSpec { name: "palette-file", value: Some("file"), example: Some("\"palettes.txt\""),
+                 help: &["Load more palettes, one per line as",
+                         "name = #background #foreground [#plane2 #both]"] },
+    OptionSpec { name: "hot-reload", value: Some("off|reset|keep-ram"), example: Some("\"reset\""),
+                 help: &["Load the ROM again when its file changes, from",
+                         "scratch or keeping the memory outside the program",
+                         "(default reset)"] },
+    OptionSpec { name: "rom-dir", value: Some("directory"), example: Some("\"roms\""),
+                 help: &["Where to look for ROMs to pick from (default the",
+                         "ROM's directory, or the current one)"] },
+    OptionSpec { name: "rom-db", value: Some("programs.json"), example: Some("\"programs.json\""),
+                 help: &["Look up settings for the ROM by its hash, in",
+                         "the chip-8-database format (default programs.json",
+                         "in the config directory, if it's there)"] },
+    OptionSpec { name: "rom-overrides", value: Some("file.json"), example: Some("\"rom-overrides.json\""),
+                 help: &["Override entries in the ROM database (default",
+                         "rom-overrides.json in the config directory)"] },
+    OptionSpec { name: "save-settings", value: None, example: None,
+                 help: &["Save the other options given to the settings file,",
+                         "so they apply from now on"] },
+    OptionSpec { name: "reset-settings", value: None, example: None,
+                 help: &["Start the settings file over with everything at its",
+                         "default"] },
+    OptionSpec { name: "version", value: None, example: None,
+                 help: &["Show the version"] },
+];
+
+pub struct Settings {
+    pub rom_path: Option<PathBuf>,
+    pub frontend: String,
+    pub frame_limit: Option<u64>,
+    pub start_paused: bool,
+    pub cycles_per_frame: Option<u32>,
+    pub speed: SpeedSettings,
+    pub quirks: Option<Quirks>,
+    pub seed: Option<u64>,
+    // Other machines to run the ROM on, to compare them.
+    pub compare: Vec<MachineSpec>,
+    pub gdb_port: Option<u16>,
+    pub dap_port: Option<u16>,
+    pub load_address: Option<u16>,
+    pub record_path: Option<PathBuf>,
+    pub record_audio_path: Option<PathBuf>,
+    pub sound: SoundControls,
+    pub display: DisplaySettings,
+    // Kept apart from the display settings so that the ROM database can tell
+    // whether it's been asked for.
+    pub rotation: Option<Rotation>,
+    pub display_filter: DisplayFilter,
+    pub osd: OsdSettings,
+    pub filter_strength: Option<u8>,
+    pub keymap_path: Option<PathBuf>,
+    pub palette_name: Option<String>,
+    pub palette_path: Option<PathBuf>,
+    pub reload_mode: ReloadMode,
+    pub rom_dir: Option<PathBuf>,
+    pub rom_db_path: Option<PathBuf>,
+    pub rom_overrides_path: Option<PathBuf>,
+    pub show_help: bool,
+    pub show_version: bool,
+    pub save_settings: bool,
+    pub reset_settings: bool,
+    // The options that have been set and belong in the settings file, as
+    // they'd be written there.
+    saved: BTreeMap<&'static str, toml::Value>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            rom_path: None,
+            frontend: String::from("sdl"),
+            frame_limit: None,
+            start_paused: false,
+            cycles_per_frame: None,
+            speed: SpeedSettings::default(),
+            quirks: None,
+            seed: None,
+            compare: Vec::new(),
+            gdb_port: None,
+            dap_port: None,
+            load_address: None,
+            record_path: None,
+            record_audio_path: None,
+            sound: SoundControls {
+                settings: BuzzerSettings::default(),
+                is_muted: false,
+            },
+            display: DisplaySettings::default(),
+            rotation: None,
+            display_filter: DisplayFilter::None,
+            osd: OsdSettings::default(),
+            filter_strength: None,
+            keymap_path: None,
+            palette_name: None,
+            palette_path: None,
+            reload_mode: ReloadMode::Reset,
+            rom_dir: None,
+            rom_db_path: None,
+            rom_overrides_path: None,
+            show_help: false,
+            show_version: false,
+            save_settings: false,
+            reset_settings: false,
+            saved: BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    // Applies options from the command line, not including the program name.
+    pub fn parse_args<I: Iterator<Item = String>>(&mut self, mut args: I) -> Result<(), String> {
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None if arg == "-h" => "help",
+                None if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("Unknown option: {}", arg));
+                }
+                None => {
+                    if self.rom_path.is_some() {
+                        return Err(format!("Only one ROM can be given, but got {} as well", arg));
+                    }
+                    self.rom_path = Some(PathBuf::from(arg));
+                    continue;
+                }
+            };
+            let spec = find_option(name).ok_or_else(|| format!("Unknown option: {}", arg))?;
+            let value = match spec.value {
+                Some(description) => {
+                    let value = args.next()
+                        .ok_or_else(|| format!("--{} needs a value: {}", name, description))?;
+                    Some(value)
+                }
+                None => None,
+            };
+            self.set(spec, value.as_deref())?;
+        }
+        Ok(())
+    }
+
+    // Applies a settings file.
+    pub fn parse_file(&mut self, text: &str) -> Result<(), String> {
+        let table = match text.parse::<toml::Value>().map_err(|error| error.to_string())? {
+            toml::Value::Table(table) => table,
+            _ => return Err(String::from("expected a table")),
+        };
+        for (name, value) in table {
+            let spec = find_option(&name)
+                .filter(|spec| spec.example.is_some())
+                .ok_or_else(|| format!("{} can't be set in the settings file", name))?;
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) if spec.value.is_none() => value.to_string(),
+                _ => return Err(format!("{} has the wrong kind of value", name)),
+            };
+            self.set(spec, Some(&value))?;
+        }
+        Ok(())
+    }
+
+    pub fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|error| error.to_string())?;
+        self.parse_file(&text)
+    }
+
+    // Writes the settings that have been set out to a settings file, along
+    // with the rest as comments.
+    pub fn save_file(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(path)?;
+        file.write_all(self.to_file_text().as_bytes())
+    }
+
+    fn to_file_text(&self) -> String {
+        let mut text = String::from("# Settings for chip8_emu_driver. Options given on the command line\n\
+                                     # take precedence over these. Uncomment a line to change it.\n");
+        for spec in OPTIONS.iter() {
+            let example = match spec.example {
+                Some(example) => example,
+                None => continue,
+            };
+            text.push('\n');
+            for line in spec.help {
+                text.push_str(&format!("# {}\n", line));
+            }
+            match self.saved.get(spec.name) {
+                Some(value) => text.push_str(&format!("{} = {}\n", spec.name, value)),
+                None => text.push_str(&format!("# {} = {}\n", spec.name, example)),
+            }
+        }
+        text
+    }
+
+    fn set(&mut self, spec: &'static OptionSpec, value: Option<&str>) -> Result<(), String> {
+        let name = spec.name;
+        // Flags can be turned off again from the settings file.
+        let is_on = match value {
+            None | Some("true") => true,
+            Some("false") => false,
+            Some(_) if spec.value.is_none() => return Err(format!("{} should be true or false", name)),
+            Some(_) => true,
+        };
+        let text = value.unwrap_or("");
+        let invalid = |expected: &str| format!("Invalid value for --{}: {} (expected {})", name, text, expected);
+
+        match name {
+            "help" => self.show_help = true,
+            "version" => self.show_version = true,
+            "save-settings" => self.save_settings = true,
+            "reset-settings" => self.reset_settings = true,
+            "paused" => self.start_paused = is_on,
+            "mute" => self.sound.is_muted = is_on,
+            "integer-scaling" => self.display.integer_scaling = is_on,
+            "fullscreen" => self.display.fullscreen = is_on,
+            "show-stats" => self.osd.show_stats = is_on,
+            "show-keys" => self.osd.show_keys = is_on,
+            "frontend" => {
+                match text {
+                    "sdl" | "terminal" | "terminal-braille" | "headless" => self.frontend = String::from(text),
+                    _ => return Err(invalid("sdl, terminal, terminal-braille or headless")),
+                }
+            }
+            "frames" => {
+                self.frame_limit = Some(text.parse::<u64>().map_err(|_| invalid("a number of frames"))?);
+            }
+            "ips" => {
+                let cycles_per_frame = parse_ips(text)
+                    .ok_or_else(|| invalid(&format!("a number from {} to {}", MIN_IPS, MAX_IPS)))?;
+                self.cycles_per_frame = Some(cycles_per_frame);
+            }
+            "turbo" => {
+                self.speed.turbo = speed::parse_turbo(text).ok_or_else(|| invalid("a number from 2 to 50, or uncapped"))?;
+            }
+            "turbo-audio" => {
+                self.speed.turbo_audio = speed::parse_turbo_audio(text).ok_or_else(|| invalid("mute or pitch"))?;
+            }
+            "slow-motion" => {
+                self.speed.slow_motion = speed::parse_slow_motion(text)
+                    .ok_or_else(|| invalid("a number from 0.05 to 0.9"))?;
+            }
+            "quirks" => {
+                self.quirks = Some(parse_quirks(text).ok_or_else(|| {
+                    invalid(&format!("default or one of {}", romdb::platform_names().join(", ")))
+                })?);
+            }
+            "seed" => {
+                self.seed = Some(text.parse::<u64>().map_err(|_| invalid("a whole number"))?);
+            }
+            "compare" => {
+                self.compare = text.split(';').map(parse_machine).collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| invalid("quirks=, ips= or seed= for each machine"))?;
+            }
+            "gdb" => self.gdb_port = Some(parse_port(text).ok_or_else(|| invalid("a port number"))?),
+            "dap" => self.dap_port = Some(parse_port(text).ok_or_else(|| invalid("a port number"))?),
+            "load-address" => {
+                let address = parse_address(text).ok_or_else(|| invalid("an address below 0x1000"))?;
+                self.load_address = Some(address);
+            }
+            "record" => self.record_path = Some(PathBuf::from(text)),
+            "record-audio" => self.record_audio_path = Some(PathBuf::from(text)),
+            "waveform" => {
+                self.sound.settings.waveform = sound::parse_waveform(text)
+                    .ok_or_else(|| invalid("square, triangle, sine or vip"))?;
+            }
+            "tone" => {
+                self.sound.settings.frequency = sound::parse_frequency(text)
+                    .ok_or_else(|| invalid("a frequency in Hz"))?;
+            }
+            "volume" => {
+                self.sound.settings.volume = sound::parse_volume(text)
+                    .ok_or_else(|| invalid("a percentage"))?;
+            }
+            "scale" => {
+                self.display.scale = display::parse_scale(text).ok_or_else(|| invalid("a number from 1 to 50"))?;
+            }
+            "rotate" => {
+                self.rotation = Some(display::parse_rotation(text).ok_or_else(|| invalid("0, 90, 180 or 270"))?);
+            }
+            "crt" => {
+                self.display.crt = crt::parse_effects(text)
+                    .ok_or_else(|| invalid("scanlines, gaps, bloom, curvature, all or none"))?;
+            }
+            "filter" => {
+                self.display_filter = filter::parse_filter(text).ok_or_else(|| invalid("none, phosphor or merge"))?;
+            }
+            "filter-strength" => {
+                self.filter_strength = Some(filter::parse_strength(text).ok_or_else(|| invalid("a percentage"))?);
+            }
+            "keymap" => self.keymap_path = Some(PathBuf::from(text)),
+            "palette" => self.palette_name = Some(String::from(text)),
+            "palette-file" => self.palette_path = Some(PathBuf::from(text)),
+            "hot-reload" => {
+                self.reload_mode = reload::parse_reload_mode(text).ok_or_else(|| invalid("off, reset or keep-ram"))?;
+            }
+            "rom-dir" => self.rom_dir = Some(PathBuf::from(text)),
+            "rom-db" => self.rom_db_path = Some(PathBuf::from(text)),
+            "rom-overrides" => self.rom_overrides_path = Some(PathBuf::from(text)),
+            _ => unreachable!("no handling for option {}", name),
+        }
+
+        if spec.example.is_some() {
+            let saved_value = match spec.value {
+                None => toml::Value::Boolean(is_on),
+                Some(_) => saved_value(text),
+            };
+            self.saved.insert(name, saved_value);
+        }
+        Ok(())
+    }
+}
+
+// Numbers go in the file as numbers, and everything else as strings.
+fn saved_value(text: &str) -> toml::Value {
+    if let Ok(number) = text.parse::<i64>() {
+        return toml::Value::Integer(number);
+    }
+    match text.parse::<f64>() {
+        Ok(number) if number.is_finite() && text.contains('.') => toml::Value::Float(number),
+        _ => toml::Value::String(String::from(text)),
+    }
+}
+
+fn find_option(name: &str) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|spec| spec.name == name)
+}
+
+// Turns instructions per second into instructions per frame.
+fn parse_ips(value: &str) -> Option<u32> {
+    let ips = value.parse::<u32>().ok().filter(|ips| (MIN_IPS..=MAX_IPS).contains(ips))?;
+    let fps = FRAMES_PER_SECOND;
+    Some((ips + fps / 2) / fps)
+}
+
+fn parse_quirks(value: &str) -> Option<Quirks> {
+    if value == "default" {
+        Some(Quirks::default())
+    } else {
+        romdb::platform_quirks(value)
+    }
+}
+
+// One machine for --compare, like "quirks=chip48,ips=1200". Anything not given
+// is the same as for the first machine.
+fn parse_machine(value: &str) -> Option<MachineSpec> {
+    let mut machine = MachineSpec::default();
+    for setting in value.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+        let (name, value) = setting.split_once('=')?;
+        let value = value.trim();
+        match name.trim() {
+            "quirks" => machine.quirks = Some(parse_quirks(value)?),
+            "ips" => machine.cycles_per_frame = Some(parse_ips(value)?),
+            "seed" => machine.seed = Some(value.parse::<u64>().ok()?),
+            _ => return None,
+        }
+    }
+    Some(machine)
+}
+
+// Takes addresses in hex, like 0x200, or in decimal.
+fn parse_address(value: &str) -> Option<u16> {
+    let address = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse::<u16>().ok(),
+    };
+    address.filter(|&address| address < 0x1000)
+}
+
+fn parse_port(value: &str) -> Option<u16> {
+    value.parse::<u16>().ok().filter(|&port| port > 0)
+}
+
+// Lists the options, for --help.
+pub fn options_help() -> String {
+    let mut lines = Vec::new();
+    for spec in OPTIONS.iter() {
+        let option = match spec.value {
+            Some(value) => format!("--{} {}", spec.name, value),
+            None => format!("--{}", spec.name),
+        };
+        // Long options get their help on the lines after.
+        let mut help = spec.help.iter();
+        if option.len() < 28 {
+            lines.push(format!("  {:<28}{}", option, help.next().unwrap_or(&"")));
+        } else {
+            lines.push(format!("  {}", option));
+        }
+        for line in help {
+            lines.push(format!("  {:<28}{}", "", line));
+        }
+    }
+    lines.join("\n")
+}
+
+// Where settings and other files live: the usual place for the platform,
+// in a directory of our own.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map(|base| base.join("chip8_emu"))
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_emu::Quirks;
+
+    use compare::MachineSpec;
+    use display::Rotation;
+    use romdb;
+    use super::{options_help, Settings, OPTIONS};
+
+    fn parse(args: &[&str]) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        settings.parse_args(args.iter().map(|arg| arg.to_string()))?;
+        Ok(settings)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let settings = parse(&["--ips", "900", "--quirks", "chip48", "--seed", "42",
+                               "--load-address", "0x600", "--paused", "--mute", "--rotate", "90",
+                               "pong.ch8"]).unwrap();
+        assert_eq!(Some(15), settings.cycles_per_frame);
+        assert_eq!(romdb::platform_quirks("chip48"), settings.quirks);
+        assert_eq!(Some(42), settings.seed);
+        assert_eq!(Some(0x600), settings.load_address);
+        assert!(settings.start_paused);
+        assert!(settings.sound.is_muted);
+        assert_eq!(Some(Rotation::Quarter), settings.rotation);
+        assert_eq!("pong.ch8", settings.rom_path.unwrap().to_str().unwrap());
+
+        assert_eq!(Some(Quirks::default()), parse(&["--quirks", "default"]).unwrap().quirks);
+        assert!(parse(&["-h"]).unwrap().show_help);
+    }
+
+    fn parse_error(args: &[&str]) -> String {
+        parse(args).err().expect("should have failed")
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        assert!(parse_error(&["--ips", "10"]).contains("60 to 60000"));
+        assert!(parse(&["--load-address", "0x1000"]).is_err());
+        assert!(parse_error(&["--quirks", "vip2000"]).contains("chip48"));
+        assert!(parse_error(&["--scale"]).contains("needs a value"));
+        assert!(parse_error(&["--explode"]).starts_with("Unknown option"));
+        assert!(parse(&["a.ch8", "b.ch8"]).is_err());
+    }
+
+    #[test]
+    fn test_compare_machines() {
+        let settings = parse(&["--compare", "quirks=chip48; ips=1200, seed=7;"]).unwrap();
+        assert_eq!(3, settings.compare.len());
+        assert_eq!(romdb::platform_quirks("chip48"), settings.compare[0].quirks);
+        assert_eq!(Some(20), settings.compare[1].cycles_per_frame);
+        assert_eq!(Some(7), settings.compare[1].seed);
+        assert_eq!(MachineSpec::default(), settings.compare[2]);
+        assert!(parse_error(&["--compare", "speed=2"]).contains("quirks="));
+        assert!(parse(&["--compare", "ips=1"]).is_err());
+    }
+
+    #[test]
+    fn test_command_line_overrides_file() {
+        let mut settings = Settings::default();
+        settings.parse_file("scale = 4\npalette = \"amber\"\nmute = true\nfullscreen = false\n").unwrap();
+        settings.parse_args(vec![String::from("--scale"), String::from("6")].into_iter()).unwrap();
+        assert_eq!(6, settings.display.scale);
+        assert_eq!(Some(String::from("amber")), settings.palette_name);
+        assert!(settings.sound.is_muted);
+
+        assert!(Settings::default().parse_file("record = \"x.gif\"\n").is_err());
+        assert!(Settings::default().parse_file("mute = \"loud\"\n").is_err());
+        assert!(Settings::default().parse_file("scale = 500\n").is_err());
+    }
+
+    #[test]
+    fn test_saved_settings_read_back() {
+        let settings = parse(&["--scale", "6", "--palette", "green", "--fullscreen", "--frames", "5",
+                               "--slow-motion", "0.5", "--turbo", "uncapped"]).unwrap();
+        let text = settings.to_file_text();
+        assert!(text.contains("\nscale = 6\n"));
+        assert!(text.contains("\nslow-motion = 0.5\n"));
+        assert!(text.contains("\npalette = \"green\"\n"));
+        assert!(text.contains("\n# volume = 50\n"));
+        // Only for one run.
+        assert!(!text.contains("frames"));
+
+        let mut loaded = Settings::default();
+        loaded.parse_file(&text).unwrap();
+        assert_eq!(6, loaded.display.scale);
+        assert!(loaded.display.fullscreen);
+        assert_eq!(settings.speed, loaded.speed);
+        assert_eq!(None, loaded.frame_limit);
+
+        // The template with nothing set is valid too.
+        Settings::default().parse_file(&Settings::default().to_file_text()).unwrap();
+    }
+
+    #[test]
+    fn test_help_lists_every_option() {
+        let help = options_help();
+        for spec in OPTIONS.iter() {
+            assert!(help.contains(&format!("--{}", spec.name)));
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use chip8_emu::audio::{BuzzerSettings, Waveform};
+
+// Buzzer controls that can be changed from the command line and while running.
+
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 8000.0;
+// Volume steps for the hotkeys, as a percentage of full scale.
+pub const VOLUME_STEP: i32 = 5;
+
+#[derive(Clone, Copy, Debug)]
+pub enum SoundCommand {
+    CycleWaveform,
+    ToggleMute,
+    // Change in volume, as a percentage of full scale.
+    ChangeVolume(i32),
+    // Change in pitch, in semitones.
+    ChangePitch(i32),
+}
+
+pub struct SoundControls {
+    pub settings: BuzzerSettings,
+    pub is_muted: bool,
+}
+
+impl SoundControls {
+    pub fn apply(&mut self, command: SoundCommand) {
+        match command {
+            SoundCommand::CycleWaveform => {
+                self.settings.waveform = match self.settings.waveform {
+                    Waveform::Square => Waveform::Triangle,
+                    Waveform::Triangle => Waveform::Sine,
+                    Waveform::Sine => Waveform::VipBuzz,
+                    Waveform::VipBuzz => Waveform::Square,
+                };
+            }
+            SoundCommand::ToggleMute => {
+                self.is_muted = !self.is_muted;
+            }
+            SoundCommand::ChangeVolume(delta) => {
+                let percent = volume_to_percent(self.settings.volume) + delta;
+                self.settings.volume = percent_to_volume(percent.clamp(0, 100));
+            }
+            SoundCommand::ChangePitch(semitones) => {
+                let frequency = self.settings.frequency * 2f32.powf(semitones as f32 / 12.0);
+                self.settings.frequency = frequency.clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} {:.0}Hz, volume {}%{}",
+                waveform_name(self.settings.waveform),
+                self.settings.frequency,
+                volume_to_percent(self.settings.volume),
+                if self.is_muted { " (muted)" } else { "" })
+    }
+}
+
+pub fn parse_waveform(name: &str) -> Option<Waveform> {
+    match name {
+        "square" => Some(Waveform::Square),
+        "triangle" => Some(Waveform::Triangle),
+        "sine" => Some(Waveform::Sine),
+        "vip" => Some(Waveform::VipBuzz),
+        _ => None,
+    }
+}
+
+pub fn waveform_name(waveform: Waveform) -> &'static str {
+    match waveform {
+        Waveform::Square => "square",
+        Waveform::Triangle => "triangle",
+        Waveform::Sine => "sine",
+        Waveform::VipBuzz => "vip",
+    }
+}
+
+pub fn parse_frequency(value: &str) -> Option<f32> {
+    value.parse::<f32>().ok().filter(|&f| (MIN_FREQUENCY..=MAX_FREQUENCY).contains(&f))
+}
+
+// Volume is given as a percentage of the loudest possible sample.
+pub fn parse_volume(value: &str) -> Option<i16> {
+    value.parse::<i32>().ok().filter(|&v| (0..=100).contains(&v)).map(percent_to_volume)
+}
+
+fn percent_to_volume(percent: i32) -> i16 {
+    (i16::MAX as i32 * percent / 100) as i16
+}
+
+fn volume_to_percent(volume: i16) -> i32 {
+    (volume as i32 * 100 + i16::MAX as i32 / 2) / i16::MAX as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_emu::audio::{BuzzerSettings, Waveform};
+
+    use super::{parse_frequency, parse_volume, volume_to_percent, SoundCommand, SoundControls};
+
+    fn controls() -> SoundControls {
+        SoundControls {
+            settings: BuzzerSettings::default(),
+            is_muted: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_bounds() {
+        assert_eq!(Some(0), parse_volume("0"));
+        assert_eq!(Some(i16::MAX), parse_volume("100"));
+        assert_eq!(Some(50), parse_volume("50").map(volume_to_percent));
+        assert_eq!(None, parse_volume("101"));
+        assert_eq!(None, parse_volume("-1"));
+        assert_eq!(None, parse_volume("loud"));
+
+        assert_eq!(Some(20.0), parse_frequency("20"));
+        assert_eq!(Some(8000.0), parse_frequency("8000"));
+        assert_eq!(Some(440.5), parse_frequency("440.5"));
+        assert_eq!(None, parse_frequency("19.9"));
+        assert_eq!(None, parse_frequency("8001"));
+        assert_eq!(None, parse_frequency("NaN"));
+    }
+
+    #[test]
+    fn test_commands_round_trip() {
+        let mut sound = controls();
+        sound.apply(SoundCommand::ToggleMute);
+        assert!(sound.describe().ends_with("(muted)"));
+        sound.apply(SoundCommand::ToggleMute);
+        assert_eq!("square 125Hz, volume 9%", sound.describe());
+
+        for _ in 0..4 {
+            sound.apply(SoundCommand::CycleWaveform);
+        }
+        assert_eq!(Waveform::Square, sound.settings.waveform);
+
+        sound.apply(SoundCommand::ChangeVolume(10));
+        sound.apply(SoundCommand::ChangeVolume(-10));
+        assert_eq!(9, volume_to_percent(sound.settings.volume));
+        sound.apply(SoundCommand::ChangeVolume(200));
+        assert_eq!(i16::MAX, sound.settings.volume);
+        sound.apply(SoundCommand::ChangeVolume(-200));
+        assert_eq!(0, sound.settings.volume);
+
+        sound.apply(SoundCommand::ChangePitch(12));
+        assert_eq!(250.0, sound.settings.frequency);
+        sound.apply(SoundCommand::ChangePitch(-12));
+        assert_eq!(125.0, sound.settings.frequency);
+        // The pitch stays within what can be given on the command line.
+        sound.apply(SoundCommand::ChangePitch(-48));
+        assert_eq!(20.0, sound.settings.frequency);
+        sound.apply(SoundCommand::ChangePitch(200));
+        assert_eq!(8000.0, sound.settings.frequency);
+    }
+}
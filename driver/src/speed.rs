@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+// How fast the emulator runs compared to real time. The runner always shows
+// 60 frames a second; what changes with the speed is how many emulated frames
+// go into each of them. Turbo runs several (or as many as there's time for),
+// slow motion only runs one every few frames, and pausing runs none.
+
+pub const DEFAULT_TURBO: Turbo = Turbo::Times(4);
+pub const DEFAULT_SLOW_MOTION: f32 = 0.25;
+const MAX_TURBO: u32 = 50;
+const MIN_SLOW_MOTION: f32 = 0.05;
+const MAX_SLOW_MOTION: f32 = 0.9;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Turbo {
+    Times(u32),
+    // As many frames as fit in the time between displayed frames.
+    Uncapped,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TurboAudio {
+    Mute,
+    // Keep playing the buzzer, squeezed into real time at its normal pitch.
+    PitchCorrected,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeedSettings {
+    pub turbo: Turbo,
+    pub turbo_audio: TurboAudio,
+    // Fraction of normal speed.
+    pub slow_motion: f32,
+}
+
+impl Default for SpeedSettings {
+    fn default() -> SpeedSettings {
+        SpeedSettings {
+            turbo: DEFAULT_TURBO,
+            turbo_audio: TurboAudio::Mute,
+            slow_motion: DEFAULT_SLOW_MOTION,
+        }
+    }
+}
+
+// How much emulation to fit into the next displayed frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameBudget {
+    Frames(u32),
+    // Keep running frames for this long, but always at least one.
+    Time(Duration),
+}
+
+pub struct FramePacer {
+    settings: SpeedSettings,
+    is_paused: bool,
+    is_turbo: bool,
+    is_slow_motion: bool,
+    // The part of a frame that slow motion hasn't run yet.
+    owed: f32,
+}
+
+impl FramePacer {
+    pub fn new(settings: SpeedSettings) -> FramePacer {
+        FramePacer {
+            settings,
+            is_paused: false,
+            is_turbo: false,
+            is_slow_motion: false,
+            owed: 0.0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: SpeedSettings) {
+        self.settings = settings;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    pub fn set_paused(&mut self, is_paused: bool) {
+        self.is_paused = is_paused;
+    }
+
+    pub fn set_turbo(&mut self, is_turbo: bool) {
+        self.is_turbo = is_turbo;
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.is_slow_motion = !self.is_slow_motion;
+        self.owed = 0.0;
+    }
+
+    // Pausing beats turbo, which beats slow motion, so holding turbo while in
+    // slow motion catches up quickly and letting go slows down again.
+    pub fn next_budget(&mut self, frame_duration: Duration) -> FrameBudget {
+        if self.is_paused {
+            FrameBudget::Frames(0)
+        } else if self.is_turbo {
+            match self.settings.turbo {
+                Turbo::Times(times) => FrameBudget::Frames(times),
+                // Leave some of the frame for drawing and everything else.
+                Turbo::Uncapped => FrameBudget::Time(frame_duration * 3 / 4),
+            }
+        } else if self.is_slow_motion {
+            self.owed += self.settings.slow_motion;
+            let frames = self.owed.floor();
+            self.owed -= frames;
+            FrameBudget::Frames(frames as u32)
+        } else {
+            FrameBudget::Frames(1)
+        }
+    }
+
+    pub fn mutes_audio(&self) -> bool {
+        self.is_turbo && !self.is_paused && self.settings.turbo_audio == TurboAudio::Mute
+    }
+
+    // A short note on anything other than normal speed, for the title bar.
+    pub fn describe(&self) -> Option<String> {
+        if self.is_paused {
+            Some(String::from("paused"))
+        } else if self.is_turbo {
+            match self.settings.turbo {
+                Turbo::Times(times) => Some(format!("turbo x{}", times)),
+                Turbo::Uncapped => Some(String::from("turbo")),
+            }
+        } else if self.is_slow_motion {
+            Some(format!("slow motion x{}", self.settings.slow_motion))
+        } else {
+            None
+        }
+    }
+}
+
+pub fn parse_turbo(value: &str) -> Option<Turbo> {
+    if value == "uncapped" {
+        return Some(Turbo::Uncapped);
+    }
+    value.parse::<u32>().ok()
+        .filter(|times| (2..=MAX_TURBO).contains(times))
+        .map(Turbo::Times)
+}
+
+pub fn parse_turbo_audio(value: &str) -> Option<TurboAudio> {
+    match value {
+        "mute" => Some(TurboAudio::Mute),
+        "pitch" => Some(TurboAudio::PitchCorrected),
+        _ => None,
+    }
+}
+
+pub fn parse_slow_motion(value: &str) -> Option<f32> {
+    value.parse::<f32>().ok().filter(|&speed| (MIN_SLOW_MOTION..=MAX_SLOW_MOTION).contains(&speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FrameBudget, FramePacer, SpeedSettings, Turbo};
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    fn frames_over(pacer: &mut FramePacer, count: usize) -> Vec<u32> {
+        (0..count).map(|_| match pacer.next_budget(FRAME) {
+            FrameBudget::Frames(frames) => frames,
+            FrameBudget::Time(_) => panic!("expected a frame count"),
+        }).collect()
+    }
+
+    #[test]
+    fn test_slow_motion_runs_a_fraction_of_frames() {
+        let mut pacer = FramePacer::new(SpeedSettings { slow_motion: 0.25, ..SpeedSettings::default() });
+        assert_eq!(vec![1, 1, 1], frames_over(&mut pacer, 3));
+        pacer.toggle_slow_motion();
+        assert_eq!(vec![0, 0, 0, 1, 0, 0, 0, 1], frames_over(&mut pacer, 8));
+        assert_eq!(Some(String::from("slow motion x0.25")), pacer.describe());
+    }
+
+    #[test]
+    fn test_pause_beats_turbo_beats_slow_motion() {
+        let mut pacer = FramePacer::new(SpeedSettings::default());
+        pacer.toggle_slow_motion();
+        pacer.set_turbo(true);
+        assert_eq!(vec![4], frames_over(&mut pacer, 1));
+        assert!(pacer.mutes_audio());
+
+        pacer.set_paused(true);
+        assert_eq!(vec![0], frames_over(&mut pacer, 1));
+        assert!(!pacer.mutes_audio());
+        assert_eq!(Some(String::from("paused")), pacer.describe());
+
+        pacer.set_paused(false);
+        pacer.set_settings(SpeedSettings { turbo: Turbo::Uncapped, ..SpeedSettings::default() });
+        assert_eq!(FrameBudget::Time(FRAME * 3 / 4), pacer.next_budget(FRAME));
+    }
+
+    #[test]
+    fn test_parse_speeds() {
+        assert_eq!(Some(Turbo::Times(8)), super::parse_turbo("8"));
+        assert_eq!(Some(Turbo::Uncapped), super::parse_turbo("uncapped"));
+        assert_eq!(None, super::parse_turbo("1"));
+        assert_eq!(Some(0.5), super::parse_slow_motion("0.5"));
+        assert_eq!(None, super::parse_slow_motion("2"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// What an assembler can tell the debugger about a ROM: which source line each
+// instruction came from, and where its labels are. It's a text file with one
+// address on each line, followed by either a source file and line or a
+// label:
+//
+//   # Comments start with a hash.
+//   0x200 main
+//   0x200 game.8o:12
+//   0x202 game.8o:13
+//
+// Source files are found relative to the symbol map.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    pub file: PathBuf,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolMap {
+    // Both sorted by address.
+    lines: Vec<(u16, SourceLocation)>,
+    labels: Vec<(u16, String)>,
+    dir: PathBuf,
+}
+
+impl SymbolMap {
+    pub fn load(path: &Path) -> Result<SymbolMap, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Couldn't read symbols from {}: {}", path.display(), error))?;
+        let mut symbols = SymbolMap::parse(&text)
+            .map_err(|error| format!("Couldn't read symbols from {}: {}", path.display(), error))?;
+        symbols.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(symbols)
+    }
+
+    pub fn parse(text: &str) -> Result<SymbolMap, String> {
+        let mut symbols = SymbolMap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("line {} isn't an address and a source line or label", number + 1);
+            let (address, rest) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let address = parse_address(address).ok_or_else(invalid)?;
+            let rest = rest.trim();
+            let location = rest.rsplit_once(':')
+                .and_then(|(file, line)| Some((file, line.parse::<u32>().ok()?)));
+            match location {
+                Some((file, line)) => {
+                    symbols.lines.push((address, SourceLocation { file: PathBuf::from(file), line }));
+                }
+                None => symbols.labels.push((address, String::from(rest))),
+            }
+        }
+        symbols.lines.sort_by_key(|&(address, _)| address);
+        symbols.labels.sort_by_key(|&(address, _)| address);
+        Ok(symbols)
+    }
+
+    // Where the instruction at this address came from.
+    pub fn location(&self, address: u16) -> Option<&SourceLocation> {
+        self.lines.iter().find(|&&(at, _)| at == address).map(|(_, location)| location)
+    }
+
+    // The source file as the editor would know it.
+    pub fn source_path(&self, location: &SourceLocation) -> PathBuf {
+        self.dir.join(&location.file)
+    }
+
+    // The first instruction from this line of the file, or if there isn't one
+    // the next line that has one. Returns its address and line.
+    pub fn address_of(&self, path: &Path, line: u32) -> Option<(u16, u32)> {
+        self.lines.iter()
+            .filter(|(_, location)| location.line >= line && path.ends_with(&location.file))
+            .min_by_key(|(address, location)| (location.line, *address))
+            .map(|(address, location)| (*address, location.line))
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.iter().find(|&&(at, _)| at == address).map(|(_, label)| label.as_str())
+    }
+
+    // The nearest label at or before this address, which is usually the
+    // subroutine it's in.
+    pub fn enclosing_label(&self, address: u16) -> Option<&str> {
+        self.labels.iter().rev().find(|&&(at, _)| at <= address).map(|(_, label)| label.as_str())
+    }
+}
+
+pub fn parse_address(value: &str) -> Option<u16> {
+    let hex = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).unwrap_or(value);
+    u16::from_str_radix(hex, 16).ok().filter(|&address| address < 0x1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{SourceLocation, SymbolMap};
+
+    #[test]
+    fn test_parse_symbols() {
+        let symbols = SymbolMap::parse("# From the assembler\n\
+                                        0x200 main\n\
+                                        0x200 src/game.8o:12\n\
+                                        204 src/game.8o:14\n\
+                                        0x202 src/game.8o:13\n\
+                                        0x300 draw\n").unwrap();
+        let location = SourceLocation { file: PathBuf::from("src/game.8o"), line: 13 };
+        assert_eq!(Some(&location), symbols.location(0x202));
+        assert_eq!(None, symbols.location(0x206));
+        assert_eq!(Some((0x202, 13)), symbols.address_of(Path::new("/home/me/rom/src/game.8o"), 13));
+        // Breakpoints on lines without code move down to the next one that has some.
+        assert_eq!(Some((0x204, 14)), symbols.address_of(Path::new("src/game.8o"), 14));
+        assert_eq!(None, symbols.address_of(Path::new("src/game.8o"), 15));
+        assert_eq!(None, symbols.address_of(Path::new("other.8o"), 12));
+        assert_eq!(Some("main"), symbols.label_at(0x200));
+        assert_eq!(Some("main"), symbols.enclosing_label(0x2FE));
+        assert_eq!(Some("draw"), symbols.enclosing_label(0x300));
+        assert_eq!(None, symbols.enclosing_label(0x1FE));
+
+        assert!(SymbolMap::parse("0x200").unwrap_err().contains("line 1"));
+        assert!(SymbolMap::parse("\n0x1000 main").unwrap_err().contains("line 2"));
+    }
+}
@@ -0,0 +1,343 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+use frontend::{is_lit, AudioSink, InputEvent, InputSource, Key, Shades, VideoSink};
+use palette::Palette;
+
+// Terminal frontend, for playing over SSH or anywhere else without a window.
+// The display is drawn with Unicode block or braille characters, input is read
+// straight from the TTY and the buzzer rings the terminal bell.
+//
+// Terminals only tell us when a key is typed, not when it's released, so keys
+// are held down for a short while after the last time we saw them. The first
+// press is held for longer to bridge the gap before key repeat kicks in. We
+// only see the characters that were typed, so keys are always matched by
+// their labels rather than their positions.
+
+const FIRST_PRESS_HOLD_FRAMES: u32 = 30;
+const REPEAT_HOLD_FRAMES: u32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerminalStyle {
+    // Each character covers two pixels stacked vertically, in colour.
+    HalfBlock,
+    // Each character covers 2x4 pixels, in the palette's foreground and
+    // background colours only.
+    Braille,
+}
+
+pub struct TerminalInput {
+    tty: File,
+    saved_settings: String,
+    held_keys: Vec<(Key, u32)>,
+    pending: Vec<u8>,
+}
+
+pub struct TerminalVideo {
+    style: TerminalStyle,
+    palette: Palette,
+    // What's currently on the terminal, one entry per character cell, so that
+    // we only redraw the cells that change.
+    cells: Vec<Option<u32>>,
+}
+
+pub struct TerminalAudio {
+    was_playing: bool,
+}
+
+pub fn init(style: TerminalStyle) -> io::Result<(TerminalInput, TerminalVideo, TerminalAudio)> {
+    let tty = File::open("/dev/tty")?;
+    let saved_settings = run_stty(&tty, &["-g"])?;
+    // No line buffering, no echo, and let us handle Ctrl-C ourselves so that we
+    // always get a chance to restore the terminal. Reads return straight away
+    // even if there's nothing to read.
+    run_stty(&tty, &["-icanon", "-echo", "-isig", "min", "0", "time", "0"])?;
+
+    // Switch to the alternate screen and hide the cursor.
+    print!("\x1B[?1049h\x1B[?25l\x1B[2J");
+    io::stdout().flush()?;
+
+    let (columns, rows) = cell_grid_size(style);
+    Ok((TerminalInput {
+            tty,
+            saved_settings: saved_settings.trim().to_string(),
+            held_keys: Vec::new(),
+            pending: Vec::new(),
+        },
+        TerminalVideo {
+            style,
+            palette: Palette::default(),
+            cells: vec![None; columns * rows],
+        },
+        TerminalAudio { was_playing: false }))
+}
+
+fn run_stty(tty: &File, args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::from(tty.try_clone()?))
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        let _ = run_stty(&self.tty, &[&self.saved_settings]);
+    }
+}
+
+impl InputSource for TerminalInput {
+    fn poll_events(&mut self, events: &mut Vec<InputEvent>) {
+        let mut buffer = [0u8; 64];
+        while let Ok(count) = self.tty.read(&mut buffer) {
+            if count == 0 {
+                break;
+            }
+            self.pending.extend_from_slice(&buffer[..count]);
+        }
+
+        let mut typed = Vec::new();
+        let mut position = 0;
+        while position < self.pending.len() {
+            let (key, length) = parse_key(&self.pending[position..]);
+            match key {
+                Some(Ok(key)) => typed.push(key),
+                Some(Err(())) => events.push(InputEvent::Quit),
+                None => {}
+            }
+            position += length;
+        }
+        self.pending.clear();
+
+        for key in typed {
+            if let Some(held) = self.held_keys.iter_mut().find(|held| held.0 == key) {
+                held.1 = REPEAT_HOLD_FRAMES;
+                events.push(InputEvent::KeyDown { key, repeat: true });
+                continue;
+            }
+            self.held_keys.push((key, FIRST_PRESS_HOLD_FRAMES));
+            events.push(InputEvent::KeyDown { key, repeat: false });
+        }
+
+        for held in self.held_keys.iter_mut() {
+            held.1 -= 1;
+            if held.1 == 0 {
+                events.push(InputEvent::KeyUp { key: held.0 });
+            }
+        }
+        self.held_keys.retain(|held| held.1 > 0);
+    }
+}
+
+// Decodes one key from the start of the input, returning it along with how
+// many bytes it took up. Ctrl-C comes back as Err so that it can quit.
+fn parse_key(bytes: &[u8]) -> (Option<Result<Key, ()>>, usize) {
+    match bytes[0] {
+        0x03 => (Some(Err(())), 1),
+        0x1B => {
+            // Only "ESC [" and "ESC O" start a sequence. Anything else is the
+            // Escape key itself, with the next key typed straight after it.
+            if bytes.len() == 1 || (bytes[1] != b'[' && bytes[1] != b'O') {
+                return (Some(Ok(Key::Escape)), 1);
+            }
+            // Find the end of the escape sequence: it's the first byte that
+            // isn't a digit or separator after the introducer.
+            let end = bytes[2..].iter()
+                .position(|&b| !(b.is_ascii_digit() || b == b';'))
+                .map_or(bytes.len(), |i| i + 3)
+                .min(bytes.len());
+            let key = match &bytes[1..end] {
+                b"OP" => Some(Key::Function(1)),
+                b"OQ" => Some(Key::Function(2)),
+                b"OR" => Some(Key::Function(3)),
+                b"OS" => Some(Key::Function(4)),
+                b"[15~" => Some(Key::Function(5)),
+                b"[17~" => Some(Key::Function(6)),
+                b"[18~" => Some(Key::Function(7)),
+                b"[19~" => Some(Key::Function(8)),
+                b"[20~" => Some(Key::Function(9)),
+                b"[21~" => Some(Key::Function(10)),
+                b"[23~" => Some(Key::Function(11)),
+                b"[24~" => Some(Key::Function(12)),
+                b"[5~" => Some(Key::PageUp),
+                b"[6~" => Some(Key::PageDown),
+                b"[H" | b"OH" | b"[1~" => Some(Key::Home),
+                b"[F" | b"OF" | b"[4~" => Some(Key::End),
+                b"[A" => Some(Key::Up),
+                b"[B" => Some(Key::Down),
+                b"[C" => Some(Key::Right),
+                b"[D" => Some(Key::Left),
+                _ => None,
+            };
+            (key.map(Ok), end)
+        }
+        b'\r' | b'\n' => (Some(Ok(Key::Enter)), 1),
+        b'\t' => (Some(Ok(Key::Tab)), 1),
+        0x7F => (Some(Ok(Key::Backspace)), 1),
+        byte if byte.is_ascii_graphic() || byte == b' ' => {
+            (Some(Ok(Key::Char(byte.to_ascii_lowercase() as char))), 1)
+        }
+        _ => (None, 1),
+    }
+}
+
+fn cell_grid_size(style: TerminalStyle) -> (usize, usize) {
+    match style {
+        TerminalStyle::HalfBlock => (64, 16),
+        TerminalStyle::Braille => (32, 8),
+    }
+}
+
+// Packs the pixels under a character cell into a number, so that cells can be
+// compared cheaply between frames. Half blocks keep both shades; braille dots
+// can only be on or off.
+fn cell_pixels(shades: &Shades, style: TerminalStyle, column: usize, row: usize) -> u32 {
+    match style {
+        TerminalStyle::HalfBlock => {
+            let top = shades[row * 2][column] as u32;
+            let bottom = shades[row * 2 + 1][column] as u32;
+            top | (bottom << 8)
+        }
+        TerminalStyle::Braille => {
+            // Braille dot bits, by (x, y) within the 2x4 cell.
+            const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+            let mut bits = 0;
+            for (dx, dots) in DOTS.iter().enumerate() {
+                for (dy, &dot) in dots.iter().enumerate() {
+                    if is_lit(shades[row * 4 + dy][column * 2 + dx]) {
+                        bits |= dot;
+                    }
+                }
+            }
+            bits
+        }
+    }
+}
+
+fn write_color(out: &mut String, layer: u8, color: [u8; 3]) {
+    out.push_str(&format!("\x1B[{};2;{};{};{}m", layer, color[0], color[1], color[2]));
+}
+
+impl VideoSink for TerminalVideo {
+    // Cells are compared against what's already on the terminal, which also
+    // catches the very first frame, so the change flag isn't needed here.
+    fn present(&mut self, shades: &Shades, _has_changed: bool) {
+        let (columns, rows) = cell_grid_size(self.style);
+        let mut out = String::new();
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let pixels = cell_pixels(shades, self.style, column, row);
+                let cell = &mut self.cells[row * columns + column];
+                if *cell == Some(pixels) {
+                    continue;
+                }
+                *cell = Some(pixels);
+
+                // Terminal rows and columns count from 1.
+                out.push_str(&format!("\x1B[{};{}H", row + 1, column + 1));
+                match self.style {
+                    TerminalStyle::HalfBlock => {
+                        write_color(&mut out, 38, self.palette.shade_color(pixels as u8));
+                        write_color(&mut out, 48, self.palette.shade_color((pixels >> 8) as u8));
+                        out.push('\u{2580}');
+                    }
+                    TerminalStyle::Braille => {
+                        write_color(&mut out, 38, self.palette.foreground());
+                        write_color(&mut out, 48, self.palette.background());
+                        out.push(::std::char::from_u32(0x2800 + pixels).unwrap());
+                    }
+                }
+            }
+        }
+
+        if !out.is_empty() {
+            out.push_str("\x1B[0m");
+            print!("{}", out);
+            let _ = io::stdout().flush();
+        }
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+        for cell in self.cells.iter_mut() {
+            *cell = None;
+        }
+    }
+
+    // Most terminals show this in their title bar or tab.
+    fn set_title(&mut self, title: &str) {
+        print!("\x1B]0;{}\x07", title);
+        let _ = io::stdout().flush();
+    }
+}
+
+impl Drop for TerminalVideo {
+    fn drop(&mut self) {
+        // Show the cursor again and go back to the normal screen.
+        print!("\x1B[0m\x1B[?25h\x1B[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
+
+impl AudioSink for TerminalAudio {
+    // The samples only tell us whether the buzzer is on, so the rate doesn't
+    // matter much.
+    fn sample_rate(&self) -> u32 {
+        44100
+    }
+
+    fn queue_samples(&mut self, samples: &[i16]) {
+        let is_playing = samples.iter().any(|&sample| sample != 0);
+        if is_playing && !self.was_playing {
+            print!("\x07");
+            let _ = io::stdout().flush();
+        }
+        self.was_playing = is_playing;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frontend::Key;
+    use super::{cell_pixels, parse_key, TerminalStyle};
+
+    #[test]
+    fn test_parse_plain_and_escape_keys() {
+        assert_eq!((Some(Ok(Key::Char('q'))), 1), parse_key(b"Qx"));
+        assert_eq!((Some(Ok(Key::Escape)), 1), parse_key(b"\x1B"));
+        assert_eq!((Some(Ok(Key::Function(5))), 5), parse_key(b"\x1B[15~a"));
+        assert_eq!((Some(Ok(Key::Function(1))), 3), parse_key(b"\x1BOP"));
+        assert_eq!((Some(Ok(Key::PageUp)), 4), parse_key(b"\x1B[5~"));
+        assert_eq!((Some(Ok(Key::Home)), 3), parse_key(b"\x1BOH"));
+        assert_eq!((Some(Ok(Key::End)), 4), parse_key(b"\x1B[4~"));
+        assert_eq!((Some(Ok(Key::Left)), 3), parse_key(b"\x1B[D"));
+        assert_eq!((Some(Ok(Key::Char(' '))), 1), parse_key(b" "));
+        assert_eq!((Some(Ok(Key::Enter)), 1), parse_key(b"\r"));
+        assert_eq!((Some(Err(())), 1), parse_key(b"\x03"));
+    }
+
+    #[test]
+    fn test_parse_escape_followed_by_another_key() {
+        // Only the Escape is taken, so the key after it is read next.
+        assert_eq!((Some(Ok(Key::Escape)), 1), parse_key(b"\x1Bq"));
+        assert_eq!((Some(Ok(Key::Escape)), 1), parse_key(b"\x1B\x1B"));
+    }
+
+    #[test]
+    fn test_cell_pixels() {
+        let mut shades = [[0; 64]; 32];
+        shades[1][0] = 255;
+        assert_eq!(0xFF00, cell_pixels(&shades, TerminalStyle::HalfBlock, 0, 0));
+        // Faded pixels still show in half blocks, but not as braille dots.
+        shades[0][0] = 0x40;
+        assert_eq!(0xFF40, cell_pixels(&shades, TerminalStyle::HalfBlock, 0, 0));
+        shades[3][1] = 255;
+        assert_eq!(0x02 | 0x80, cell_pixels(&shades, TerminalStyle::Braille, 0, 0));
+    }
+}